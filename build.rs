@@ -8,6 +8,10 @@ use clap_complete::{generate, Generator, Shell};
 #[path = "src/app.rs"]
 mod app;
 
+#[allow(dead_code)]
+#[path = "src/warning.rs"]
+mod warning;
+
 fn main() {
     let env_dir = std::env::var_os("OUT_DIR");
     let outdir = match env_dir {
@@ -1,6 +1,8 @@
 use clap::{Arg, ArgAction};
 use clap::Command;
+use crate::warning::Warning;
 use std::ffi::{OsStr, OsString};
+use std::time::Duration;
 
 /// From file subcommand name.
 pub const FROM_FILE_SUBCOMMAND: &str = "from-file";
@@ -8,6 +10,36 @@ pub const FROM_FILE_SUBCOMMAND: &str = "from-file";
 /// To ASCII subcommand name.
 pub const TO_ASCII_SUBCOMMAND: &str = "to-ascii";
 
+/// Rules subcommand name.
+pub const RULES_SUBCOMMAND: &str = "rules";
+
+/// Passes subcommand name.
+pub const PASSES_SUBCOMMAND: &str = "passes";
+
+/// Serve subcommand name.
+pub const SERVE_SUBCOMMAND: &str = "serve";
+
+/// Backups subcommand name.
+pub const BACKUPS_SUBCOMMAND: &str = "backups";
+
+/// Backups prune subcommand name.
+pub const BACKUPS_PRUNE_SUBCOMMAND: &str = "prune";
+
+/// Plan subcommand name.
+pub const PLAN_SUBCOMMAND: &str = "plan";
+
+/// Plan diff subcommand name.
+pub const PLAN_DIFF_SUBCOMMAND: &str = "diff";
+
+/// Dump subcommand name.
+pub const DUMP_SUBCOMMAND: &str = "dump";
+
+/// Dump spec subcommand name.
+pub const DUMP_SPEC_SUBCOMMAND: &str = "spec";
+
+/// Validate template subcommand name.
+pub const VALIDATE_TEMPLATE_SUBCOMMAND: &str = "validate-template";
+
 /// Create application using clap. It sets all options and command-line help.
 pub fn create_app<'a>() -> Command {
     // These commons args are shared by all commands.
@@ -24,21 +56,63 @@ pub fn create_app<'a>() -> Command {
             .action(ArgAction::SetTrue)
             .help("Make actual changes to files")
             .conflicts_with("dry-run"),
+        Arg::new("simulate")
+            .long("simulate")
+            .num_args(0..=1)
+            .require_equals(true)
+            .value_name("FORMAT")
+            .value_parser(["tree", "json"])
+            .default_missing_value("tree")
+            .help("Apply the changes to an in-memory copy and print the resulting tree (tree or json)")
+            .conflicts_with_all(["dry-run", "force"]),
         Arg::new("backup")
             .long("backup")
             .short('b')
             .action(ArgAction::SetTrue)
             .help("Generate file backups before renaming"),
+        Arg::new("backup-keep")
+            .long("backup-keep")
+            .requires("backup")
+            .num_args(1)
+            .value_name("N")
+            .value_parser(clap::builder::RangedI64ValueParser::<usize>::new().range(1..))
+            .help("Keep only the newest N backups of each file, removing older ones"),
         Arg::new("silent")
             .long("silent")
             .short('s')
             .action(ArgAction::SetTrue)
-            .help("Do not print any information"),
+            .help("Do not print any information")
+            .conflicts_with("quiet-success"),
+        Arg::new("quiet-success")
+            .long("quiet-success")
+            .action(ArgAction::SetTrue)
+            .help("Print only warnings and errors, nothing for successful operations")
+            .conflicts_with("silent"),
+        Arg::new("verbose")
+            .long("verbose")
+            .short('v')
+            .action(ArgAction::Count)
+            .help("Print more information, repeat to print debug information (-vv)")
+            .conflicts_with("quiet-success"),
         Arg::new("color")
             .long("color")
             .value_parser(["always", "auto", "never"])
             .default_value("auto")
             .help("Set color output mode"),
+        Arg::new("quote")
+            .long("quote")
+            .value_name("STYLE")
+            .value_parser(["escape", "shell"])
+            .default_value("escape")
+            .help("Quote printed paths only when ambiguous, or quote them for POSIX shells to paste them"),
+        Arg::new("suppress-warn")
+            .long("suppress-warn")
+            .num_args(1)
+            .value_name("CATEGORIES")
+            .value_delimiter(',')
+            .action(ArgAction::Append)
+            .value_parser(Warning::ALL.map(|warning| warning.name()))
+            .help("Do not print warnings of these comma separated categories, only count them in the summary"),
         Arg::new("dump")
             .long("dump")
             .action(ArgAction::SetTrue)
@@ -49,6 +123,93 @@ pub fn create_app<'a>() -> Command {
             .action(ArgAction::SetTrue)
             .help("Do not dump operations into a file")
             .conflicts_with("dump"),
+        Arg::new("abs")
+            .long("abs")
+            .action(ArgAction::SetTrue)
+            .help("Store canonical absolute paths in dump files")
+            .conflicts_with("no-dump"),
+        Arg::new("report")
+            .long("report")
+            .num_args(1)
+            .value_name("FORMAT:PATH")
+            .value_parser(clap::builder::StringValueParser::new())
+            .help("Write a report of the planned operations (e.g. html:report.html)"),
+        Arg::new("xattr-tag")
+            .long("xattr-tag")
+            .action(ArgAction::SetTrue)
+            .help("Record the original path, run ID and date in an extended attribute of renamed files"),
+        Arg::new("ignore-errors-matching")
+            .long("ignore-errors-matching")
+            .num_args(1)
+            .value_name("GLOBS")
+            .value_delimiter(',')
+            .action(ArgAction::Append)
+            .value_parser(clap::builder::StringValueParser::new())
+            .help("Print errors renaming paths matching these comma separated globs as warnings and go on"),
+        Arg::new("timeout")
+            .long("timeout")
+            .num_args(1)
+            .value_name("DURATION")
+            .value_parser(parse_duration)
+            .help("Stop planning or renaming when this time budget is exhausted (e.g. 90s, 10m, 1h)"),
+        Arg::new("wait-lock")
+            .long("wait-lock")
+            .action(ArgAction::SetTrue)
+            .help("Wait for other runs renaming in the same directories instead of failing"),
+        Arg::new("progress")
+            .long("progress")
+            .num_args(1)
+            .value_name("MODE")
+            .value_parser(["bar", "plain"])
+            .help("Show renaming progress in standard error, as a bar or as plain lines for logs")
+            .conflicts_with("silent"),
+        Arg::new("slowest")
+            .long("slowest")
+            .num_args(1)
+            .value_name("N")
+            .value_parser(clap::builder::RangedI64ValueParser::<usize>::new().range(1..))
+            .help("Report the N slowest operations and how long they took after renaming"),
+        Arg::new("paranoid")
+            .long("paranoid")
+            .action(ArgAction::SetTrue)
+            .help("Enable --confirm, --contain and a limit of 1000 operations (default for root)")
+            .conflicts_with("no-paranoid"),
+        Arg::new("no-paranoid")
+            .long("no-paranoid")
+            .action(ArgAction::SetTrue)
+            .help("Do not enable the paranoid safety checks when running as root")
+            .conflicts_with("paranoid"),
+        Arg::new("confirm")
+            .long("confirm")
+            .action(ArgAction::SetTrue)
+            .help("Print the planned operations and ask for confirmation before renaming"),
+        Arg::new("contain")
+            .long("contain")
+            .action(ArgAction::SetTrue)
+            .help("Refuse to move paths outside the directory of their source"),
+        Arg::new("max-ops")
+            .long("max-ops")
+            .num_args(1)
+            .value_name("N")
+            .value_parser(clap::builder::RangedI64ValueParser::<usize>::new())
+            .help("Refuse to run more than N operations, no limit if set to 0"),
+        Arg::new("plan-hash")
+            .long("plan-hash")
+            .num_args(1)
+            .value_name("HASH")
+            .help("Refuse to run a plan whose hash differs from this approved one"),
+        Arg::new("config")
+            .long("config")
+            .num_args(1)
+            .value_name("FILE")
+            .value_parser(clap::value_parser!(std::path::PathBuf))
+            .help("Read settings from this file instead of the default configuration file"),
+        Arg::new("map")
+            .long("map")
+            .num_args(1)
+            .value_name("FILE")
+            .value_parser(clap::value_parser!(std::path::PathBuf))
+            .help("Read a lookup table from a CSV file with 'key,value' lines, used by ${1:map} in replacements"),
     ];
 
     // Path related arguments.
@@ -57,7 +218,13 @@ pub fn create_app<'a>() -> Command {
             .help("Target paths")
             .num_args(1..)
             .value_parser(clap::builder::StringValueParser::new())
-            .required(true).index(3),
+            .required_unless_present("paths-from"),
+        Arg::new("paths-from")
+            .long("paths-from")
+            .num_args(1)
+            .value_name("FILE")
+            .value_parser(clap::value_parser!(std::path::PathBuf))
+            .help("Read target paths from a file, one per line, or from standard input with '-'"),
         Arg::new("include-dirs")
             .long("include-dirs")
             .short('D')
@@ -69,13 +236,18 @@ pub fn create_app<'a>() -> Command {
             .short('r')
             .action(ArgAction::SetTrue)
             .help("Recursive mode"),
+        Arg::new("dir-as-contents")
+            .long("dir-as-contents")
+            .action(ArgAction::SetTrue)
+            .help("Rename the contents of directory arguments, except hidden ones, instead of the directories")
+            .conflicts_with("recursive"),
         Arg::new("max-depth")
             .requires("recursive")
             .long("max-depth")
             .short('d')
             .num_args(1)
             .value_name("LEVEL")
-            .value_parser(clap::builder::RangedI64ValueParser::<i64>::new())
+            .value_parser(clap::builder::RangedI64ValueParser::<usize>::new())
             .help("Set max depth in recursive mode"),
         Arg::new("hidden")
             .requires("recursive")
@@ -83,36 +255,128 @@ pub fn create_app<'a>() -> Command {
             .long("hidden")
             .short('x')
             .help("Include hidden files and directories"),
+        Arg::new("hidden-attr")
+            .requires("recursive")
+            .action(ArgAction::SetTrue)
+            .long("hidden-attr")
+            .help("Treat paths with the hidden attribute as hidden too (Windows)"),
+        Arg::new("skip-in-use")
+            .long("skip-in-use")
+            .action(ArgAction::SetTrue)
+            .help("Skip files currently opened by other processes (Linux and Windows)"),
+        Arg::new("collide-ignore-ext")
+            .long("collide-ignore-ext")
+            .action(ArgAction::SetTrue)
+            .help("Refuse targets sharing their name without extension with other paths in the same directory"),
+        Arg::new("older")
+            .long("older")
+            .num_args(1)
+            .value_name("FILE")
+            .value_parser(clap::value_parser!(std::path::PathBuf))
+            .help("Only rename paths older than this reference file"),
+        Arg::new("newer")
+            .long("newer")
+            .num_args(1)
+            .value_name("FILE")
+            .value_parser(clap::value_parser!(std::path::PathBuf))
+            .help("Only rename paths newer than this reference file"),
+        Arg::new("time")
+            .long("time")
+            .value_parser(["mtime", "exif"])
+            .default_value("mtime")
+            .help("Timestamp compared by --older and --newer, paths without it are skipped"),
+    ];
+
+    // Replacement related arguments, shared by the commands renaming with expressions and rules.
+    let replace_args = [
+        Arg::new("case-fold")
+            .long("case-fold")
+            .action(ArgAction::SetTrue)
+            .help("Match case insensitively with full Unicode case folding (e.g. ß matches ss)"),
+        Arg::new("move-to")
+            .long("move-to")
+            .num_args(1)
+            .value_name("DIR")
+            .value_parser(clap::builder::StringValueParser::new())
+            .help("Move renamed paths into this directory, relative to their parent, creating it if needed"),
+        Arg::new("dir-mode")
+            .long("dir-mode")
+            .num_args(1)
+            .value_name("MODE")
+            .value_parser(parse_mode)
+            .requires("move-to")
+            .help("Create directories with these octal permissions regardless of the umask (e.g. 2775)"),
+        Arg::new("inherit-perms")
+            .long("inherit-perms")
+            .action(ArgAction::SetTrue)
+            .requires("move-to")
+            .conflicts_with("dir-mode")
+            .help("Create directories with the permissions and group of their parent, and its owner if permitted"),
+        Arg::new("sample")
+            .long("sample")
+            .num_args(1)
+            .value_name("N")
+            .value_parser(clap::builder::RangedI64ValueParser::<usize>::new().range(1..))
+            .help("Show or apply only a random sample of about N renames with the renames they depend on, applied samples are partial runs"),
+        Arg::new("sample-seed")
+            .long("sample-seed")
+            .num_args(1)
+            .value_name("SEED")
+            .requires("sample")
+            .value_parser(clap::value_parser!(u64))
+            .help("Seed of the random sample, to select the same renames again"),
+        Arg::new("io-concurrency")
+            .long("io-concurrency")
+            .num_args(1)
+            .value_name("N")
+            .default_value("1")
+            .value_parser(clap::builder::RangedI64ValueParser::<usize>::new().range(1..))
+            .help("Read file metadata and EXIF dates with up to N parallel readers before planning"),
+        Arg::new("replace-limit")
+            .long("replace-limit")
+            .short('l')
+            .num_args(1)
+            .value_name("LIMIT")
+            .default_value("1")
+            .value_parser(clap::builder::RangedI64ValueParser::<usize>::new())
+            .help("Limit of replacements, all matches if set to 0"),
     ];
 
     Command::new("rnr")
         .version(env!("CARGO_PKG_VERSION"))
         .author(env!("CARGO_PKG_AUTHORS"))
         .about(env!("CARGO_PKG_DESCRIPTION"))
+        .subcommand_negates_reqs(true)
         .arg(
             Arg::new("EXPRESSION")
                 .help("Expression to match (can be a regex)")
                 .required(true)
-                .value_parser(clap::builder::StringValueParser::new())
-                .index(1),
+                .value_parser(clap::builder::StringValueParser::new()),
         )
         .arg(
             Arg::new("REPLACEMENT")
                 .help("Expression replacement (use single quotes for capture groups)")
                 .required(true)
+                .value_parser(clap::builder::StringValueParser::new()),
+        )
+        .arg(
+            Arg::new("capture-range")
+                .long("capture-range")
+                .num_args(1)
+                .value_name("GROUP:MIN-MAX")
+                .action(ArgAction::Append)
                 .value_parser(clap::builder::StringValueParser::new())
-                .index(2),
+                .help("Only rename paths whose capture group is a number in this range (e.g. 1:100-299)"),
         )
         .arg(
-            Arg::new("replace-limit")
-                .long("replace-limit")
-                .short('l')
+            Arg::new("group-by")
+                .long("group-by")
                 .num_args(1)
-                .value_name("LIMIT")
-                .default_value("1")
-                .value_parser(clap::builder::RangedI64ValueParser::<usize>::new())
-                .help("Limit of replacements, all matches if set to 0"),
+                .value_name("KEY")
+                .value_parser(clap::builder::StringValueParser::new())
+                .help("Number paths within groups sharing this key (e.g. '$1') with {group} and {seq}"),
         )
+        .args(&replace_args)
         .args(&common_args)
         .args(&path_args)
         .subcommand(
@@ -133,6 +397,37 @@ pub fn create_app<'a>() -> Command {
                         .action(ArgAction::SetTrue)
                         .help("Undo the operations from the dump file"),
                 )
+                .arg(
+                    Arg::new("exclude-newer-than-dump")
+                        .long("exclude-newer-than-dump")
+                        .requires("undo")
+                        .action(ArgAction::SetTrue)
+                        .help("Do not revert paths modified after the dump file was created"),
+                )
+                .arg(
+                    Arg::new("tags")
+                        .long("tags")
+                        .num_args(1)
+                        .value_name("TAGS")
+                        .value_parser(clap::builder::StringValueParser::new())
+                        .help("Only apply operations with these comma separated tags (keep, later, never, untagged)"),
+                )
+                .arg(
+                    Arg::new("format")
+                        .long("format")
+                        .num_args(1)
+                        .value_name("FORMAT")
+                        .value_parser(["dump", "mmv", "rename-log"])
+                        .default_value("dump")
+                        .help("Read a dump file, or the verbose output of mmv or rename to undo or audit it"),
+                )
+                .arg(
+                    Arg::new("review")
+                        .long("review")
+                        .action(ArgAction::SetTrue)
+                        .help("Tag operations interactively and store the tags in the dump file without renaming")
+                        .conflicts_with_all(["force", "undo", "tags"]),
+                )
                 .about("Read operations from a dump file"),
         )
         .subcommand(
@@ -141,6 +436,211 @@ pub fn create_app<'a>() -> Command {
                 .args(&path_args)
                 .about("Replace file name UTF-8 chars with ASCII chars representation."),
         )
+        .subcommand(
+            Command::new(RULES_SUBCOMMAND)
+                .args(&common_args)
+                .args(&replace_args)
+                .arg(
+                    Arg::new("rules")
+                        .required(true)
+                        .value_name("FILE")
+                        .value_parser(clap::value_parser!(std::path::PathBuf))
+                        .help("Rules file, or standard input with '-'"),
+                )
+                .args(&path_args)
+                .about("Rename paths with the rules of a file, chained for each name"),
+        )
+        .subcommand(
+            Command::new(PASSES_SUBCOMMAND)
+                .args(&common_args)
+                .args(replace_args.iter().filter(|arg| {
+                    let passes_args = [
+                        "case-fold",
+                        "sample",
+                        "sample-seed",
+                        "io-concurrency",
+                        "replace-limit",
+                    ];
+                    passes_args.contains(&arg.get_id().as_str())
+                }))
+                .arg(
+                    Arg::new("passes")
+                        .required(true)
+                        .value_name("FILES")
+                        .value_delimiter(',')
+                        .value_parser(clap::value_parser!(std::path::PathBuf))
+                        .help("Comma separated rules files, one of them can be standard input with '-'"),
+                )
+                .args(&path_args)
+                .about("Rename paths with a full pass for each rules file, one after another"),
+        )
+        .subcommand(
+            Command::new(SERVE_SUBCOMMAND)
+                .args(
+                    common_args
+                        .iter()
+                        .filter(|arg| ["abs", "backup", "backup-keep", "config", "contain", "dump", "map", "max-ops", "no-dump", "no-paranoid", "paranoid", "slowest"].contains(&arg.get_id().as_str())),
+                )
+                .arg(
+                    Arg::new("stdio")
+                        .long("stdio")
+                        .required(true)
+                        .action(ArgAction::SetTrue)
+                        .help("Speak JSON-RPC over standard input and output"),
+                )
+                .about("Run a JSON-RPC server for editor integrations"),
+        )
+        .subcommand(
+            Command::new(PLAN_SUBCOMMAND)
+                .subcommand_required(true)
+                .subcommand(
+                    Command::new(PLAN_DIFF_SUBCOMMAND)
+                        .args(
+                            common_args
+                                .iter()
+                                .filter(|arg| {
+                                    ["color", "quiet-success", "silent", "verbose"]
+                                        .contains(&arg.get_id().as_str())
+                                }),
+                        )
+                        .arg(
+                            Arg::new("OLD")
+                                .required(true)
+                                .value_name("OLD")
+                                .value_parser(clap::builder::StringValueParser::new())
+                                .help("Dump file with the previous plan"),
+                        )
+                        .arg(
+                            Arg::new("NEW")
+                                .required(true)
+                                .value_name("NEW")
+                                .value_parser(clap::builder::StringValueParser::new())
+                                .help("Dump file with the new plan"),
+                        )
+                        .about("Show operations added, removed or changed between two dump files"),
+                )
+                .about("Inspect plans stored in dump files"),
+        )
+        .subcommand(
+            Command::new(DUMP_SUBCOMMAND)
+                .subcommand_required(true)
+                .subcommand(
+                    Command::new(DUMP_SPEC_SUBCOMMAND)
+                        .about("Print the fields of dump files, to check files generated for from-file"),
+                )
+                .about("Describe the format of dump files"),
+        )
+        .subcommand(
+            Command::new(BACKUPS_SUBCOMMAND)
+                .subcommand_required(true)
+                .subcommand(
+                    Command::new(BACKUPS_PRUNE_SUBCOMMAND)
+                        .args(common_args.iter().filter(|arg| {
+                            ["color", "dry-run", "force", "quiet-success", "silent", "verbose"]
+                                .contains(&arg.get_id().as_str())
+                        }))
+                        .arg(
+                            Arg::new("DIR(S)")
+                                .help("Directories with backups and dump files")
+                                .num_args(1..)
+                                .default_value(".")
+                                .value_parser(clap::builder::StringValueParser::new()),
+                        )
+                        .arg(
+                            Arg::new("recursive")
+                                .long("recursive")
+                                .short('r')
+                                .action(ArgAction::SetTrue)
+                                .help("Search backups in subdirectories too"),
+                        )
+                        .arg(
+                            Arg::new("keep")
+                                .long("keep")
+                                .num_args(1)
+                                .value_name("N")
+                                .value_parser(clap::builder::RangedI64ValueParser::<usize>::new().range(1..))
+                                .help("Keep only the newest N backups of each file and N dump files"),
+                        )
+                        .arg(
+                            Arg::new("max-age")
+                                .long("max-age")
+                                .num_args(1)
+                                .value_name("DURATION")
+                                .value_parser(parse_duration)
+                                .help("Remove backups and dump files older than this (e.g. 30d)"),
+                        )
+                        .group(
+                            clap::ArgGroup::new("threshold")
+                                .args(["keep", "max-age"])
+                                .multiple(true)
+                                .required(true),
+                        )
+                        .about("Remove old backups and dump files"),
+                )
+                .about("Manage backups and dump files"),
+        )
+        .subcommand(
+            Command::new(VALIDATE_TEMPLATE_SUBCOMMAND)
+                .args(common_args.iter().filter(|arg| {
+                    ["color", "config", "map", "quiet-success", "silent", "verbose"]
+                        .contains(&arg.get_id().as_str())
+                }))
+                .arg(
+                    Arg::new("TEMPLATE")
+                        .required(true)
+                        .value_name("TEMPLATE")
+                        .value_parser(clap::builder::StringValueParser::new())
+                        .help("Replacement template to check"),
+                )
+                .arg(
+                    Arg::new("expression")
+                        .long("expression")
+                        .short('e')
+                        .num_args(1)
+                        .value_name("EXPRESSION")
+                        .value_parser(clap::builder::StringValueParser::new())
+                        .help("Check that the captures used by the template exist in this expression"),
+                )
+                .about("Check a replacement template and list the captures and placeholders it uses"),
+        )
+}
+
+/// Parse octal permission bits, like `755` or `2775`.
+pub fn parse_mode(value: &str) -> Result<u32, String> {
+    match u32::from_str_radix(value, 8) {
+        Ok(mode) if mode <= 0o7777 => Ok(mode),
+        _ => Err(format!(
+            "Invalid mode '{}', use octal permissions like 755 or 2775",
+            value
+        )),
+    }
+}
+
+/// Parse a duration with an optional unit suffix: `ms`, `s`, `m`, `h` or `d`. Seconds are assumed
+/// if no unit is provided.
+pub fn parse_duration(value: &str) -> Result<Duration, String> {
+    let value = value.trim();
+    let unit_start = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+    let amount = match value[..unit_start].parse::<u64>() {
+        Ok(amount) => amount,
+        Err(_) => return Err(format!("Invalid duration '{}'", value)),
+    };
+    let seconds = match &value[unit_start..] {
+        "ms" => return Ok(Duration::from_millis(amount)),
+        "" | "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        unit => {
+            return Err(format!(
+                "Invalid duration unit '{}', use ms, s, m, h or d",
+                unit
+            ))
+        }
+    };
+    Ok(Duration::from_secs(amount.saturating_mul(seconds)))
 }
 
 /// Check if the input provided is valid unsigned integer
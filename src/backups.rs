@@ -0,0 +1,300 @@
+use crate::driver::FsDriver;
+use crate::dumpfile;
+use crate::error::*;
+use crate::runid;
+use chrono::NaiveDateTime;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+/// Suffix added to backup file names, followed by a number if the name already exists
+pub const BACKUP_SUFFIX: &str = ".bk";
+
+/// Thresholds to remove backups and dump files. Files are removed if they are beyond the newest
+/// `keep` ones of their group or older than `max_age`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PrunePolicy {
+    pub keep: Option<usize>,
+    pub max_age: Option<Duration>,
+}
+
+/// Get the original file name of a backup name, like `a.txt` for `a.txt.bk` or `a.txt.bk.2`.
+pub fn original_name(name: &str) -> Option<&str> {
+    let name = match name.rsplit_once('.') {
+        Some((base, index)) if !index.is_empty() && index.chars().all(|c| c.is_ascii_digit()) => {
+            base
+        }
+        _ => name,
+    };
+    name.strip_suffix(BACKUP_SUFFIX)
+        .filter(|original| !original.is_empty())
+}
+
+/// Check if a file name is a dump file name, like `rnr-<DATE>-<RUN ID>.json`.
+pub fn is_dump_name(name: &str) -> bool {
+    let stem = match name
+        .strip_prefix("rnr-")
+        .and_then(|name| name.strip_suffix(".json"))
+    {
+        Some(stem) => stem,
+        None => return false,
+    };
+    // Dates are formatted with fixed width fields
+    let date_len = "0000-00-00_000000".len();
+    match (stem.get(..date_len), stem.get(date_len..)) {
+        (Some(date), Some(run_id)) => {
+            NaiveDateTime::parse_from_str(date, dumpfile::FILE_DATE_FORMAT).is_ok()
+                && run_id
+                    .strip_prefix('-')
+                    .is_some_and(|run_id| runid::validate(run_id).is_ok())
+        }
+        _ => false,
+    }
+}
+
+/// Backups found in directories, so each directory is listed once however many files in it are
+/// backed up.
+#[derive(Default)]
+pub struct BackupIndex {
+    dirs: HashMap<PathBuf, Vec<PathBuf>>,
+}
+
+impl BackupIndex {
+    /// Remove the backups of a path beyond the newest `keep` ones, including the backup just
+    /// created. Removed backups are returned.
+    pub fn rotate(
+        &mut self,
+        path: &Path,
+        backup: &Path,
+        keep: usize,
+        driver: &dyn FsDriver,
+    ) -> Result<Vec<PathBuf>> {
+        let file_name = path.file_name().and_then(|name| name.to_str());
+        let dir = match path.parent() {
+            Some(parent) if parent.as_os_str().is_empty() => Path::new("."),
+            Some(parent) => parent,
+            None => return Ok(Vec::new()),
+        };
+        let listed = self
+            .dirs
+            .entry(dir.to_path_buf())
+            .or_insert_with(|| list_backups(dir, driver));
+        let backup = dir.join(backup.file_name().unwrap_or_default());
+        if !listed.contains(&backup) {
+            listed.push(backup);
+        }
+
+        let backups: Vec<PathBuf> = listed
+            .iter()
+            .filter(|backup| {
+                let name = backup.file_name().and_then(|name| name.to_str());
+                name.and_then(original_name)
+                    .is_some_and(|name| Some(name) == file_name)
+            })
+            .cloned()
+            .collect();
+        let policy = PrunePolicy {
+            keep: Some(keep),
+            max_age: None,
+        };
+        let removed = select(backups, &policy, SystemTime::now(), driver);
+        for backup in &removed {
+            remove(backup, driver)?;
+        }
+        listed.retain(|backup| !removed.contains(backup));
+        Ok(removed)
+    }
+}
+
+/// Get the backups and dump files to remove in the given directories according to the policy.
+/// Only files created by rnr are candidates: dump files named like `rnr-<DATE>-<RUN ID>.json` and
+/// the backups of paths renamed by them. Backups are grouped by their original path and all dump
+/// files of a directory are a group.
+pub fn prune_candidates(
+    dirs: &[PathBuf],
+    recursive: bool,
+    policy: &PrunePolicy,
+    driver: &dyn FsDriver,
+) -> Vec<PathBuf> {
+    let mut groups: BTreeMap<PathBuf, Vec<PathBuf>> = BTreeMap::new();
+    // Names of the renamed paths by directory
+    let mut sources: BTreeMap<PathBuf, BTreeSet<String>> = BTreeMap::new();
+    let max_depth = if recursive { None } else { Some(1) };
+    for dir in dirs {
+        for path in driver.walk(dir, max_depth, &|_, _| true) {
+            let name = path.file_name().and_then(|name| name.to_str());
+            if !name.is_some_and(is_dump_name)
+                || driver
+                    .symlink_metadata(&path)
+                    .map_or(true, |entry| entry.is_dir())
+            {
+                continue;
+            }
+            let dump = match dumpfile::read_dump_with_driver(&path, driver) {
+                Ok(dump) => dump,
+                Err(_) => continue,
+            };
+            // Relative paths of dumps start at the directory they were written to
+            let base = path.parent().unwrap_or_else(|| Path::new(""));
+            for operation in dump.operations {
+                let source = base.join(&operation.source);
+                let name = source.file_name().and_then(|name| name.to_str());
+                if let (Some(parent), Some(name)) = (source.parent(), name) {
+                    let names = sources.entry(parent.to_path_buf()).or_default();
+                    names.insert(name.to_string());
+                }
+            }
+            let paths = groups.entry(path.with_file_name("rnr-*.json")).or_default();
+            if !paths.contains(&path) {
+                paths.push(path);
+            }
+        }
+    }
+    for (dir, names) in sources {
+        for backup in list_backups(&dir, driver) {
+            let name = backup.file_name().and_then(|name| name.to_str());
+            if let Some(original) = name.and_then(original_name) {
+                if names.contains(original) {
+                    let paths = groups.entry(dir.join(original)).or_default();
+                    paths.push(backup);
+                }
+            }
+        }
+    }
+
+    let now = SystemTime::now();
+    groups
+        .into_values()
+        .flat_map(|paths| select(paths, policy, now, driver))
+        .collect()
+}
+
+/// List the backup files of a directory, without its subdirectories.
+fn list_backups(dir: &Path, driver: &dyn FsDriver) -> Vec<PathBuf> {
+    driver
+        .walk(dir, Some(1), &|_, _| true)
+        .into_iter()
+        .skip(1)
+        .filter(|path| {
+            let name = path.file_name().and_then(|name| name.to_str());
+            name.and_then(original_name).is_some()
+                && driver
+                    .symlink_metadata(path)
+                    .is_ok_and(|entry| !entry.is_dir())
+        })
+        .collect()
+}
+
+/// Remove a backup or dump file.
+pub fn remove(path: &Path, driver: &dyn FsDriver) -> Result<()> {
+    driver.remove_file(path).map_err(|err| Error {
+        kind: ErrorKind::RemoveFile,
+        value: Some(format!("{}\n{}", path.display(), err)),
+    })
+}
+
+/// Select the files of a group to remove, sorted from newest to oldest.
+fn select(
+    paths: Vec<PathBuf>,
+    policy: &PrunePolicy,
+    now: SystemTime,
+    driver: &dyn FsDriver,
+) -> Vec<PathBuf> {
+    let mut files: Vec<(SystemTime, PathBuf)> = paths
+        .into_iter()
+        .map(|path| {
+            let modified = driver
+                .symlink_metadata(&path)
+                .ok()
+                .and_then(|entry| entry.modified)
+                .unwrap_or(SystemTime::UNIX_EPOCH);
+            (modified, path)
+        })
+        .collect();
+    files.sort_by(|a, b| b.cmp(a));
+
+    files
+        .into_iter()
+        .enumerate()
+        .filter(|(index, (modified, _))| {
+            policy.keep.is_some_and(|keep| *index >= keep)
+                || policy.max_age.is_some_and(|max_age| {
+                    now.duration_since(*modified).is_ok_and(|age| age > max_age)
+                })
+        })
+        .map(|(_, (_, path))| path)
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::driver::MemoryDriver;
+
+    #[test]
+    fn backup_names() {
+        assert_eq!(original_name("a.txt.bk"), Some("a.txt"));
+        assert_eq!(original_name("a.txt.bk.12"), Some("a.txt"));
+        assert_eq!(original_name("a.txt"), None);
+        assert_eq!(original_name(".bk"), None);
+        assert_eq!(original_name("a.bk.x"), None);
+        assert!(is_dump_name("rnr-2021-06-15_103000-abc.json"));
+        assert!(!is_dump_name("rnr.json"));
+        assert!(!is_dump_name("rnr-1.json"));
+        assert!(!is_dump_name("rnr-2021-06-15_103000.json"));
+        assert!(!is_dump_name("rnr-2021-13-15_103000-abc.json"));
+        assert!(!is_dump_name("rnr-2021-06-15_103000-a b.json"));
+    }
+
+    #[test]
+    fn rotate_and_prune() {
+        let driver = MemoryDriver::new();
+        let path = |name: &str| Path::new("/data").join(name);
+        let day = Duration::from_secs(24 * 60 * 60);
+        let now = SystemTime::now();
+        let dump = |source: &str| {
+            format!(
+                r#"{{"date":"2021-06-15 10:30:00","operations":[{{"source":"{}","target":"x"}}]}}"#,
+                source
+            )
+        };
+        let files = [
+            ("a.txt.bk", 3, String::new()),
+            ("a.txt.bk.1", 2, String::new()),
+            ("a.txt.bk.2", 1, String::new()),
+            ("b.txt.bk", 40, String::new()),
+            ("c.txt.bk", 40, String::new()),
+            ("rnr-2021-06-15_103000-a.json", 50, dump("a.txt")),
+            ("rnr-2021-06-16_103000-b.json", 1, dump("/data/b.txt")),
+            ("rnr-notes.json", 60, String::new()),
+            ("notes.txt", 100, String::new()),
+        ];
+        for (name, age, content) in files {
+            driver.create_file(&path(name), content.as_bytes());
+            driver.set_modified(&path(name), now - day * age).unwrap();
+        }
+
+        // Oldest backups of the path are removed
+        let mut index = BackupIndex::default();
+        let removed = index
+            .rotate(&path("a.txt"), &path("a.txt.bk.2"), 2, &driver)
+            .unwrap();
+        assert_eq!(removed, vec![path("a.txt.bk")]);
+
+        // Backups of paths not renamed by a dump file and unknown JSON files are kept
+        let policy = PrunePolicy {
+            keep: Some(1),
+            max_age: Some(day * 30),
+        };
+        let mut candidates = prune_candidates(&[PathBuf::from("/data")], false, &policy, &driver);
+        candidates.sort();
+        assert_eq!(
+            candidates,
+            vec![
+                path("a.txt.bk.1"),
+                path("b.txt.bk"),
+                path("rnr-2021-06-15_103000-a.json")
+            ]
+        );
+    }
+}
@@ -0,0 +1,141 @@
+use regex::{Regex, RegexBuilder};
+
+/// Build a case insensitive regex using full Unicode case folding. Simple folding is done by the
+/// regex engine, like `K` and the Kelvin sign or `Σ`, `σ` and `ς`. Besides, literal `ss` and `ß`
+/// match each other and `i` matches Turkish dotted `İ` and dotless `ı` too.
+pub fn build_regex(pattern: &str) -> Result<Regex, regex::Error> {
+    RegexBuilder::new(&fold_pattern(pattern))
+        .case_insensitive(true)
+        .build()
+}
+
+/// Expand the literals of a pattern whose full case folding is not handled by the regex engine.
+/// Escapes, character classes, group names, flags and repetitions are kept as they are.
+fn fold_pattern(pattern: &str) -> String {
+    let chars: Vec<char> = pattern.chars().collect();
+    let mut folded = String::new();
+    let mut index = 0;
+
+    while index < chars.len() {
+        let rest = &chars[index..];
+        let verbatim = match rest[0] {
+            '\\' => escape_len(rest),
+            '[' => class_len(rest),
+            '(' if rest.get(1) == Some(&'?') => group_prefix_len(rest),
+            '{' => delimited_len(rest, '}'),
+            _ => 0,
+        };
+        if verbatim > 0 {
+            folded.extend(&rest[..verbatim]);
+            index += verbatim;
+            continue;
+        }
+
+        // Repetitions apply to the last char only, so it cannot be expanded along with others
+        let repeated = rest
+            .get(2)
+            .is_some_and(|next| matches!(next, '*' | '+' | '?' | '{'));
+        if is_s(rest[0]) && rest.get(1).is_some_and(|next| is_s(*next)) && !repeated {
+            folded.push_str("(?:ss|ß)");
+            index += 2;
+            continue;
+        }
+        match rest[0] {
+            'ß' | 'ẞ' => folded.push_str("(?:ss|ß)"),
+            'i' | 'I' | 'İ' | 'ı' => folded.push_str("[iİı]"),
+            c => folded.push(c),
+        }
+        index += 1;
+    }
+    folded
+}
+
+fn is_s(c: char) -> bool {
+    matches!(c, 's' | 'S' | 'ſ')
+}
+
+/// Length of an escape sequence, including braced ones like `\p{Greek}` or `\x{1F600}`.
+fn escape_len(chars: &[char]) -> usize {
+    match chars.get(1) {
+        Some('p' | 'P' | 'x' | 'u' | 'U') if chars.get(2) == Some(&'{') => {
+            2 + delimited_len(&chars[2..], '}')
+        }
+        Some(_) => 2,
+        None => 1,
+    }
+}
+
+/// Length of a possibly nested character class. A leading `]` is a literal.
+fn class_len(chars: &[char]) -> usize {
+    let mut index = 1;
+    if chars.get(index) == Some(&'^') {
+        index += 1;
+    }
+    if chars.get(index) == Some(&']') {
+        index += 1;
+    }
+    while index < chars.len() {
+        match chars[index] {
+            '\\' => index += escape_len(&chars[index..]),
+            '[' => index += class_len(&chars[index..]),
+            ']' => return index + 1,
+            _ => index += 1,
+        }
+    }
+    chars.len()
+}
+
+/// Length of a group prefix with flags or a name, like `(?i:`, `(?s)` or `(?P<name>`.
+fn group_prefix_len(chars: &[char]) -> usize {
+    chars
+        .iter()
+        .position(|c| matches!(c, ':' | ')' | '>'))
+        .map(|end| end + 1)
+        .unwrap_or(chars.len())
+}
+
+/// Length up to the closing delimiter included.
+fn delimited_len(chars: &[char], delimiter: char) -> usize {
+    chars
+        .iter()
+        .position(|c| *c == delimiter)
+        .map(|end| end + 1)
+        .unwrap_or(chars.len())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn fold_patterns() {
+        assert_eq!(fold_pattern("straße"), "stra(?:ss|ß)e");
+        assert_eq!(fold_pattern("Strasse"), "Stra(?:ss|ß)e");
+        assert_eq!(fold_pattern("ss+"), "ss+");
+        assert_eq!(
+            fold_pattern(r"\ss(?s)[is]\p{Greek}"),
+            r"\ss(?s)[is]\p{Greek}"
+        );
+        assert_eq!(fold_pattern("(?P<is>ss){2}"), "(?P<is>(?:ss|ß)){2}");
+    }
+
+    #[test]
+    fn full_case_folding() {
+        let regex = build_regex("strasse").unwrap();
+        assert!(regex.is_match("Straße.txt"));
+        assert!(regex.is_match("STRASSE.txt"));
+        assert!(build_regex("STRAẞE").unwrap().is_match("strasse"));
+
+        let regex = build_regex("^istanbul").unwrap();
+        assert!(regex.is_match("İSTANBUL.jpg"));
+        assert!(regex.is_match("ıstanbul.jpg"));
+
+        let regex = build_regex("οδοσ").unwrap();
+        assert!(regex.is_match("ΟΔΟΣ.txt"));
+        assert!(regex.is_match("οδος.txt"));
+
+        // Captures keep the original text
+        let regex = build_regex("(stra(ss)e)").unwrap();
+        assert_eq!(&regex.captures("Straße").unwrap()[1], "Straße");
+    }
+}
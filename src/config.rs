@@ -1,11 +1,36 @@
+use std::any::Any;
 use std::ffi::OsString;
 use std::io::IsTerminal;
 use std::ops::Deref;
-use crate::app::{create_app, FROM_FILE_SUBCOMMAND, TO_ASCII_SUBCOMMAND};
+use std::path::{Path, PathBuf};
+use crate::app::{
+    create_app, BACKUPS_PRUNE_SUBCOMMAND, BACKUPS_SUBCOMMAND, DUMP_SPEC_SUBCOMMAND,
+    DUMP_SUBCOMMAND, FROM_FILE_SUBCOMMAND, PASSES_SUBCOMMAND, PLAN_DIFF_SUBCOMMAND,
+    PLAN_SUBCOMMAND, RULES_SUBCOMMAND, SERVE_SUBCOMMAND, TO_ASCII_SUBCOMMAND,
+    VALIDATE_TEMPLATE_SUBCOMMAND,
+};
+use crate::backups::PrunePolicy;
+use crate::casefold;
 use clap::ArgMatches;
-use crate::output::Printer;
+use crate::driver::{DirMode, FsDriver, LocalDriver};
+use crate::fileutils::{read_path_list, STDIN_PATH};
+use crate::filters::{CaptureRange, TimeFilter, TimeSource};
+use crate::importer;
+use crate::output::{Level, Printer, Quote};
+use crate::progress;
+use crate::report::Report;
+use crate::rules::{self, Rule};
+use crate::runid;
+use crate::safety::{self, Safety};
+use crate::sample::Sample;
+use crate::settings::{self, Settings};
+use crate::simulate::{self, Simulation};
+use crate::solver::Tag;
+use crate::template::Template;
+use crate::warning::Warning;
 use regex::Regex;
 use std::sync::Arc;
+use std::time::Duration;
 use clap::builder::TypedValueParser;
 
 /// This module is defined Config struct to carry application configuration. This struct is created
@@ -14,11 +39,51 @@ use clap::builder::TypedValueParser;
 pub struct Config {
     pub force: bool,
     pub backup: bool,
+    /// Keep only this number of backups of each file
+    pub backup_keep: Option<usize>,
     pub dirs: bool,
     pub dump: bool,
+    pub abs: bool,
+    pub report: Option<Report>,
+    pub time_filter: Option<TimeFilter>,
+    /// Only rename paths whose captures are within these ranges
+    pub capture_ranges: Vec<CaptureRange>,
+    /// Random sample of the renames shown or applied
+    pub sample: Option<Sample>,
+    /// Key rendered for each path to number paths within groups
+    pub group_by: Option<Template>,
+    /// Directory where matched paths are moved, relative to their parent unless absolute
+    pub move_to: Option<PathBuf>,
+    /// Permissions of the directories created by `move_to`
+    pub dir_mode: DirMode,
+    /// Record the origin of renamed files in an extended attribute
+    pub xattr_tag: bool,
+    /// Maximum number of parallel metadata readers, metadata is read on demand if set to 1
+    pub io_concurrency: usize,
+    pub skip_in_use: bool,
+    /// Targets must have unique names without their extensions in their directories
+    pub collide_ignore_ext: bool,
+    /// Wait for other runs renaming in the same directories instead of failing
+    pub wait_lock: bool,
+    /// Globs of paths whose renaming errors are printed as warnings
+    pub ignore_errors: Vec<String>,
+    pub timeout: Option<Duration>,
+    /// Display of renaming progress
+    pub progress: Option<progress::Mode>,
+    /// Number of slowest operations reported after renaming
+    pub slowest: Option<usize>,
+    /// Guards against mistakes, enabled by default for the root user
+    pub safety: Safety,
     pub run_mode: RunMode,
     pub replace_mode: ReplaceMode,
     pub printer: Printer,
+    pub settings: Settings,
+    /// File system where paths are planned and renamed
+    pub driver: Arc<dyn FsDriver>,
+    /// Changes are applied to an in-memory snapshot of the paths and the result is printed
+    pub simulation: Option<Simulation>,
+    /// Unique ID of this run included in dump files, reports and output
+    pub run_id: String,
 }
 
 impl Config {
@@ -33,23 +98,57 @@ impl Config {
 
 pub enum RunMode {
     Simple(Vec<String>),
+    /// Like simple mode, with directories replaced by their direct children
+    Contents(Vec<String>),
     Recursive {
         paths: Vec<String>,
         max_depth: Option<usize>,
         hidden: bool,
+        /// Paths with the hidden attribute are hidden, not only dot-prefixed names (Windows)
+        hidden_attr: bool,
     },
     FromFile {
         path: String,
         undo: bool,
+        /// Only apply operations with these tags, `None` stands for untagged operations
+        tags: Option<Vec<Option<Tag>>>,
+        /// Skip paths modified after the dump was created when undoing
+        exclude_newer: bool,
+        /// Format of the file, a dump or the log of another tool
+        format: importer::Format,
+    },
+    /// Tag operations of a dump file interactively
+    Review(String),
+    Serve,
+    /// Compare the operations of two dump files
+    PlanDiff {
+        old: String,
+        new: String,
+    },
+    /// Print the fields of dump files
+    DumpSpec,
+    /// Check a replacement template, optionally against an expression
+    ValidateTemplate {
+        template: String,
+        expression: Option<String>,
+    },
+    /// Remove old backups and dump files in some directories
+    PruneBackups {
+        paths: Vec<String>,
+        recursive: bool,
+        policy: PrunePolicy,
     },
 }
 
 pub enum ReplaceMode {
     RegExp {
         expression: Regex,
-        replacement: String,
+        replacement: Template,
         limit: usize,
     },
+    Rules(Vec<Rule>),
+    /// Full passes run one after another over the names produced by the previous ones
+    Passes(Vec<ReplaceMode>),
     ToASCII,
 }
 
@@ -59,6 +158,13 @@ pub enum AppCommand {
     Root,
     FromFile,
     ToASCII,
+    Serve,
+    Plan,
+    Dump,
+    Backups,
+    ValidateTemplate,
+    Rules,
+    Passes,
 }
 
 impl AppCommand {
@@ -67,6 +173,13 @@ impl AppCommand {
             "" => Ok(AppCommand::Root),
             FROM_FILE_SUBCOMMAND => Ok(AppCommand::FromFile),
             TO_ASCII_SUBCOMMAND => Ok(AppCommand::ToASCII),
+            SERVE_SUBCOMMAND => Ok(AppCommand::Serve),
+            PLAN_SUBCOMMAND => Ok(AppCommand::Plan),
+            DUMP_SUBCOMMAND => Ok(AppCommand::Dump),
+            BACKUPS_SUBCOMMAND => Ok(AppCommand::Backups),
+            VALIDATE_TEMPLATE_SUBCOMMAND => Ok(AppCommand::ValidateTemplate),
+            RULES_SUBCOMMAND => Ok(AppCommand::Rules),
+            PASSES_SUBCOMMAND => Ok(AppCommand::Passes),
             _ => Err(format!("Non-registered subcommand '{}'", name)),
         }
     }
@@ -76,44 +189,286 @@ struct ArgumentParser<'a> {
     matches: &'a ArgMatches,
     printer: &'a Printer,
     command: &'a AppCommand,
+    settings: &'a Settings,
 }
 
 impl ArgumentParser<'_> {
     fn parse_run_mode(&self) -> Result<RunMode, String> {
         if let AppCommand::FromFile = self.command {
+            let path =
+                String::from(get_one::<String>(self.matches, "DUMPFILE").unwrap_or(&String::new()));
+            if get_flag(self.matches, "review") {
+                return Ok(RunMode::Review(path));
+            }
+            let tags = match get_one::<String>(self.matches, "tags") {
+                Some(tags) => Some(
+                    tags.split(',')
+                        .map(|tag| Tag::parse_filter(tag.trim()))
+                        .collect::<Result<Vec<_>, String>>()
+                        .map_err(|err| {
+                            format!(
+                                "{}{}",
+                                self.printer.colors.error.paint("Error: "),
+                                self.printer.colors.error.paint(err)
+                            )
+                        })?,
+                ),
+                None => None,
+            };
             return Ok(RunMode::FromFile {
-                path: String::from(self.matches.get_one::<String>("DUMPFILE").unwrap_or(&String::new())),
-                undo: self.matches.get_flag("undo"),
+                path,
+                undo: get_flag(self.matches, "undo"),
+                tags,
+                exclude_newer: get_flag(self.matches, "exclude-newer-than-dump"),
+                format: importer::Format::from_str(
+                    get_one::<String>(self.matches, "format").map_or("dump", String::as_str),
+                )?,
+            });
+        }
+        if let AppCommand::Serve = self.command {
+            return Ok(RunMode::Serve);
+        }
+        if let AppCommand::Backups = self.command {
+            return Ok(RunMode::PruneBackups {
+                paths: self
+                    .matches
+                    .get_many::<String>("DIR(S)")
+                    .unwrap_or_default()
+                    .cloned()
+                    .collect(),
+                recursive: get_flag(self.matches, "recursive"),
+                policy: PrunePolicy {
+                    keep: get_one::<usize>(self.matches, "keep").copied(),
+                    max_age: get_one::<Duration>(self.matches, "max-age").copied(),
+                },
+            });
+        }
+        if let AppCommand::ValidateTemplate = self.command {
+            return Ok(RunMode::ValidateTemplate {
+                template: get_one::<String>(self.matches, "TEMPLATE")
+                    .cloned()
+                    .unwrap_or_default(),
+                expression: get_one::<String>(self.matches, "expression").cloned(),
+            });
+        }
+        if let AppCommand::Dump = self.command {
+            return Ok(RunMode::DumpSpec);
+        }
+        if let AppCommand::Plan = self.command {
+            let dump_file = |id: &str| {
+                get_one::<String>(self.matches, id)
+                    .cloned()
+                    .unwrap_or_default()
+            };
+            return Ok(RunMode::PlanDiff {
+                old: dump_file("OLD"),
+                new: dump_file("NEW"),
             });
         }
 
         // Detect run mode and set parameters accordingly
-        let input_paths: Vec<String> = self
+        let mut input_paths: Vec<String> = self
             .matches
             .get_many::<String>("PATH(S)")
             .unwrap_or_default()
             .map(String::from)
             .collect();
 
-        if self.matches.get_flag("recursive") {
-            let max_depth = self.matches.get_one::<usize>("max-depth").copied();
+        input_paths.extend(self.read_paths_from()?);
+
+        if get_flag(self.matches, "recursive") {
+            let max_depth = get_one::<usize>(self.matches, "max-depth").copied();
             Ok(RunMode::Recursive {
                 paths: input_paths,
                 max_depth,
-                hidden: self.matches.get_flag("hidden"),
+                hidden: get_flag(self.matches, "hidden"),
+                hidden_attr: get_flag(self.matches, "hidden-attr"),
             })
+        } else if get_flag(self.matches, "dir-as-contents") {
+            Ok(RunMode::Contents(input_paths))
         } else {
             Ok(RunMode::Simple(input_paths))
         }
     }
 
+    /// Check that a single argument reads the standard input, rules files and path lists alike.
+    fn check_stdin(&self) -> Result<(), String> {
+        let stdin = Path::new(STDIN_PATH);
+        let rules = get_one::<PathBuf>(self.matches, "rules");
+        let passes = self
+            .matches
+            .try_get_many::<PathBuf>("passes")
+            .ok()
+            .flatten();
+        let paths_from = get_one::<PathBuf>(self.matches, "paths-from");
+        let stdin_readers = rules
+            .into_iter()
+            .chain(passes.into_iter().flatten())
+            .chain(paths_from)
+            .filter(|path| *path == stdin)
+            .count();
+        if stdin_readers > 1 {
+            return Err(format!(
+                "{}{}",
+                self.printer.colors.error.paint("Error: "),
+                self.printer.colors.error.paint(
+                    "Only one of the rules files and path lists can be read from standard input"
+                )
+            ));
+        }
+        Ok(())
+    }
+
+    /// Read the target paths given with `--paths-from`.
+    fn read_paths_from(&self) -> Result<Vec<String>, String> {
+        match get_one::<PathBuf>(self.matches, "paths-from") {
+            Some(path) => read_path_list(path).map_err(|err| {
+                format!(
+                    "{}{}",
+                    self.printer.colors.error.paint("Error: "),
+                    self.printer.colors.error.paint(err)
+                )
+            }),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    fn parse_time_filter(&self) -> Result<Option<TimeFilter>, String> {
+        let older = get_one::<PathBuf>(self.matches, "older");
+        let newer = get_one::<PathBuf>(self.matches, "newer");
+        if older.is_none() && newer.is_none() {
+            return Ok(None);
+        }
+
+        let source = get_one::<String>(self.matches, "time")
+            .map(|source| TimeSource::from_str(source))
+            .unwrap_or(Ok(TimeSource::Mtime));
+        source
+            .and_then(|source| {
+                TimeFilter::from_references(
+                    source,
+                    older.map(PathBuf::as_path),
+                    newer.map(PathBuf::as_path),
+                )
+            })
+            .map(Some)
+            .map_err(|err| {
+                format!(
+                    "{}{}",
+                    self.printer.colors.error.paint("Error: "),
+                    self.printer.colors.error.paint(err)
+                )
+            })
+    }
+
+    /// Compile an expression, with full Unicode case folding if required.
+    fn compile(&self, pattern: &str) -> Result<Regex, regex::Error> {
+        if get_flag(self.matches, "case-fold") {
+            casefold::build_regex(pattern)
+        } else {
+            Regex::new(pattern)
+        }
+    }
+
+    /// Compile the expressions of rules again with full Unicode case folding if required.
+    fn fold_rules(&self, mut rules: Vec<Rule>) -> Result<Vec<Rule>, String> {
+        if get_flag(self.matches, "case-fold") {
+            for rule in rules.iter_mut() {
+                rule.expression = casefold::build_regex(rule.expression.as_str())
+                    .map_err(|err| err.to_string())?;
+            }
+        }
+        Ok(rules)
+    }
+
+    fn parse_group_by(&self) -> Result<Option<Template>, String> {
+        get_one::<String>(self.matches, "group-by")
+            .map(|key| Template::parse_with_settings(key, self.settings))
+            .transpose()
+            .map_err(|err| {
+                format!(
+                    "{}Bad group key template provided\n\n{}",
+                    self.printer.colors.error.paint("Error: "),
+                    self.printer.colors.error.paint(err)
+                )
+            })
+    }
+
+    fn parse_capture_ranges(
+        &self,
+        replace_mode: &ReplaceMode,
+    ) -> Result<Vec<CaptureRange>, String> {
+        let expression = match replace_mode {
+            ReplaceMode::RegExp { expression, .. } => expression,
+            _ => return Ok(Vec::new()),
+        };
+        self.matches
+            .try_get_many::<String>("capture-range")
+            .ok()
+            .flatten()
+            .unwrap_or_default()
+            .map(|range| {
+                let range = CaptureRange::parse(range)?;
+                range.validate(expression)?;
+                Ok(range)
+            })
+            .collect::<Result<Vec<_>, String>>()
+            .map_err(|err| {
+                format!(
+                    "{}{}",
+                    self.printer.colors.error.paint("Error: "),
+                    self.printer.colors.error.paint(err)
+                )
+            })
+    }
+
     fn parse_replace_mode(&self) -> Result<ReplaceMode, String> {
         if let AppCommand::ToASCII = self.command {
             return Ok(ReplaceMode::ToASCII);
         }
 
+        let limit = *get_one::<usize>(self.matches, "replace-limit").unwrap_or(&0);
+
+        if let Some(paths) = self
+            .matches
+            .try_get_many::<PathBuf>("passes")
+            .ok()
+            .flatten()
+        {
+            return paths
+                .map(|path| {
+                    rules::read_from_file(path, self.settings, limit)
+                        .and_then(|rules| self.fold_rules(rules))
+                        .map(ReplaceMode::Rules)
+                })
+                .collect::<Result<Vec<_>, String>>()
+                .map(ReplaceMode::Passes)
+                .map_err(|err| {
+                    format!(
+                        "{}{}",
+                        self.printer.colors.error.paint("Error: "),
+                        self.printer.colors.error.paint(err)
+                    )
+                });
+        }
+
+        if let Some(path) = get_one::<PathBuf>(self.matches, "rules") {
+            let rules = rules::read_from_file(path, self.settings, limit)
+                .and_then(|rules| self.fold_rules(rules));
+            return match rules {
+                Ok(rules) => Ok(ReplaceMode::Rules(rules)),
+                Err(err) => Err(format!(
+                    "{}{}",
+                    self.printer.colors.error.paint("Error: "),
+                    self.printer.colors.error.paint(err)
+                )),
+            };
+        }
+
         // Get and validate regex expression and replacement from arguments
-        let expression = match Regex::new(self.matches.get_one::<String>("EXPRESSION").unwrap_or(&String::new()).deref()) {
+        let expression = match self
+            .compile(get_one::<String>(self.matches, "EXPRESSION").unwrap_or(&String::new()))
+        {
             Ok(expr) => expr,
             Err(err) => {
                 return Err(format!(
@@ -123,12 +478,21 @@ impl ArgumentParser<'_> {
                 ));
             }
         };
-        let replacement = String::from(self.matches.get_one::<String>("REPLACEMENT").unwrap_or(&String::new()).deref());
-
-        let limit = *self
-            .matches
-            .get_one::<usize>("replace-limit")
-            .unwrap_or(&0);
+        let replacement = match Template::parse_with_settings(
+            get_one::<String>(self.matches, "REPLACEMENT")
+                .unwrap_or(&String::new())
+                .deref(),
+            self.settings,
+        ) {
+            Ok(template) => template,
+            Err(err) => {
+                return Err(format!(
+                    "{}Bad replacement template provided\n\n{}",
+                    self.printer.colors.error.paint("Error: "),
+                    self.printer.colors.error.paint(err)
+                ));
+            }
+        };
 
         Ok(ReplaceMode::RegExp {
             expression,
@@ -149,44 +513,229 @@ fn parse_arguments() -> Result<Config, String> {
             return Err("No command provided".to_string());
         }
     };
+    // Plan, dump and backups commands are nested in their own subcommands
+    let matches = match (&command, matches.subcommand()) {
+        (AppCommand::Plan, Some((PLAN_DIFF_SUBCOMMAND, submatches))) => submatches,
+        (AppCommand::Dump, Some((DUMP_SPEC_SUBCOMMAND, submatches))) => submatches,
+        (AppCommand::Backups, Some((BACKUPS_PRUNE_SUBCOMMAND, submatches))) => submatches,
+        (AppCommand::Plan | AppCommand::Dump | AppCommand::Backups, _) => {
+            return Err("No command provided".to_string())
+        }
+        _ => matches,
+    };
 
     // Set dump defaults: write in force mode and do not in dry-run unless it is explicitly asked
-    let dump = if !matches.get_flag("dry-run") {
-        !matches.get_flag("no-dump")
+    let dump = if !get_flag(matches, "dry-run") {
+        !get_flag(matches, "no-dump")
     } else {
-        matches.get_flag("dump")
+        get_flag(matches, "dump")
     };
 
-    let printer = if matches.get_flag("silent") {
+    // Server output is reserved for the protocol
+    let mut printer = if get_flag(matches, "silent") || command == AppCommand::Serve {
         Printer::silent()
     } else {
-        match matches.get_one::<String>("color").unwrap_or(&"auto".to_string()).deref() {
+        match get_one::<String>(matches, "color")
+            .unwrap_or(&"auto".to_string())
+            .deref()
+        {
             "always" => Printer::color(),
             "never" => Printer::no_color(),
             _ => detect_output_color(), // Ignore non-valid values and use auto.
         }
     };
+    if get_one::<String>(matches, "quote").is_some_and(|quote| quote == "shell") {
+        printer.set_quote(Quote::Shell);
+    }
+    let suppressed = matches
+        .try_get_many::<String>("suppress-warn")
+        .ok()
+        .flatten()
+        .unwrap_or_default()
+        .map(|name| Warning::from_str(name))
+        .collect::<Result<Vec<_>, String>>()?;
+    printer.set_suppressed(suppressed);
+
+    if get_flag(matches, "quiet-success") {
+        printer.set_level(Level::QuietSuccess);
+    }
+    match get_one::<u8>(matches, "verbose").copied().unwrap_or(0) {
+        0 => {}
+        1 => printer.set_level(Level::Verbose),
+        _ => printer.set_level(Level::Debug),
+    }
+
+    let settings = Settings::load(get_one::<PathBuf>(matches, "config").map(PathBuf::as_path))
+        .and_then(|mut settings| {
+            if let Some(path) = get_one::<PathBuf>(matches, "map") {
+                settings.map = Some(Arc::new(settings::read_map(path)?));
+            }
+            Ok(settings)
+        });
+    let settings = match settings {
+        Ok(settings) => settings,
+        Err(err) => {
+            return Err(format!(
+                "{}{}",
+                printer.colors.error.paint("Error: "),
+                printer.colors.error.paint(err)
+            ));
+        }
+    };
+
+    let run_id = match runid::run_id() {
+        Ok(run_id) => run_id,
+        Err(err) => {
+            return Err(format!(
+                "{}{}",
+                printer.colors.error.paint("Error: "),
+                printer.colors.error.paint(err)
+            ));
+        }
+    };
+
+    let report = match get_one::<String>(matches, "report").map(|report| Report::parse(report)) {
+        Some(Ok(report)) => Some(report),
+        Some(Err(err)) => {
+            return Err(format!(
+                "{}{}",
+                printer.colors.error.paint("Error: "),
+                printer.colors.error.paint(err)
+            ));
+        }
+        None => None,
+    };
 
     let argument_parser = ArgumentParser {
         printer: &printer,
         matches,
         command: &command,
+        settings: &settings,
     };
 
+    argument_parser.check_stdin()?;
     let run_mode = argument_parser.parse_run_mode()?;
     let replace_mode = argument_parser.parse_replace_mode()?;
+    let time_filter = argument_parser.parse_time_filter()?;
+    let capture_ranges = argument_parser.parse_capture_ranges(&replace_mode)?;
+    let group_by = argument_parser.parse_group_by()?;
+
+    // Simulations rename paths in memory only, without dumping them
+    let simulation = match get_one::<String>(matches, "simulate") {
+        Some(format) => Some(
+            simulate::Format::from_str(format)
+                .and_then(|format| Simulation::new(format, &run_mode))
+                .map_err(|err| {
+                    format!(
+                        "{}{}",
+                        printer.colors.error.paint("Error: "),
+                        printer.colors.error.paint(err)
+                    )
+                })?,
+        ),
+        None => None,
+    };
+    let driver: Arc<dyn FsDriver> = match &simulation {
+        Some(simulation) => Arc::new(simulate::snapshot(
+            &simulation.roots,
+            &run_mode,
+            &LocalDriver,
+        )),
+        None => Arc::new(LocalDriver),
+    };
+    // JSON output is reserved for the resulting tree
+    if simulation
+        .as_ref()
+        .is_some_and(|simulation| simulation.format == simulate::Format::Json)
+    {
+        printer.set_level(Level::QuietSuccess);
+    }
+
+    let progress = match get_one::<String>(matches, "progress") {
+        Some(mode) => Some(progress::Mode::from_str(mode).map_err(|err| {
+            format!(
+                "{}{}",
+                printer.colors.error.paint("Error: "),
+                printer.colors.error.paint(err)
+            )
+        })?),
+        None => None,
+    };
+
+    // Mistakes are catastrophic with root privileges, so safety checks are enabled by default
+    let paranoid =
+        get_flag(matches, "paranoid") || (safety::is_root() && !get_flag(matches, "no-paranoid"));
+    let mut safety = if paranoid {
+        Safety::paranoid()
+    } else {
+        Safety::default()
+    };
+    safety.confirm = (safety.confirm || get_flag(matches, "confirm")) && simulation.is_none();
+    safety.contain = safety.contain || get_flag(matches, "contain");
+    if let Some(max_ops) = get_one::<usize>(matches, "max-ops") {
+        safety.max_ops = Some(*max_ops).filter(|max_ops| *max_ops > 0);
+    }
+    safety.plan_hash = get_one::<String>(matches, "plan-hash").cloned();
+
+    let dir_mode = match get_one::<u32>(matches, "dir-mode") {
+        Some(mode) => DirMode::Mode(*mode),
+        None if get_flag(matches, "inherit-perms") => DirMode::Inherit,
+        None => DirMode::Umask,
+    };
 
     Ok(Config {
-        force: matches.get_flag("force"),
-        backup: matches.get_flag("backup"),
-        dirs: matches.get_flag("include-dirs"),
-        dump,
+        force: get_flag(matches, "force") || simulation.is_some(),
+        backup: get_flag(matches, "backup"),
+        backup_keep: get_one::<usize>(matches, "backup-keep").copied(),
+        dirs: get_flag(matches, "include-dirs"),
+        dump: dump && simulation.is_none(),
+        abs: get_flag(matches, "abs"),
+        report,
+        time_filter,
+        capture_ranges,
+        sample: get_one::<usize>(matches, "sample")
+            .map(|size| Sample::new(*size, get_one::<u64>(matches, "sample-seed").copied())),
+        group_by,
+        move_to: get_one::<String>(matches, "move-to").map(PathBuf::from),
+        dir_mode,
+        xattr_tag: get_flag(matches, "xattr-tag"),
+        io_concurrency: get_one::<usize>(matches, "io-concurrency")
+            .copied()
+            .unwrap_or(1),
+        skip_in_use: get_flag(matches, "skip-in-use"),
+        collide_ignore_ext: get_flag(matches, "collide-ignore-ext"),
+        wait_lock: get_flag(matches, "wait-lock"),
+        ignore_errors: matches
+            .try_get_many::<String>("ignore-errors-matching")
+            .ok()
+            .flatten()
+            .unwrap_or_default()
+            .cloned()
+            .collect(),
+        timeout: get_one::<Duration>(matches, "timeout").copied(),
+        progress,
+        slowest: get_one::<usize>(matches, "slowest").copied(),
+        safety,
         run_mode,
         replace_mode,
         printer,
+        settings,
+        driver,
+        simulation,
+        run_id,
     })
 }
 
+/// Get argument value. Arguments not defined for the current command are considered unset.
+fn get_one<'a, T: Any + Clone + Send + Sync>(matches: &'a ArgMatches, id: &str) -> Option<&'a T> {
+    matches.try_get_one::<T>(id).ok().flatten()
+}
+
+/// Get flag value. Flags not defined for the current command are considered unset.
+fn get_flag(matches: &ArgMatches, id: &str) -> bool {
+    get_one::<bool>(matches, id).copied().unwrap_or(false)
+}
+
 /// Detect if output must be colored and returns a properly configured printer.
 fn detect_output_color() -> Printer {
     if std::io::stdout().is_terminal() {
@@ -211,6 +760,7 @@ fn detect_output_color() -> Printer {
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::app::{parse_duration, parse_mode};
 
     #[test]
     fn app_command_from_str() {
@@ -223,6 +773,78 @@ mod test {
             AppCommand::from_str(TO_ASCII_SUBCOMMAND).unwrap(),
             AppCommand::ToASCII
         );
+        assert_eq!(
+            AppCommand::from_str(SERVE_SUBCOMMAND).unwrap(),
+            AppCommand::Serve
+        );
+        assert_eq!(
+            AppCommand::from_str(PLAN_SUBCOMMAND).unwrap(),
+            AppCommand::Plan
+        );
+        assert_eq!(
+            AppCommand::from_str(DUMP_SUBCOMMAND).unwrap(),
+            AppCommand::Dump
+        );
+        assert_eq!(
+            AppCommand::from_str(BACKUPS_SUBCOMMAND).unwrap(),
+            AppCommand::Backups
+        );
+        assert_eq!(
+            AppCommand::from_str(VALIDATE_TEMPLATE_SUBCOMMAND).unwrap(),
+            AppCommand::ValidateTemplate
+        );
+        assert_eq!(
+            AppCommand::from_str(RULES_SUBCOMMAND).unwrap(),
+            AppCommand::Rules
+        );
+        assert_eq!(
+            AppCommand::from_str(PASSES_SUBCOMMAND).unwrap(),
+            AppCommand::Passes
+        );
+    }
+
+    #[test]
+    fn app_definition() {
+        create_app().debug_assert();
+    }
+
+    #[test]
+    fn duration() {
+        assert_eq!(parse_duration("90").unwrap(), Duration::from_secs(90));
+        assert_eq!(parse_duration("90s").unwrap(), Duration::from_secs(90));
+        assert_eq!(parse_duration("10m").unwrap(), Duration::from_secs(600));
+        assert_eq!(parse_duration("2h").unwrap(), Duration::from_secs(7200));
+        assert_eq!(parse_duration("1d").unwrap(), Duration::from_secs(86400));
+        assert_eq!(parse_duration("250ms").unwrap(), Duration::from_millis(250));
+        assert!(parse_duration("").is_err());
+        assert!(parse_duration("10x").is_err());
+        assert!(parse_duration("m").is_err());
+    }
+
+    #[test]
+    fn mode() {
+        assert_eq!(parse_mode("755").unwrap(), 0o755);
+        assert_eq!(parse_mode("2775").unwrap(), 0o2775);
+        assert!(parse_mode("800").is_err());
+        assert!(parse_mode("17777").is_err());
+        assert!(parse_mode("rwx").is_err());
+    }
+
+    #[test]
+    fn paths_after_separator() {
+        let matches = create_app()
+            .try_get_matches_from(["rnr", "a", "b", "--", "-f", "-", "-new\nline"])
+            .unwrap();
+        let parser = ArgumentParser {
+            matches: &matches,
+            printer: &Printer::no_color(),
+            command: &AppCommand::Root,
+            settings: &Settings::default(),
+        };
+        match parser.parse_run_mode().unwrap() {
+            RunMode::Simple(paths) => assert_eq!(paths, ["-f", "-", "-new\nline"]),
+            _ => panic!("Expected simple run mode"),
+        }
     }
 
     #[test]
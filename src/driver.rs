@@ -0,0 +1,1012 @@
+use crate::fileutils::is_same_file;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Component, Path, PathBuf};
+use std::sync::Mutex;
+use std::time::SystemTime;
+use walkdir::WalkDir;
+
+/// Kind of file system entry
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EntryKind {
+    File,
+    Dir,
+    Symlink,
+}
+
+/// File system entry information used while planning and renaming
+#[derive(Clone, Debug, PartialEq)]
+pub struct Entry {
+    pub kind: EntryKind,
+    pub modified: Option<SystemTime>,
+    /// Entry has the hidden attribute, only available on Windows
+    pub hidden: bool,
+    /// Size in bytes
+    pub size: u64,
+}
+
+/// Path listed by a walk, with the kind given by the listing of its directory
+#[derive(Clone, Debug, PartialEq)]
+pub struct WalkEntry {
+    pub path: PathBuf,
+    pub kind: EntryKind,
+}
+
+/// Permissions of the directories created by rnr
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum DirMode {
+    /// Permissions given by the umask of the process
+    #[default]
+    Umask,
+    /// Permission bits, set regardless of the umask
+    Mode(u32),
+    /// Permission bits and group of the parent directory, and its owner if permitted
+    Inherit,
+}
+
+impl Entry {
+    pub fn is_dir(&self) -> bool {
+        self.kind == EntryKind::Dir
+    }
+}
+
+/// File system containing a path
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FileSystem {
+    /// ID of the device, shared by all the paths in the same file system
+    pub device: u64,
+    /// Bytes available to unprivileged users
+    pub available: u64,
+}
+
+/// File system operations needed to plan and apply renames. The local driver is used by default,
+/// other drivers allow running plans against other backends, like the in-memory one.
+pub trait FsDriver: Send + Sync {
+    /// Get entry information following symlinks.
+    fn metadata(&self, path: &Path) -> io::Result<Entry>;
+
+    /// Get entry information without following symlinks.
+    fn symlink_metadata(&self, path: &Path) -> io::Result<Entry>;
+
+    /// Get the content of a symlink without resolving it.
+    fn read_link(&self, path: &Path) -> io::Result<PathBuf>;
+
+    /// Read up to `limit` bytes from the beginning of a file.
+    fn read(&self, path: &Path, limit: u64) -> io::Result<Vec<u8>>;
+
+    /// Rename a path. Symlinks are renamed themselves.
+    fn rename(&self, source: &Path, target: &Path) -> io::Result<()>;
+
+    /// Copy file content into a new file.
+    fn copy(&self, source: &Path, target: &Path) -> io::Result<()>;
+
+    /// Create a symlink at `path` with the given content.
+    fn symlink(&self, link: &Path, path: &Path) -> io::Result<()>;
+
+    /// Remove a file or symlink.
+    fn remove_file(&self, path: &Path) -> io::Result<()>;
+
+    /// Create a directory and its missing parents with the given permissions. Existing directories
+    /// are left as they are.
+    fn create_dir_all(&self, path: &Path, mode: DirMode) -> io::Result<()>;
+
+    /// Set an extended attribute of a path without following symlinks.
+    fn set_xattr(&self, path: &Path, name: &str, value: &[u8]) -> io::Result<()>;
+
+    /// Get the file system containing a path.
+    fn file_system(&self, path: &Path) -> io::Result<FileSystem>;
+
+    /// List the root path and its descendants up to the given depth, without following symlinks.
+    /// Entries rejected by the filter, given the path and its depth, are skipped with their
+    /// descendants. Kinds are read from the directory listings as they are streamed, so
+    /// directories with many entries are listed without reading the metadata of each one.
+    fn walk_entries(
+        &self,
+        root: &Path,
+        max_depth: Option<usize>,
+        filter: &dyn Fn(&Path, usize) -> bool,
+    ) -> Vec<WalkEntry>;
+
+    /// List the root path and its descendants like `walk_entries`, without their kinds.
+    fn walk(
+        &self,
+        root: &Path,
+        max_depth: Option<usize>,
+        filter: &dyn Fn(&Path, usize) -> bool,
+    ) -> Vec<PathBuf> {
+        self.walk_entries(root, max_depth, filter)
+            .into_iter()
+            .map(|entry| entry.path)
+            .collect()
+    }
+
+    /// Check if the paths references the same entry, like case variants in case insensitive
+    /// file systems.
+    fn same_file(&self, source: &Path, target: &Path) -> bool;
+}
+
+/// Driver using the local file system
+pub struct LocalDriver;
+
+impl LocalDriver {
+    fn entry(metadata: fs::Metadata) -> Entry {
+        let kind = if metadata.file_type().is_symlink() {
+            EntryKind::Symlink
+        } else if metadata.is_dir() {
+            EntryKind::Dir
+        } else {
+            EntryKind::File
+        };
+
+        #[cfg(windows)]
+        let hidden = {
+            use std::os::windows::fs::MetadataExt;
+            const FILE_ATTRIBUTE_HIDDEN: u32 = 0x2;
+            metadata.file_attributes() & FILE_ATTRIBUTE_HIDDEN != 0
+        };
+        #[cfg(not(windows))]
+        let hidden = false;
+
+        Entry {
+            kind,
+            modified: metadata.modified().ok(),
+            hidden,
+            size: metadata.len(),
+        }
+    }
+}
+
+impl FsDriver for LocalDriver {
+    fn metadata(&self, path: &Path) -> io::Result<Entry> {
+        fs::metadata(path).map(LocalDriver::entry)
+    }
+
+    fn symlink_metadata(&self, path: &Path) -> io::Result<Entry> {
+        fs::symlink_metadata(path).map(LocalDriver::entry)
+    }
+
+    fn read_link(&self, path: &Path) -> io::Result<PathBuf> {
+        fs::read_link(path)
+    }
+
+    fn read(&self, path: &Path, limit: u64) -> io::Result<Vec<u8>> {
+        let mut buffer = Vec::new();
+        fs::File::open(path)?.take(limit).read_to_end(&mut buffer)?;
+        Ok(buffer)
+    }
+
+    fn rename(&self, source: &Path, target: &Path) -> io::Result<()> {
+        // Targets created by other processes after planning are never replaced
+        #[cfg(target_os = "linux")]
+        if let Some(result) = rename_noreplace(source, target) {
+            return result;
+        }
+        fs::rename(source, target)
+    }
+
+    fn copy(&self, source: &Path, target: &Path) -> io::Result<()> {
+        fs::copy(source, target).map(|_| ())
+    }
+
+    fn symlink(&self, link: &Path, path: &Path) -> io::Result<()> {
+        #[cfg(windows)]
+        {
+            // Windows needs to know the kind of the target, relative links start at the parent
+            let target = path.parent().unwrap_or(Path::new("")).join(link);
+            if target.is_dir() {
+                std::os::windows::fs::symlink_dir(link, path)
+            } else {
+                std::os::windows::fs::symlink_file(link, path)
+            }
+        }
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink(link, path)
+        }
+    }
+
+    fn remove_file(&self, path: &Path) -> io::Result<()> {
+        fs::remove_file(path)
+    }
+
+    fn create_dir_all(&self, path: &Path, mode: DirMode) -> io::Result<()> {
+        if mode == DirMode::Umask {
+            return fs::create_dir_all(path);
+        }
+        // Missing directories are created one by one from the topmost, to set the mode of each one
+        let missing: Vec<&Path> = path
+            .ancestors()
+            .take_while(|dir| !dir.as_os_str().is_empty() && !dir.exists())
+            .collect();
+        if missing.is_empty() && !path.is_dir() {
+            return Err(io::Error::from(io::ErrorKind::AlreadyExists));
+        }
+        for dir in missing.into_iter().rev() {
+            fs::create_dir(dir)?;
+            set_dir_mode(dir, mode)?;
+        }
+        Ok(())
+    }
+
+    fn set_xattr(&self, path: &Path, name: &str, value: &[u8]) -> io::Result<()> {
+        #[cfg(any(target_os = "linux", target_os = "macos"))]
+        {
+            use std::ffi::CString;
+            use std::os::unix::ffi::OsStrExt;
+
+            let path = CString::new(path.as_os_str().as_bytes())?;
+            let name = CString::new(name)?;
+            // SAFETY: both strings are NUL terminated and the value length matches its buffer
+            #[cfg(target_os = "linux")]
+            let result = unsafe {
+                libc::lsetxattr(
+                    path.as_ptr(),
+                    name.as_ptr(),
+                    value.as_ptr().cast(),
+                    value.len(),
+                    0,
+                )
+            };
+            // SAFETY: same as above, macOS takes the position and the flags as extra arguments
+            #[cfg(target_os = "macos")]
+            let result = unsafe {
+                libc::setxattr(
+                    path.as_ptr(),
+                    name.as_ptr(),
+                    value.as_ptr().cast(),
+                    value.len(),
+                    0,
+                    libc::XATTR_NOFOLLOW,
+                )
+            };
+            if result == 0 {
+                Ok(())
+            } else {
+                Err(io::Error::last_os_error())
+            }
+        }
+
+        #[cfg(not(any(target_os = "linux", target_os = "macos")))]
+        {
+            let _ = (path, name, value);
+            Err(io::Error::from(io::ErrorKind::Unsupported))
+        }
+    }
+
+    fn file_system(&self, path: &Path) -> io::Result<FileSystem> {
+        #[cfg(unix)]
+        {
+            use std::ffi::CString;
+            use std::os::unix::ffi::OsStrExt;
+            use std::os::unix::fs::MetadataExt;
+
+            let device = fs::metadata(path)?.dev();
+            let path = CString::new(path.as_os_str().as_bytes())?;
+            // SAFETY: statvfs is plain data, all zeros is a valid value
+            let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+            // SAFETY: the path is NUL terminated and the struct outlives the call
+            if unsafe { libc::statvfs(path.as_ptr(), &mut stat) } != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(FileSystem {
+                device,
+                #[allow(clippy::unnecessary_cast)]
+                available: stat.f_bavail as u64 * stat.f_frsize as u64,
+            })
+        }
+
+        #[cfg(not(unix))]
+        {
+            let _ = path;
+            Err(io::Error::from(io::ErrorKind::Unsupported))
+        }
+    }
+
+    fn walk_entries(
+        &self,
+        root: &Path,
+        max_depth: Option<usize>,
+        filter: &dyn Fn(&Path, usize) -> bool,
+    ) -> Vec<WalkEntry> {
+        let walkdir = match max_depth {
+            Some(max_depth) => WalkDir::new(root).max_depth(max_depth),
+            None => WalkDir::new(root),
+        };
+        // Directories are read in batches of entries (getdents on Linux), and file types come
+        // from those entries unless the file system does not provide them
+        walkdir
+            .into_iter()
+            .filter_entry(|entry| filter(entry.path(), entry.depth()))
+            .filter_map(|entry| entry.ok())
+            .map(|entry| {
+                let file_type = entry.file_type();
+                let kind = if file_type.is_symlink() {
+                    EntryKind::Symlink
+                } else if file_type.is_dir() {
+                    EntryKind::Dir
+                } else {
+                    EntryKind::File
+                };
+                WalkEntry {
+                    path: entry.into_path(),
+                    kind,
+                }
+            })
+            .collect()
+    }
+
+    fn same_file(&self, source: &Path, target: &Path) -> bool {
+        is_same_file(source, target)
+    }
+}
+
+/// Rename a path failing if the target exists, checking and renaming in a single atomic step with
+/// `renameat2` and `RENAME_NOREPLACE`. Returns `None` if the kernel or the file system does not
+/// support it.
+#[cfg(target_os = "linux")]
+fn rename_noreplace(source: &Path, target: &Path) -> Option<io::Result<()>> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let source = CString::new(source.as_os_str().as_bytes()).ok()?;
+    let target = CString::new(target.as_os_str().as_bytes()).ok()?;
+    // SAFETY: both paths are NUL terminated strings that outlive the call. The raw syscall is used
+    // because the libc wrapper is missing in older C libraries.
+    let result = unsafe {
+        libc::syscall(
+            libc::SYS_renameat2,
+            libc::AT_FDCWD,
+            source.as_ptr(),
+            libc::AT_FDCWD,
+            target.as_ptr(),
+            libc::RENAME_NOREPLACE,
+        )
+    };
+    if result == 0 {
+        return Some(Ok(()));
+    }
+    let err = io::Error::last_os_error();
+    match err.raw_os_error() {
+        Some(libc::ENOSYS | libc::EINVAL) => None,
+        _ => Some(Err(err)),
+    }
+}
+
+/// Set the permissions of a created directory. Only root can give directories away, so other
+/// users inheriting permissions keep owning them, and keep their group if they are not members of
+/// the parent's one.
+#[cfg(unix)]
+fn set_dir_mode(dir: &Path, mode: DirMode) -> io::Result<()> {
+    use std::os::unix::fs::{chown, MetadataExt, PermissionsExt};
+
+    let bits = match mode {
+        DirMode::Umask => return Ok(()),
+        DirMode::Mode(bits) => bits,
+        DirMode::Inherit => {
+            let parent = match dir.parent() {
+                Some(parent) if !parent.as_os_str().is_empty() => parent,
+                _ => Path::new("."),
+            };
+            let parent = fs::metadata(parent)?;
+            // Ownership is changed first, it clears the setgid bit in some systems
+            if chown(dir, Some(parent.uid()), Some(parent.gid())).is_err() {
+                let _ = chown(dir, None, Some(parent.gid()));
+            }
+            parent.mode() & 0o7777
+        }
+    };
+    fs::set_permissions(dir, fs::Permissions::from_mode(bits))
+}
+
+#[cfg(not(unix))]
+fn set_dir_mode(_dir: &Path, _mode: DirMode) -> io::Result<()> {
+    Ok(())
+}
+
+/// Maximum number of symlinks followed to resolve a path
+const MAX_SYMLINK_HOPS: usize = 40;
+
+/// Driver keeping a file system tree in memory. Paths are compared as they are given, only `.`
+/// components are ignored, and symlinks are only resolved in the last path component. The root
+/// and the empty path are always existing directories.
+#[derive(Default)]
+pub struct MemoryDriver {
+    nodes: Mutex<Nodes>,
+    /// Bytes available in the tree, unlimited if not set
+    available: Mutex<Option<u64>>,
+}
+
+type Nodes = BTreeMap<PathBuf, MemoryNode>;
+
+#[derive(Clone, Debug)]
+struct MemoryNode {
+    content: MemoryContent,
+    modified: SystemTime,
+    xattrs: BTreeMap<String, Vec<u8>>,
+}
+
+#[derive(Clone, Debug)]
+enum MemoryContent {
+    File(Vec<u8>),
+    Dir,
+    Symlink(PathBuf),
+}
+
+impl MemoryDriver {
+    pub fn new() -> MemoryDriver {
+        MemoryDriver::default()
+    }
+
+    /// Create a file with the given content, creating its parent directories too.
+    pub fn create_file(&self, path: &Path, content: &[u8]) {
+        self.create_parents(path);
+        self.insert(path, MemoryContent::File(content.to_vec()));
+    }
+
+    /// Create a directory and its parents.
+    pub fn create_dir(&self, path: &Path) {
+        self.create_parents(path);
+        self.insert(path, MemoryContent::Dir);
+    }
+
+    /// Create a symlink with the given content, creating its parent directories too.
+    pub fn create_symlink(&self, path: &Path, link: &Path) {
+        self.create_parents(path);
+        self.insert(path, MemoryContent::Symlink(link.to_path_buf()));
+    }
+
+    /// Set modification time of an existing entry.
+    pub fn set_modified(&self, path: &Path, modified: SystemTime) -> io::Result<()> {
+        let mut nodes = self.nodes.lock().unwrap();
+        let node = nodes.get_mut(&key(path)).ok_or_else(not_found)?;
+        node.modified = modified;
+        Ok(())
+    }
+
+    fn create_parents(&self, path: &Path) {
+        let ancestors: Vec<PathBuf> = key(path)
+            .ancestors()
+            .skip(1)
+            .filter(|ancestor| !is_implicit_dir(ancestor))
+            .map(Path::to_path_buf)
+            .collect();
+        let mut nodes = self.nodes.lock().unwrap();
+        for ancestor in ancestors.into_iter().rev() {
+            nodes.entry(ancestor).or_insert_with(|| MemoryNode {
+                content: MemoryContent::Dir,
+                modified: SystemTime::now(),
+                xattrs: BTreeMap::new(),
+            });
+        }
+    }
+
+    fn insert(&self, path: &Path, content: MemoryContent) {
+        let path = key(path);
+        if is_implicit_dir(&path) {
+            return;
+        }
+        self.nodes.lock().unwrap().insert(
+            path,
+            MemoryNode {
+                content,
+                modified: SystemTime::now(),
+                xattrs: BTreeMap::new(),
+            },
+        );
+    }
+
+    /// Limit the bytes available in the tree.
+    #[cfg(test)]
+    pub fn set_available(&self, available: u64) {
+        *self.available.lock().unwrap() = Some(available);
+    }
+
+    /// Get an extended attribute of a path without following symlinks.
+    #[cfg(test)]
+    pub fn xattr(&self, path: &Path, name: &str) -> Option<Vec<u8>> {
+        let nodes = self.nodes.lock().unwrap();
+        nodes.get(&key(path))?.xattrs.get(name).cloned()
+    }
+
+    /// Get the node of a path following symlinks.
+    fn resolve(&self, nodes: &Nodes, path: &Path) -> io::Result<MemoryNode> {
+        let mut path = key(path);
+        for _ in 0..MAX_SYMLINK_HOPS {
+            if is_implicit_dir(&path) {
+                return Ok(implicit_dir());
+            }
+            let node = nodes.get(&path).ok_or_else(not_found)?;
+            match &node.content {
+                MemoryContent::Symlink(link) => {
+                    path = match path.parent() {
+                        Some(parent) if link.is_relative() => key(&parent.join(link)),
+                        _ => key(link),
+                    };
+                }
+                _ => return Ok(node.clone()),
+            }
+        }
+        Err(io::Error::other("too many levels of symbolic links"))
+    }
+
+    /// Check that the parent of a path is a directory, so an entry can be created in it.
+    fn check_parent(&self, nodes: &Nodes, path: &Path) -> io::Result<()> {
+        match path.parent() {
+            Some(parent) if !is_implicit_dir(parent) => match self.resolve(nodes, parent) {
+                Ok(node) if matches!(node.content, MemoryContent::Dir) => Ok(()),
+                Ok(_) => Err(io::Error::from(io::ErrorKind::NotADirectory)),
+                Err(err) => Err(err),
+            },
+            _ => Ok(()),
+        }
+    }
+}
+
+impl FsDriver for MemoryDriver {
+    fn metadata(&self, path: &Path) -> io::Result<Entry> {
+        let nodes = self.nodes.lock().unwrap();
+        self.resolve(&nodes, path).map(|node| node.entry())
+    }
+
+    fn symlink_metadata(&self, path: &Path) -> io::Result<Entry> {
+        let path = key(path);
+        if is_implicit_dir(&path) {
+            return Ok(implicit_dir().entry());
+        }
+        let nodes = self.nodes.lock().unwrap();
+        nodes
+            .get(&path)
+            .map(MemoryNode::entry)
+            .ok_or_else(not_found)
+    }
+
+    fn read_link(&self, path: &Path) -> io::Result<PathBuf> {
+        let nodes = self.nodes.lock().unwrap();
+        match nodes.get(&key(path)).map(|node| &node.content) {
+            Some(MemoryContent::Symlink(link)) => Ok(link.clone()),
+            Some(_) => Err(io::Error::from(io::ErrorKind::InvalidInput)),
+            None => Err(not_found()),
+        }
+    }
+
+    fn read(&self, path: &Path, limit: u64) -> io::Result<Vec<u8>> {
+        let nodes = self.nodes.lock().unwrap();
+        match self.resolve(&nodes, path)?.content {
+            MemoryContent::File(content) => {
+                let limit = usize::try_from(limit).unwrap_or(usize::MAX);
+                Ok(content.into_iter().take(limit).collect())
+            }
+            _ => Err(io::Error::from(io::ErrorKind::IsADirectory)),
+        }
+    }
+
+    fn rename(&self, source: &Path, target: &Path) -> io::Result<()> {
+        let (source, target) = (key(source), key(target));
+        let mut nodes = self.nodes.lock().unwrap();
+        if !nodes.contains_key(&source) {
+            return Err(not_found());
+        }
+        self.check_parent(&nodes, &target)?;
+        if source == target {
+            return Ok(());
+        }
+        if target.starts_with(&source) {
+            return Err(io::Error::from(io::ErrorKind::InvalidInput));
+        }
+
+        // Existing targets are never replaced, like `LocalDriver` with `RENAME_NOREPLACE`
+        if nodes.contains_key(&target) || is_implicit_dir(&target) {
+            return Err(io::Error::from(io::ErrorKind::AlreadyExists));
+        }
+
+        // Directories are moved with all their descendants
+        let moved: Vec<PathBuf> = nodes
+            .range(source.clone()..)
+            .map(|(path, _)| path)
+            .take_while(|path| path.starts_with(&source))
+            .cloned()
+            .collect();
+        for path in moved {
+            let node = nodes.remove(&path).unwrap();
+            let relative = path.strip_prefix(&source).unwrap();
+            let new_path = if relative.as_os_str().is_empty() {
+                target.clone()
+            } else {
+                target.join(relative)
+            };
+            nodes.insert(new_path, node);
+        }
+        Ok(())
+    }
+
+    fn copy(&self, source: &Path, target: &Path) -> io::Result<()> {
+        let mut nodes = self.nodes.lock().unwrap();
+        let content = match self.resolve(&nodes, source)?.content {
+            MemoryContent::File(content) => content,
+            _ => return Err(io::Error::from(io::ErrorKind::IsADirectory)),
+        };
+        self.check_parent(&nodes, target)?;
+        nodes.insert(
+            key(target),
+            MemoryNode {
+                content: MemoryContent::File(content),
+                modified: SystemTime::now(),
+                xattrs: BTreeMap::new(),
+            },
+        );
+        Ok(())
+    }
+
+    fn symlink(&self, link: &Path, path: &Path) -> io::Result<()> {
+        let mut nodes = self.nodes.lock().unwrap();
+        self.check_parent(&nodes, path)?;
+        let path = key(path);
+        if nodes.contains_key(&path) || is_implicit_dir(&path) {
+            return Err(io::Error::from(io::ErrorKind::AlreadyExists));
+        }
+        nodes.insert(
+            path,
+            MemoryNode {
+                content: MemoryContent::Symlink(link.to_path_buf()),
+                modified: SystemTime::now(),
+                xattrs: BTreeMap::new(),
+            },
+        );
+        Ok(())
+    }
+
+    fn remove_file(&self, path: &Path) -> io::Result<()> {
+        let mut nodes = self.nodes.lock().unwrap();
+        let path = key(path);
+        match nodes.get(&path).map(|node| &node.content) {
+            Some(MemoryContent::Dir) => Err(io::Error::from(io::ErrorKind::IsADirectory)),
+            Some(_) => {
+                nodes.remove(&path);
+                Ok(())
+            }
+            None => Err(not_found()),
+        }
+    }
+
+    /// Permissions are not simulated, directories are created the same with any mode.
+    fn create_dir_all(&self, path: &Path, _mode: DirMode) -> io::Result<()> {
+        {
+            let nodes = self.nodes.lock().unwrap();
+            if let Ok(node) = self.resolve(&nodes, path) {
+                return match node.content {
+                    MemoryContent::Dir => Ok(()),
+                    _ => Err(io::Error::from(io::ErrorKind::AlreadyExists)),
+                };
+            }
+            for ancestor in key(path).ancestors().skip(1) {
+                match self.resolve(&nodes, ancestor) {
+                    Ok(node) if !matches!(node.content, MemoryContent::Dir) => {
+                        return Err(io::Error::from(io::ErrorKind::NotADirectory))
+                    }
+                    _ => {}
+                }
+            }
+        }
+        self.create_dir(path);
+        Ok(())
+    }
+
+    fn file_system(&self, path: &Path) -> io::Result<FileSystem> {
+        let nodes = self.nodes.lock().unwrap();
+        self.resolve(&nodes, path)?;
+        Ok(FileSystem {
+            device: 0,
+            available: self.available.lock().unwrap().unwrap_or(u64::MAX),
+        })
+    }
+
+    fn set_xattr(&self, path: &Path, name: &str, value: &[u8]) -> io::Result<()> {
+        let mut nodes = self.nodes.lock().unwrap();
+        let node = nodes.get_mut(&key(path)).ok_or_else(not_found)?;
+        node.xattrs.insert(name.to_string(), value.to_vec());
+        Ok(())
+    }
+
+    fn walk_entries(
+        &self,
+        root: &Path,
+        max_depth: Option<usize>,
+        filter: &dyn Fn(&Path, usize) -> bool,
+    ) -> Vec<WalkEntry> {
+        let nodes = self.nodes.lock().unwrap();
+        let root_key = key(root);
+        if !is_implicit_dir(&root_key) && !nodes.contains_key(&root_key) {
+            return Vec::new();
+        }
+
+        // Descendants are sorted right after their ancestors. Walked paths keep the given root.
+        let root_depth = root_key.components().count();
+        let mut walk_list = Vec::new();
+        let mut skipped: Option<PathBuf> = None;
+        let candidates = std::iter::once(root_key.clone()).chain(
+            nodes
+                .range(root_key.clone()..)
+                .map(|(path, _)| path.clone())
+                .filter(|path| *path != root_key && path.has_root() == root_key.has_root())
+                .take_while(|path| path.starts_with(&root_key)),
+        );
+        for path in candidates {
+            if skipped
+                .as_ref()
+                .is_some_and(|skipped| path.starts_with(skipped))
+            {
+                continue;
+            }
+            let depth = path.components().count() - root_depth;
+            if max_depth.is_some_and(|max_depth| depth > max_depth) {
+                continue;
+            }
+            let walked = match path.strip_prefix(&root_key) {
+                Ok(relative) if !relative.as_os_str().is_empty() => root.join(relative),
+                _ => root.to_path_buf(),
+            };
+            if filter(&walked, depth) {
+                let kind = nodes
+                    .get(&path)
+                    .map_or(EntryKind::Dir, |node| node.entry().kind);
+                walk_list.push(WalkEntry { path: walked, kind });
+            } else {
+                skipped = Some(path);
+            }
+        }
+        walk_list
+    }
+
+    fn same_file(&self, source: &Path, target: &Path) -> bool {
+        key(source) == key(target)
+    }
+}
+
+impl MemoryNode {
+    fn entry(&self) -> Entry {
+        Entry {
+            kind: match self.content {
+                MemoryContent::File(_) => EntryKind::File,
+                MemoryContent::Dir => EntryKind::Dir,
+                MemoryContent::Symlink(_) => EntryKind::Symlink,
+            },
+            modified: Some(self.modified),
+            hidden: false,
+            size: match &self.content {
+                MemoryContent::File(content) => content.len() as u64,
+                _ => 0,
+            },
+        }
+    }
+}
+
+/// Get the key of a path in the memory tree resolving `.` and `..` components lexically.
+fn key(path: &Path) -> PathBuf {
+    let mut key = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir if key.file_name().is_some() => {
+                key.pop();
+            }
+            component => key.push(component),
+        }
+    }
+    key
+}
+
+/// Root and current directories exist without being stored
+fn is_implicit_dir(path: &Path) -> bool {
+    path.as_os_str().is_empty() || path.parent().is_none()
+}
+
+fn implicit_dir() -> MemoryNode {
+    MemoryNode {
+        content: MemoryContent::Dir,
+        modified: SystemTime::UNIX_EPOCH,
+        xattrs: BTreeMap::new(),
+    }
+}
+
+fn not_found() -> io::Error {
+    io::Error::from(io::ErrorKind::NotFound)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn memory_tree() {
+        let driver = MemoryDriver::new();
+        driver.create_file(Path::new("/data/photos/a.jpg"), b"photo");
+        driver.create_file(Path::new("/data/notes.txt"), b"");
+        driver.create_dir(Path::new("/data/.cache"));
+        driver.create_file(Path::new("/data/.cache/c"), b"");
+
+        assert!(driver.metadata(Path::new("/data/photos")).unwrap().is_dir());
+        assert_eq!(
+            driver.read(Path::new("/data/photos/a.jpg"), 3).unwrap(),
+            b"pho"
+        );
+        assert!(driver
+            .metadata(Path::new("/data/./photos/../notes.txt"))
+            .is_ok());
+        assert!(driver.metadata(Path::new("/data/missing")).is_err());
+
+        // Hidden entries are skipped with their descendants
+        let walk = driver.walk(Path::new("/data"), None, &|path, depth| {
+            depth == 0 || !path.file_name().unwrap().to_string_lossy().starts_with('.')
+        });
+        assert_eq!(
+            walk,
+            [
+                "/data",
+                "/data/notes.txt",
+                "/data/photos",
+                "/data/photos/a.jpg"
+            ]
+            .iter()
+            .map(PathBuf::from)
+            .collect::<Vec<_>>()
+        );
+        assert_eq!(
+            driver.walk(Path::new("/data"), Some(0), &|_, _| true).len(),
+            1
+        );
+    }
+
+    #[test]
+    fn memory_rename() {
+        let driver = MemoryDriver::new();
+        driver.create_file(Path::new("/data/dir/file"), b"");
+        driver.create_file(Path::new("/data/other"), b"");
+
+        // Directories are moved with their content
+        driver
+            .rename(Path::new("/data/dir"), Path::new("/data/moved"))
+            .unwrap();
+        assert!(driver.metadata(Path::new("/data/moved/file")).is_ok());
+        assert!(driver.metadata(Path::new("/data/dir")).is_err());
+
+        // Existing targets are never replaced
+        let err = driver
+            .rename(Path::new("/data/other"), Path::new("/data/moved/file"))
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+        assert!(driver.metadata(Path::new("/data/other")).is_ok());
+        let err = driver
+            .rename(Path::new("/data/other"), Path::new("/data/moved"))
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+        assert!(driver
+            .rename(Path::new("/data/missing"), Path::new("/data/x"))
+            .is_err());
+        assert!(driver
+            .rename(Path::new("/data/other"), Path::new("/missing/x"))
+            .is_err());
+
+        // Missing parents are created, but files are never replaced by directories
+        let mode = DirMode::Umask;
+        driver
+            .create_dir_all(Path::new("/data/new/sub"), mode)
+            .unwrap();
+        assert!(driver
+            .metadata(Path::new("/data/new/sub"))
+            .unwrap()
+            .is_dir());
+        driver.create_dir_all(Path::new("/data/new"), mode).unwrap();
+        assert!(driver
+            .create_dir_all(Path::new("/data/other"), mode)
+            .is_err());
+        assert!(driver
+            .create_dir_all(Path::new("/data/other/sub"), mode)
+            .is_err());
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn local_rename_noreplace() {
+        let tempdir = tempfile::tempdir().expect("Error creating temp directory");
+        println!("Running test in '{:?}'", tempdir);
+        let (source, target) = (tempdir.path().join("source"), tempdir.path().join("target"));
+        fs::write(&source, "source").unwrap();
+        fs::write(&target, "created after planning").unwrap();
+
+        let err = LocalDriver.rename(&source, &target).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+        assert_eq!(
+            fs::read_to_string(&target).unwrap(),
+            "created after planning"
+        );
+
+        fs::remove_file(&target).unwrap();
+        LocalDriver.rename(&source, &target).unwrap();
+        assert_eq!(fs::read_to_string(&target).unwrap(), "source");
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn local_dir_mode() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let tempdir = tempfile::tempdir().expect("Error creating temp directory");
+        println!("Running test in '{:?}'", tempdir);
+        let path = |name: &str| tempdir.path().join(name);
+        let mode = |name: &str| fs::metadata(path(name)).unwrap().permissions().mode() & 0o7777;
+
+        LocalDriver
+            .create_dir_all(&path("a/b"), DirMode::Mode(0o750))
+            .unwrap();
+        assert_eq!(mode("a"), 0o750);
+        assert_eq!(mode("a/b"), 0o750);
+
+        fs::create_dir(path("shared")).unwrap();
+        fs::set_permissions(path("shared"), fs::Permissions::from_mode(0o2770)).unwrap();
+        LocalDriver
+            .create_dir_all(&path("shared/c/d"), DirMode::Inherit)
+            .unwrap();
+        assert_eq!(mode("shared/c"), 0o2770);
+        assert_eq!(mode("shared/c/d"), 0o2770);
+
+        // Existing directories are left as they are, files are not replaced
+        LocalDriver
+            .create_dir_all(&path("a"), DirMode::Mode(0o700))
+            .unwrap();
+        assert_eq!(mode("a"), 0o750);
+        fs::write(path("file"), "").unwrap();
+        assert!(LocalDriver
+            .create_dir_all(&path("file"), DirMode::Mode(0o700))
+            .is_err());
+    }
+
+    #[test]
+    fn memory_symlinks() {
+        let driver = MemoryDriver::new();
+        driver.create_file(Path::new("/data/file"), b"content");
+        driver
+            .symlink(Path::new("file"), Path::new("/data/link"))
+            .unwrap();
+        driver
+            .symlink(Path::new("../missing"), Path::new("/data/dangling"))
+            .unwrap();
+        driver
+            .symlink(Path::new("loop"), Path::new("/data/loop"))
+            .unwrap();
+
+        assert_eq!(
+            driver
+                .symlink_metadata(Path::new("/data/link"))
+                .unwrap()
+                .kind,
+            EntryKind::Symlink
+        );
+        assert_eq!(
+            driver.metadata(Path::new("/data/link")).unwrap().kind,
+            EntryKind::File
+        );
+        assert_eq!(
+            driver.read(Path::new("/data/link"), 100).unwrap(),
+            b"content"
+        );
+        assert!(driver.symlink_metadata(Path::new("/data/dangling")).is_ok());
+        assert!(driver.metadata(Path::new("/data/dangling")).is_err());
+        assert!(driver.metadata(Path::new("/data/loop")).is_err());
+        assert_eq!(
+            driver.read_link(Path::new("/data/dangling")).unwrap(),
+            PathBuf::from("../missing")
+        );
+
+        // Renaming or removing links does not change their targets
+        driver
+            .rename(Path::new("/data/link"), Path::new("/data/renamed"))
+            .unwrap();
+        assert_eq!(
+            driver.read_link(Path::new("/data/renamed")).unwrap(),
+            PathBuf::from("file")
+        );
+        driver.remove_file(Path::new("/data/renamed")).unwrap();
+        assert!(driver.metadata(Path::new("/data/file")).is_ok());
+    }
+}
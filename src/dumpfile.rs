@@ -1,23 +1,73 @@
 use chrono;
+use chrono::NaiveDateTime;
+use crate::driver::FsDriver;
 use crate::error::*;
+use crate::fileutils::normalize_path;
+use crate::plan;
 use serde_json;
-use crate::solver::{Operation, Operations};
+use crate::solver::{Operation, Operations, Tag};
+use serde_json::Value;
 use std::fs::File;
-use std::path::Path;
+use std::io::Read;
+use std::path::{Path, PathBuf};
 
-/// Dump operations intto file in JSON format
-pub fn dump_to_file(operations: &[Operation]) -> Result<()> {
+/// Format of dates stored in dump files
+pub const DATE_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+
+/// Format of dates in dump file names
+pub const FILE_DATE_FORMAT: &str = "%Y-%m-%d_%H%M%S";
+
+/// Dump operations intto file in JSON format. Returns the path of the created file.
+pub fn dump_to_file(operations: &[Operation], run_id: &str) -> Result<PathBuf> {
+    // Create filename with the following syntax: "rnr-<DATE>-<RUN ID>.json"
     let now = chrono::Local::now();
+    let filename = format!("rnr-{}-{}.json", now.format(FILE_DATE_FORMAT), run_id);
+
+    let filepath = PathBuf::from(filename);
+    let date = now.naive_local();
+    write(&filepath, date, Some(run_id), operations)?;
+    Ok(filepath)
+}
+
+/// Write operations dump into the given file. Paths are stored as JSON strings, so any valid UTF-8
+/// name is preserved, including names with newlines or leading dashes.
+pub fn write_to_file(
+    filepath: &Path,
+    operations: &[Operation],
+    run_id: Option<&str>,
+) -> Result<()> {
+    let date = chrono::Local::now().naive_local();
+    write(filepath, date, run_id, operations)
+}
+
+/// Write back a dump read from a file keeping its date, so updating a dump file does not change
+/// which paths were modified after it was created. The plan hash is computed again, updates like
+/// review tags change it.
+pub fn write_dump(filepath: &Path, dump: &Dump) -> Result<()> {
+    let date = dump
+        .date
+        .unwrap_or_else(|| chrono::Local::now().naive_local());
+    write(filepath, date, dump.run_id.as_deref(), &dump.operations)
+}
+
+/// Write a dump file with the plan hash of the written operations, so the stored hash always
+/// matches the content of the file.
+fn write(
+    filepath: &Path,
+    date: NaiveDateTime,
+    run_id: Option<&str>,
+    operations: &[Operation],
+) -> Result<()> {
     let dump = DumpFormat {
-        date: now.format("%Y-%m-%d %H:%M:%S").to_string(),
+        date: date.format(DATE_FORMAT).to_string(),
+        run_id: run_id.map(str::to_string),
+        plan_hash: Some(plan::hash(operations)),
         operations: operations.to_vec(),
     };
-
-    // Create filename with the following syntax: "rnr-<DATE>.json"
-    let filename = "rnr-".to_string() + &now.format("%Y-%m-%d_%H%M%S").to_string() + ".json";
+    let filename = filepath.to_string_lossy().to_string();
 
     // Dump info to a file
-    let file = match File::create(&filename) {
+    let file = match File::create(filepath) {
         Ok(file) => file,
         Err(_) => {
             return Err(Error {
@@ -35,8 +85,8 @@ pub fn dump_to_file(operations: &[Operation]) -> Result<()> {
     }
 }
 
-/// Read operations from a dump file and generate a Operations vector
-pub fn read_from_file(filepath: &Path) -> Result<Operations> {
+/// Read a dump file and generate a Operations vector with its date and run ID
+pub fn read_dump(filepath: &Path) -> Result<Dump> {
     let file = match File::open(&filepath) {
         Ok(file) => file,
         Err(_) => {
@@ -46,7 +96,22 @@ pub fn read_from_file(filepath: &Path) -> Result<Operations> {
             })
         }
     };
-    let dump: DumpFormat = match serde_json::from_reader(file) {
+    parse(file, filepath)
+}
+
+/// Read a dump file through a driver, like dumps found next to the backups they created.
+pub fn read_dump_with_driver(filepath: &Path, driver: &dyn FsDriver) -> Result<Dump> {
+    match driver.read(filepath, u64::MAX) {
+        Ok(content) => parse(content.as_slice(), filepath),
+        Err(_) => Err(Error {
+            kind: ErrorKind::ReadFile,
+            value: Some(filepath.to_string_lossy().to_string()),
+        }),
+    }
+}
+
+fn parse<R: Read>(reader: R, filepath: &Path) -> Result<Dump> {
+    let dump: DumpFormat = match serde_json::from_reader(reader) {
         Ok(dump) => dump,
         Err(_) => {
             return Err(Error {
@@ -55,11 +120,208 @@ pub fn read_from_file(filepath: &Path) -> Result<Operations> {
             })
         }
     };
-    Ok(dump.operations)
+    // Dumps may be generated in other platforms
+    let operations = dump
+        .operations
+        .into_iter()
+        .map(|operation| Operation {
+            source: normalize_path(&operation.source),
+            target: normalize_path(&operation.target),
+            tag: operation.tag,
+            // Symlink contents are kept as they are, even if they are relative or dangling
+            link: operation.link,
+        })
+        .collect();
+    Ok(Dump {
+        date: NaiveDateTime::parse_from_str(&dump.date, DATE_FORMAT).ok(),
+        run_id: dump.run_id,
+        operations,
+    })
+}
+
+/// Content of a dump file
+pub struct Dump {
+    /// Local date when the dump was written
+    pub date: Option<NaiveDateTime>,
+    /// Run that created the dump, not available in dumps of older versions
+    pub run_id: Option<String>,
+    pub operations: Operations,
 }
 
 #[derive(Serialize, Deserialize)]
 struct DumpFormat {
     date: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    run_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    plan_hash: Option<String>,
     operations: Operations,
 }
+
+/// Describe the fields of dump files, one `FIELD: TYPE, required|optional[, FORMAT]` line per
+/// field. Fields are derived from dumps serialized with and without their optional fields, so the
+/// description always matches the format written and read by this version.
+pub fn spec() -> String {
+    let operation = |full: bool| Operation {
+        source: PathBuf::from("source"),
+        target: PathBuf::from("target"),
+        tag: Some(Tag::Keep).filter(|_| full),
+        link: Some(PathBuf::from("link")).filter(|_| full),
+    };
+    let dump = |full: bool| {
+        let dump = DumpFormat {
+            date: String::new(),
+            run_id: Some(String::new()).filter(|_| full),
+            plan_hash: Some(String::new()).filter(|_| full),
+            operations: vec![operation(full)],
+        };
+        serde_json::to_value(dump).unwrap_or(Value::Null)
+    };
+
+    // Values with a format of their own
+    let tags: Vec<String> = Tag::ALL
+        .iter()
+        .filter_map(|tag| serde_json::to_value(tag).ok())
+        .filter_map(|tag| tag.as_str().map(str::to_string))
+        .collect();
+    let formats = [
+        ("date", format!("local date formatted as {}", DATE_FORMAT)),
+        ("operations[].tag", format!("one of {}", tags.join(", "))),
+    ];
+
+    let mut lines = Vec::new();
+    describe(&dump(true), Some(&dump(false)), "", &formats, &mut lines);
+    lines.join("\n")
+}
+
+/// Describe the fields of a serialized object, nested in arrays too. Fields missing in the
+/// minimal object are optional.
+fn describe(
+    full: &Value,
+    minimal: Option<&Value>,
+    prefix: &str,
+    formats: &[(&str, String)],
+    lines: &mut Vec<String>,
+) {
+    let fields = match full.as_object() {
+        Some(fields) => fields,
+        None => return,
+    };
+    for (name, value) in fields {
+        let field = format!("{}{}", prefix, name);
+        let required = minimal.and_then(|minimal| minimal.get(name));
+        let mut line = format!(
+            "{}: {}, {}",
+            field,
+            type_name(value),
+            if required.is_some() {
+                "required"
+            } else {
+                "optional"
+            }
+        );
+        if let Some((_, format)) = formats.iter().find(|(path, _)| *path == field) {
+            line.push_str(", ");
+            line.push_str(format);
+        }
+        lines.push(line);
+        if let Some(item) = value.as_array().and_then(|items| items.first()) {
+            let minimal_item = required
+                .and_then(Value::as_array)
+                .and_then(|items| items.first());
+            describe(item, minimal_item, &format!("{}[].", field), formats, lines);
+        }
+    }
+}
+
+fn type_name(value: &Value) -> String {
+    match value {
+        Value::Null => "null".to_string(),
+        Value::Bool(_) => "boolean".to_string(),
+        Value::Number(_) => "number".to_string(),
+        Value::String(_) => "string".to_string(),
+        Value::Array(items) => format!(
+            "array of {}s",
+            items.first().map_or("value".to_string(), type_name)
+        ),
+        Value::Object(_) => "object".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    extern crate tempfile;
+    use super::*;
+
+    #[test]
+    fn dump_special_names() {
+        let tempdir = tempfile::tempdir().expect("Error creating temp directory");
+        println!("Running test in '{:?}'", tempdir);
+        let dump_file = tempdir.path().join("dump.json");
+
+        let operations: Operations = ["-", "-rf", "new\nline", " space ", "quo\"te\\"]
+            .iter()
+            .map(|name| Operation {
+                source: PathBuf::from(name),
+                target: PathBuf::from(format!("{}.renamed", name)),
+                tag: None,
+                link: None,
+            })
+            .collect();
+        write_to_file(&dump_file, &operations, Some("run")).unwrap();
+
+        let dump = read_dump(&dump_file).unwrap();
+        assert_eq!(dump.run_id.as_deref(), Some("run"));
+        let read_operations = dump.operations;
+        assert_eq!(read_operations.len(), operations.len());
+        for (read, original) in read_operations.iter().zip(operations.iter()) {
+            assert_eq!(read.source, original.source);
+            assert_eq!(read.target, original.target);
+        }
+    }
+
+    #[test]
+    fn dump_plan_hash() {
+        let tempdir = tempfile::tempdir().expect("Error creating temp directory");
+        let dump_file = tempdir.path().join("dump.json");
+        let stored_hash = || -> Option<String> {
+            let content = std::fs::read_to_string(&dump_file).unwrap();
+            let dump: DumpFormat = serde_json::from_str(&content).unwrap();
+            dump.plan_hash
+        };
+
+        let operations = vec![Operation {
+            source: PathBuf::from("a"),
+            target: PathBuf::from("b"),
+            tag: None,
+            link: None,
+        }];
+        write_to_file(&dump_file, &operations, Some("run")).unwrap();
+        assert_eq!(stored_hash(), Some(plan::hash(&operations)));
+
+        // Tagged operations are hashed again when the dump is written back
+        let mut dump = read_dump(&dump_file).unwrap();
+        dump.operations[0].tag = Some(Tag::Keep);
+        write_dump(&dump_file, &dump).unwrap();
+        assert_eq!(stored_hash(), Some(plan::hash(&dump.operations)));
+        assert_ne!(stored_hash(), Some(plan::hash(&operations)));
+    }
+
+    #[test]
+    fn dump_spec() {
+        assert_eq!(
+            spec(),
+            [
+                "date: string, required, local date formatted as %Y-%m-%d %H:%M:%S",
+                "operations: array of objects, required",
+                "operations[].link: string, optional",
+                "operations[].source: string, required",
+                "operations[].tag: string, optional, one of keep, later, never",
+                "operations[].target: string, required",
+                "plan_hash: string, optional",
+                "run_id: string, optional",
+            ]
+            .join("\n")
+        );
+    }
+}
@@ -14,30 +14,52 @@ pub struct Error {
 /// Define type of error
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub enum ErrorKind {
+    Aborted,
+    Canonicalize,
     CreateBackup,
     CreateFile,
     CreateSymlink,
+    EmptyName,
     ExistingPath,
+    InvalidTemplate,
     JsonParse,
+    Locked,
+    LogParse,
+    NoSpace,
     ReadFile,
+    RemoveFile,
     Rename,
     SameFilename,
+    SameStem,
     SolveOrder,
+    Timeout,
+    Unsafe,
 }
 
 impl Error {
     pub fn description(&self) -> &str {
         use self::ErrorKind::*;
         match self.kind {
+            Aborted => "Aborted, nothing was renamed",
+            Canonicalize => "Cannot resolve absolute path of ",
             CreateBackup => "Cannot create a backup of ",
             CreateFile => "Cannot create file ",
             CreateSymlink => "Cannot create symlink ",
+            EmptyName => "Replacement leaves an empty name for ",
             ExistingPath => "Conflict with existing path ",
+            InvalidTemplate => "Invalid replacement template\n",
             JsonParse => "Cannot parse JSON file ",
+            Locked => "Another rnr run is renaming in ",
+            LogParse => "Cannot parse rename log ",
+            NoSpace => "Not enough free space for backups\n",
             ReadFile => "Cannot open/read file ",
+            RemoveFile => "Cannot remove file ",
             Rename => "Cannot rename ",
             SameFilename => "Files will have the same name\n",
+            SameStem => "Files will have the same name without extension\n",
             SolveOrder => "Cannot solve sorting problem.",
+            Timeout => "Time budget exhausted, ",
+            Unsafe => "Refusing unsafe operation, ",
         }
     }
 }
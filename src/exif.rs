@@ -0,0 +1,174 @@
+use chrono::NaiveDateTime;
+
+/// Maximum number of bytes read from the beginning of a file looking for EXIF data. The APP1
+/// segment containing EXIF is limited to 64KiB and is usually placed at the beginning of the file.
+pub const EXIF_READ_LIMIT: u64 = 128 * 1024;
+
+// TIFF tags used to find the capture date.
+const TAG_DATE_TIME: u16 = 0x0132;
+const TAG_EXIF_IFD: u16 = 0x8769;
+const TAG_DATE_TIME_ORIGINAL: u16 = 0x9003;
+const TAG_DATE_TIME_DIGITIZED: u16 = 0x9004;
+
+/// Read EXIF capture date from the beginning of a JPEG or TIFF file. It looks for
+/// `DateTimeOriginal`, then `DateTimeDigitized` and finally `DateTime` tags. Returns `None` if the
+/// data does not contain any valid date.
+pub fn read_date(data: &[u8]) -> Option<NaiveDateTime> {
+    let tiff = find_tiff_data(data)?;
+    parse_date(tiff)
+}
+
+/// Return TIFF structure slice from a JPEG APP1 segment or the full buffer for TIFF files.
+fn find_tiff_data(data: &[u8]) -> Option<&[u8]> {
+    if data.starts_with(b"II*\0") || data.starts_with(b"MM\0*") {
+        return Some(data);
+    }
+    if !data.starts_with(&[0xFF, 0xD8]) {
+        return None;
+    }
+
+    // Walk JPEG markers until APP1 segment with EXIF header is found
+    let mut offset = 2;
+    while offset + 4 <= data.len() {
+        if data[offset] != 0xFF {
+            return None;
+        }
+        let marker = data[offset + 1];
+        // Start of scan or end of image, no more metadata segments
+        if marker == 0xDA || marker == 0xD9 {
+            return None;
+        }
+        let length = u16::from_be_bytes([data[offset + 2], data[offset + 3]]) as usize;
+        let segment = data.get(offset + 4..offset + 2 + length)?;
+        if marker == 0xE1 && segment.starts_with(b"Exif\0\0") {
+            return Some(&segment[6..]);
+        }
+        offset += 2 + length;
+    }
+    None
+}
+
+/// Parse TIFF structure and extract date.
+fn parse_date(tiff: &[u8]) -> Option<NaiveDateTime> {
+    let reader = TiffReader {
+        data: tiff,
+        little_endian: match tiff.get(0..2)? {
+            b"II" => true,
+            b"MM" => false,
+            _ => return None,
+        },
+    };
+
+    let ifd0 = reader.read_u32(4)? as usize;
+    let mut date = None;
+    if let Some(exif_ifd) = reader.find_tag(ifd0, TAG_EXIF_IFD) {
+        let exif_ifd = reader.read_u32(exif_ifd + 8)? as usize;
+        date = reader
+            .find_tag(exif_ifd, TAG_DATE_TIME_ORIGINAL)
+            .or_else(|| reader.find_tag(exif_ifd, TAG_DATE_TIME_DIGITIZED))
+            .and_then(|entry| reader.read_date(entry));
+    }
+    date.or_else(|| {
+        reader
+            .find_tag(ifd0, TAG_DATE_TIME)
+            .and_then(|entry| reader.read_date(entry))
+    })
+}
+
+struct TiffReader<'a> {
+    data: &'a [u8],
+    little_endian: bool,
+}
+
+impl TiffReader<'_> {
+    fn read_u16(&self, offset: usize) -> Option<u16> {
+        let bytes = [*self.data.get(offset)?, *self.data.get(offset + 1)?];
+        Some(if self.little_endian {
+            u16::from_le_bytes(bytes)
+        } else {
+            u16::from_be_bytes(bytes)
+        })
+    }
+
+    fn read_u32(&self, offset: usize) -> Option<u32> {
+        let bytes: [u8; 4] = self.data.get(offset..offset + 4)?.try_into().ok()?;
+        Some(if self.little_endian {
+            u32::from_le_bytes(bytes)
+        } else {
+            u32::from_be_bytes(bytes)
+        })
+    }
+
+    /// Return the offset of the IFD entry with the given tag.
+    fn find_tag(&self, ifd: usize, tag: u16) -> Option<usize> {
+        let count = self.read_u16(ifd)? as usize;
+        (0..count)
+            .map(|index| ifd + 2 + index * 12)
+            .find(|entry| self.read_u16(*entry) == Some(tag))
+    }
+
+    /// Read an ASCII date value with "YYYY:MM:DD HH:MM:SS" format from an IFD entry.
+    fn read_date(&self, entry: usize) -> Option<NaiveDateTime> {
+        let count = self.read_u32(entry + 4)? as usize;
+        let offset = if count <= 4 {
+            entry + 8
+        } else {
+            self.read_u32(entry + 8)? as usize
+        };
+        let value = self.data.get(offset..offset + count)?;
+        let value = std::str::from_utf8(value).ok()?.trim_end_matches('\0');
+        NaiveDateTime::parse_from_str(value.trim(), "%Y:%m:%d %H:%M:%S").ok()
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    extern crate tempfile;
+    use super::*;
+    use std::fs;
+
+    /// Generate a minimal JPEG file content with an EXIF DateTimeOriginal tag.
+    pub fn jpeg_with_date(date: &str) -> Vec<u8> {
+        // TIFF header (little endian) + IFD0 with a single ExifIFD pointer entry
+        let mut tiff: Vec<u8> = b"II*\0".to_vec();
+        tiff.extend_from_slice(&8u32.to_le_bytes());
+        tiff.extend_from_slice(&1u16.to_le_bytes());
+        tiff.extend_from_slice(&TAG_EXIF_IFD.to_le_bytes());
+        tiff.extend_from_slice(&4u16.to_le_bytes());
+        tiff.extend_from_slice(&1u32.to_le_bytes());
+        tiff.extend_from_slice(&26u32.to_le_bytes());
+        tiff.extend_from_slice(&0u32.to_le_bytes());
+        // Exif IFD at offset 26 with DateTimeOriginal pointing to offset 44
+        tiff.extend_from_slice(&1u16.to_le_bytes());
+        tiff.extend_from_slice(&TAG_DATE_TIME_ORIGINAL.to_le_bytes());
+        tiff.extend_from_slice(&2u16.to_le_bytes());
+        tiff.extend_from_slice(&20u32.to_le_bytes());
+        tiff.extend_from_slice(&44u32.to_le_bytes());
+        tiff.extend_from_slice(&0u32.to_le_bytes());
+        tiff.extend_from_slice(date.as_bytes());
+        tiff.push(0);
+
+        let mut jpeg = vec![0xFF, 0xD8, 0xFF, 0xE1];
+        jpeg.extend_from_slice(&((tiff.len() + 8) as u16).to_be_bytes());
+        jpeg.extend_from_slice(b"Exif\0\0");
+        jpeg.extend_from_slice(&tiff);
+        jpeg.extend_from_slice(&[0xFF, 0xD9]);
+        jpeg
+    }
+
+    #[test]
+    fn exif_date() {
+        let tempdir = tempfile::tempdir().expect("Error creating temp directory");
+        println!("Running test in '{:?}'", tempdir);
+        let photo = tempdir.path().join("photo.jpg");
+        let no_exif = tempdir.path().join("no_exif.jpg");
+        fs::write(&photo, jpeg_with_date("2021:06:15 10:30:00")).unwrap();
+        fs::write(&no_exif, [0xFF, 0xD8, 0xFF, 0xD9]).unwrap();
+
+        assert_eq!(
+            read_date(&fs::read(&photo).unwrap()).unwrap().to_string(),
+            "2021-06-15 10:30:00"
+        );
+        assert!(read_date(&fs::read(&no_exif).unwrap()).is_none());
+    }
+}
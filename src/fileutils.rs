@@ -1,32 +1,86 @@
+use crate::backups::BACKUP_SUFFIX;
 use crate::config::RunMode;
+use crate::driver::{EntryKind, FsDriver};
 use crate::error::*;
+use crate::metadata::MetadataCache;
 use path_abs::PathAbs;
 use std::collections::HashMap;
+use std::env;
 use std::fs;
+#[cfg(windows)]
+use std::path::Component;
 use std::path::Path;
 use std::path::PathBuf;
-use walkdir::{DirEntry, WalkDir};
+use std::sync::atomic::{AtomicBool, Ordering};
 
 pub type PathList = Vec<PathBuf>;
 
-/// Return a list of paths for the given run mode.
-pub fn get_paths(mode: &RunMode) -> PathList {
+/// Path standing for the standard input in arguments reading files
+pub const STDIN_PATH: &str = "-";
+
+/// Set once the standard input was read as a file, it cannot be used for prompts anymore
+static STDIN_CONSUMED: AtomicBool = AtomicBool::new(false);
+
+/// Read a whole file, or the standard input if the path is `-`.
+pub fn read_input(path: &Path) -> std::io::Result<String> {
+    if path == Path::new(STDIN_PATH) {
+        STDIN_CONSUMED.store(true, Ordering::Relaxed);
+        std::io::read_to_string(std::io::stdin())
+    } else {
+        fs::read_to_string(path)
+    }
+}
+
+/// Check if the standard input was read by `read_input`.
+pub fn is_stdin_consumed() -> bool {
+    STDIN_CONSUMED.load(Ordering::Relaxed)
+}
+
+/// Get the name of a file read by `read_input` for messages.
+pub fn input_name(path: &Path) -> String {
+    if path == Path::new(STDIN_PATH) {
+        "standard input".to_string()
+    } else {
+        path.display().to_string()
+    }
+}
+
+/// Read a list of paths from a file or the standard input, one per line. Empty lines are skipped.
+pub fn read_path_list(path: &Path) -> std::result::Result<Vec<String>, String> {
+    let content = read_input(path)
+        .map_err(|err| format!("Cannot read paths from {}\n{}", input_name(path), err))?;
+    Ok(content
+        .lines()
+        .map(|line| line.trim_end_matches('\r'))
+        .filter(|line| !line.is_empty())
+        .map(String::from)
+        .collect())
+}
+
+/// Return a list of paths for the given run mode. Kinds of the walked paths are kept in the cache.
+pub fn get_paths(mode: &RunMode, cache: &MetadataCache) -> PathList {
     match mode {
         RunMode::Recursive {
             paths,
             max_depth,
             hidden,
+            hidden_attr,
         } => {
             // Detect if is a hidden file or directory, always include given path
-            let should_filter = |f: &DirEntry| -> bool {
+            let should_filter = |path: &Path, depth: usize| -> bool {
                 // if do not allow hidden file match
                 // filter none valid utf-8 filename
                 // filter dot files (hidden files)
-                if !hidden && f.depth() > 0 {
-                    f.file_name()
-                        .to_str()
+                // filter files with hidden attribute if required
+                if !hidden && depth > 0 {
+                    path.file_name()
+                        .and_then(|name| name.to_str())
                         .map(|s| !s.starts_with('.'))
                         .unwrap_or(false)
+                        && !(*hidden_attr
+                            && cache
+                                .symlink_metadata(path)
+                                .is_some_and(|entry| entry.hidden))
                 } else {
                     true
                 }
@@ -34,36 +88,96 @@ pub fn get_paths(mode: &RunMode) -> PathList {
             // Get recursive list of paths walking directories
             let mut path_list = PathList::new();
             for path in paths {
-                let walkdir = match max_depth {
-                    Some(max_depth) => WalkDir::new(path).max_depth(*max_depth),
-                    None => WalkDir::new(path),
-                };
-                let mut walk_list: PathList = walkdir
-                    .into_iter()
-                    .filter_entry(should_filter)
-                    .filter_map(|e| e.ok())
-                    .map(|p| p.path().to_path_buf())
-                    .collect();
+                let path = normalize_path(Path::new(path));
+                let mut walk_list = cache.walk(&path, *max_depth, &should_filter);
                 path_list.append(&mut walk_list);
             }
 
             path_list
         }
-        RunMode::Simple(path_list) => path_list.iter().map(PathBuf::from).collect(),
+        RunMode::Simple(path_list) => path_list
+            .iter()
+            .map(|path| normalize_path(Path::new(path)))
+            .collect(),
+        RunMode::Contents(path_list) => {
+            let mut contents = PathList::new();
+            for path in path_list {
+                let path = normalize_path(Path::new(path));
+                if !cache.metadata(&path).is_some_and(|entry| entry.is_dir()) {
+                    contents.push(path);
+                    continue;
+                }
+                // Hidden children are skipped, like shell globs do
+                let is_visible = |child: &Path, depth: usize| {
+                    depth == 0
+                        || child
+                            .file_name()
+                            .and_then(|name| name.to_str())
+                            .is_some_and(|name| !name.starts_with('.'))
+                };
+                let children = cache.walk(&path, Some(1), &is_visible);
+                contents.extend(children.into_iter().filter(|child| *child != path));
+            }
+            contents
+        }
         // Return an empty PathList otherwise
         _ => PathList::new(),
     }
 }
 
+/// Normalize path separators to make paths consistent across platforms. It removes redundant and
+/// trailing separators and, on Windows, converts forward slashes to backslashes and resolves
+/// drive-relative paths like `C:foo`.
+pub fn normalize_path(path: &Path) -> PathBuf {
+    #[cfg(windows)]
+    {
+        // Drive-relative paths depend on the current directory of that drive
+        let mut components = path.components();
+        if let Some(Component::Prefix(_)) = components.next() {
+            if components.next() != Some(Component::RootDir) {
+                if let Ok(absolute) = std::path::absolute(path) {
+                    return absolute.components().collect();
+                }
+            }
+        }
+    }
+
+    path.components().collect()
+}
+
+/// Get the canonical absolute path of a path resolving symlinks and relative segments of its parent
+/// directory. The file name is kept as it is, so the path itself may be a symlink or not exist yet.
+pub fn canonicalize_parent(path: &Path) -> Result<PathBuf> {
+    let canonicalize_error = |err: std::io::Error| Error {
+        kind: ErrorKind::Canonicalize,
+        value: Some(format!("{}\n{}", path.display(), err)),
+    };
+
+    match (path.parent(), path.file_name()) {
+        (Some(parent), Some(name)) => {
+            let parent = if parent.as_os_str().is_empty() {
+                Path::new(".")
+            } else {
+                parent
+            };
+            Ok(fs::canonicalize(parent)
+                .map_err(canonicalize_error)?
+                .join(name))
+        }
+        // Paths without file name like `..` or `/`
+        _ => fs::canonicalize(path).map_err(canonicalize_error),
+    }
+}
+
 /// Generate a non-existing name adding numbers to the end of the file name. It also supports adding a
 /// suffix to the original name.
-pub fn get_unique_filename(path: &Path, suffix: &str) -> PathBuf {
+pub fn get_unique_filename(path: &Path, suffix: &str, driver: &dyn FsDriver) -> PathBuf {
     let base_name = format!("{}{}", path.file_name().unwrap().to_string_lossy(), suffix);
     let mut unique_name = path.to_path_buf();
     unique_name.set_file_name(&base_name);
 
     let mut index = 0;
-    while unique_name.symlink_metadata().is_ok() {
+    while driver.symlink_metadata(&unique_name).is_ok() {
         index += 1;
         unique_name.set_file_name(format!("{}.{}", base_name, index));
     }
@@ -71,10 +185,20 @@ pub fn get_unique_filename(path: &Path, suffix: &str) -> PathBuf {
     unique_name
 }
 
-/// Create a backup of the file
-pub fn create_backup(path: &Path) -> Result<PathBuf> {
-    let backup = get_unique_filename(path, ".bk");
-    match fs::copy(path, &backup) {
+/// Create a backup of the file. Symlinks are backed up as symlinks with the same content, so their
+/// targets are not copied and dangling links can be backed up too.
+pub fn create_backup(path: &Path, driver: &dyn FsDriver) -> Result<PathBuf> {
+    let backup = get_unique_filename(path, BACKUP_SUFFIX, driver);
+    if let Ok(link) = driver.read_link(path) {
+        return match driver.symlink(&link, &backup) {
+            Ok(_) => Ok(backup),
+            Err(_) => Err(Error {
+                kind: ErrorKind::CreateSymlink,
+                value: Some(backup.to_string_lossy().to_string()),
+            }),
+        };
+    }
+    match driver.copy(path, &backup) {
         Ok(_) => Ok(backup),
         Err(_) => Err(Error {
             kind: ErrorKind::CreateBackup,
@@ -85,14 +209,20 @@ pub fn create_backup(path: &Path) -> Result<PathBuf> {
 
 /// Clean paths that does not exists and duplicated entries. It remove directories too if dirs
 /// parameters is set to false.
-pub fn cleanup_paths(paths: &mut PathList, keep_dirs: bool) {
+pub fn cleanup_paths(paths: &mut PathList, keep_dirs: bool, cache: &MetadataCache) {
     paths.retain(|path| {
-        // Path must exists before performing other checks
-        if path.symlink_metadata().is_err() {
-            return false;
-        }
+        // Path must exists before performing other checks. Walked paths are known from their
+        // listing and only symlinks need a second query to know if they point to a directory,
+        // this matters in directories with many entries.
+        let is_dir = match cache.kind(path) {
+            Some(EntryKind::Symlink) => cache
+                .metadata(path)
+                .is_some_and(|metadata| metadata.is_dir()),
+            Some(kind) => kind == EntryKind::Dir,
+            None => return false,
+        };
 
-        if path.is_dir() {
+        if is_dir {
             keep_dirs && path.file_name().is_some()
         } else {
             true
@@ -100,16 +230,18 @@ pub fn cleanup_paths(paths: &mut PathList, keep_dirs: bool) {
     });
 
     // Deduplicate paths generating their absolute path and inserting them in a Hashmap. Replace
-    // the PathList original content with the deduplicated data.
+    // the PathList original content with the deduplicated data. Paths are joined to the current
+    // directory, read only once, to avoid querying it for each relative path.
+    let current_dir = env::current_dir().unwrap_or_default();
     let abs_path_map: HashMap<PathAbs, PathBuf> = paths
         .drain(..)
-        .map(|p| (PathAbs::new(&p).unwrap(), p))
+        .map(|p| (PathAbs::new(current_dir.join(&p)).unwrap(), p))
         .collect();
-    paths.append(&mut abs_path_map.values().cloned().collect());
+    paths.extend(abs_path_map.into_values());
 }
 
 /// Wrapper to create symlink files without considering the OS explicitly
-#[allow(dead_code)]
+#[cfg(test)]
 pub fn create_symlink(source: &Path, symlink_file: &Path) -> Result<()> {
     #[cfg(windows)]
     match ::std::os::windows::fs::symlink_file(source, symlink_file) {
@@ -154,8 +286,10 @@ pub fn is_same_file(source: &Path, target: &Path) -> bool {
 mod test {
     extern crate tempfile;
     use super::*;
+    use crate::driver::LocalDriver;
     use std::fs;
     use std::io::prelude::*;
+    use walkdir::WalkDir;
 
     #[test]
     fn backup() {
@@ -171,7 +305,7 @@ mod test {
 
         for file in &mock_files {
             fs::File::create(&file).expect("Error creating mock file...");
-            create_backup(file).expect("Error generating backup file...");
+            create_backup(file, &LocalDriver).expect("Error generating backup file...");
         }
 
         let backup_files: PathList = vec![
@@ -186,6 +320,55 @@ mod test {
         }
     }
 
+    #[test]
+    fn normalize() {
+        assert_eq!(
+            normalize_path(Path::new("a//b/./c/")),
+            PathBuf::from("a/b/c")
+        );
+        assert_eq!(normalize_path(Path::new("./a/")), PathBuf::from("./a"));
+        assert_eq!(normalize_path(Path::new("/a/b")), PathBuf::from("/a/b"));
+
+        #[cfg(windows)]
+        {
+            assert_eq!(
+                normalize_path(Path::new("C:/foo/bar/")),
+                PathBuf::from(r"C:\foo\bar")
+            );
+            assert!(normalize_path(Path::new("C:foo")).is_absolute());
+        }
+    }
+
+    #[test]
+    fn canonicalize() {
+        let tempdir = tempfile::tempdir().expect("Error creating temp directory");
+        println!("Running test in '{:?}'", tempdir);
+        let temp_path = fs::canonicalize(tempdir.path()).unwrap();
+
+        let mock_dir = temp_path.join("mock_dir");
+        fs::create_dir(&mock_dir).expect("Error creating mock directory...");
+        let file = mock_dir.join("test_file");
+        fs::File::create(&file).expect("Error creating mock file...");
+        let dir_link = temp_path.join("dir_link");
+        create_symlink(&mock_dir, &dir_link).expect("Error creating symlink.");
+        let file_link = mock_dir.join("file_link");
+        create_symlink(&file, &file_link).expect("Error creating symlink.");
+
+        // Symlinks and relative segments in parent directories are resolved
+        assert_eq!(
+            canonicalize_parent(&dir_link.join("..").join("mock_dir").join("new_file")).unwrap(),
+            mock_dir.join("new_file")
+        );
+        #[cfg(unix)]
+        assert_eq!(
+            canonicalize_parent(&dir_link.join("test_file")).unwrap(),
+            file
+        );
+        // File names are not resolved
+        assert_eq!(canonicalize_parent(&file_link).unwrap(), file_link);
+        assert!(canonicalize_parent(&temp_path.join("missing").join("file")).is_err());
+    }
+
     #[test]
     fn unique_name() {
         let tempdir = tempfile::tempdir().expect("Error creating temp directory");
@@ -210,7 +393,10 @@ mod test {
             .expect("Error creating broken symlink.");
 
         let new_file: PathBuf = [temp_path, "test_file_1.5"].iter().collect();
-        assert_eq!(get_unique_filename(&mock_files[0], ""), new_file);
+        assert_eq!(
+            get_unique_filename(&mock_files[0], "", &LocalDriver),
+            new_file
+        );
     }
 
     #[test]
@@ -222,12 +408,41 @@ mod test {
         ];
 
         let mode = RunMode::Simple(mock_files);
-        let files = get_paths(&mode);
+        let files = get_paths(&mode, &MetadataCache::new());
         assert!(files.contains(&PathBuf::from("test_file_1.txt")));
         assert!(files.contains(&PathBuf::from("test_file_2.txt")));
         assert!(files.contains(&PathBuf::from("test_file_3.txt")));
     }
 
+    #[test]
+    fn get_dir_contents() {
+        let tempdir = tempfile::tempdir().expect("Error creating temp directory");
+        println!("Running test in '{:?}'", tempdir);
+        let temp_path = tempdir.path().to_str().unwrap().to_string();
+        let path = |name: &str| -> PathBuf { [&temp_path, name].iter().collect() };
+        fs::create_dir_all(path("photos/album")).expect("Error creating mock directory...");
+        for name in [
+            "photos/a.jpg",
+            "photos/.hidden.jpg",
+            "photos/album/b.jpg",
+            "c.jpg",
+        ] {
+            fs::File::create(path(name)).expect("Error creating mock file...");
+        }
+
+        // Directories are replaced by their children, other paths are kept
+        let mode = RunMode::Contents(vec![
+            path("photos").to_string_lossy().to_string(),
+            path("c.jpg").to_string_lossy().to_string(),
+        ]);
+        let mut files = get_paths(&mode, &MetadataCache::new());
+        files.sort();
+        assert_eq!(
+            files,
+            vec![path("c.jpg"), path("photos/a.jpg"), path("photos/album")]
+        );
+    }
+
     #[test]
     fn test_create_symlinks() {
         let tempdir = tempfile::tempdir().expect("Error creating temp directory");
@@ -361,8 +576,9 @@ mod test {
             paths: vec![temp_path.clone()],
             max_depth: None,
             hidden: false,
+            hidden_attr: false,
         };
-        let files = get_paths(&mode);
+        let files = get_paths(&mode, &MetadataCache::new());
         // Must contain these files
         #[rustfmt::skip]
         let listed_files: PathList = vec![
@@ -395,8 +611,9 @@ mod test {
             paths: vec![temp_path.clone()],
             max_depth: Some(2),
             hidden: false,
+            hidden_attr: false,
         };
-        let files = get_paths(&mode);
+        let files = get_paths(&mode, &MetadataCache::new());
         // Must contain these files
         let listed_files: PathList = vec![
             [&temp_path, "test_file.txt"].iter().collect(),
@@ -428,8 +645,9 @@ mod test {
             paths: vec![temp_path.clone()],
             max_depth: None,
             hidden: true,
+            hidden_attr: false,
         };
-        let files = get_paths(&mode);
+        let files = get_paths(&mode, &MetadataCache::new());
         // Must contain these files
         #[rustfmt::skip]
         let listed_files: PathList = vec![
@@ -446,6 +664,52 @@ mod test {
         }
     }
 
+    #[test]
+    #[cfg(windows)]
+    fn get_paths_recursive_hidden_attr() {
+        use std::os::windows::fs::OpenOptionsExt;
+        const FILE_ATTRIBUTE_HIDDEN: u32 = 0x2;
+
+        let (_tempdir, temp_path) = generate_recursive_tempdir();
+        let hidden_file: PathBuf = [&temp_path, "attr_hidden_file.txt"].iter().collect();
+        fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .attributes(FILE_ATTRIBUTE_HIDDEN)
+            .open(&hidden_file)
+            .expect("Error creating mock file...");
+
+        let mode = |hidden_attr| RunMode::Recursive {
+            paths: vec![temp_path.clone()],
+            max_depth: None,
+            hidden: false,
+            hidden_attr,
+        };
+        assert!(get_paths(&mode(false), &MetadataCache::new()).contains(&hidden_file));
+        let files = get_paths(&mode(true), &MetadataCache::new());
+        assert!(!files.contains(&hidden_file));
+        assert!(files.contains(&[&temp_path, "test_file.txt"].iter().collect::<PathBuf>()));
+    }
+
+    #[test]
+    fn cleanup_walked_paths() {
+        let (_tempdir, temp_path) = generate_recursive_tempdir();
+        let mode = RunMode::Recursive {
+            paths: vec![temp_path.clone()],
+            max_depth: None,
+            hidden: false,
+            hidden_attr: false,
+        };
+        let cache = MetadataCache::new();
+        let mut files = get_paths(&mode, &cache);
+        cleanup_paths(&mut files, false, &cache);
+        assert!(files.contains(&[&temp_path, "test_file.txt"].iter().collect::<PathBuf>()));
+        assert!(!files.contains(&[&temp_path, "mock_dir_1"].iter().collect::<PathBuf>()));
+
+        // Kinds of walked paths come from the directory listings, not from their metadata
+        assert_eq!(cache.stats().misses, 0);
+    }
+
     #[test]
     fn cleanup() {
         let tempdir = tempfile::tempdir().expect("Error creating temp directory");
@@ -516,7 +780,7 @@ mod test {
         mock_paths.append(&mut mock_files.clone());
         mock_paths.append(&mut mock_files.clone());
 
-        cleanup_paths(&mut mock_paths, false);
+        cleanup_paths(&mut mock_paths, false, &MetadataCache::new());
 
         // Must contain these the files
         let mut listed_files = PathList::new();
@@ -553,4 +817,18 @@ mod test {
         path_list.append(&mut walk_list);
         println!("{:?}", path_list);
     }
+
+    #[test]
+    fn path_list() {
+        let tempdir = tempfile::tempdir().expect("Error creating temp directory");
+        println!("Running test in '{:?}'", tempdir);
+        let list = tempdir.path().join("paths.txt");
+        fs::write(&list, "a.txt\r\n\ndir/b c.txt\n").unwrap();
+        assert_eq!(
+            read_path_list(&list).unwrap(),
+            vec!["a.txt".to_string(), "dir/b c.txt".to_string()]
+        );
+        assert!(read_path_list(&tempdir.path().join("missing")).is_err());
+        assert_eq!(input_name(Path::new("-")), "standard input");
+    }
 }
@@ -0,0 +1,263 @@
+use crate::metadata::MetadataCache;
+use chrono::{DateTime, Local, NaiveDateTime};
+use regex::Regex;
+use std::path::Path;
+
+/// Timestamp used to compare files in time filters
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TimeSource {
+    /// File modification time
+    Mtime,
+    /// EXIF capture date
+    Exif,
+}
+
+impl TimeSource {
+    pub fn from_str(name: &str) -> Result<TimeSource, String> {
+        match name {
+            "mtime" => Ok(TimeSource::Mtime),
+            "exif" => Ok(TimeSource::Exif),
+            _ => Err(format!("Unknown time source '{}', use mtime or exif", name)),
+        }
+    }
+
+    /// Get the timestamp of a path. Returns `None` if it is not available.
+    pub fn timestamp(&self, path: &Path, cache: &MetadataCache) -> Option<NaiveDateTime> {
+        match self {
+            TimeSource::Mtime => {
+                let modified = cache.metadata(path)?.modified?;
+                Some(DateTime::<Local>::from(modified).naive_local())
+            }
+            TimeSource::Exif => cache.exif_date(path),
+        }
+    }
+}
+
+/// Select paths by their timestamp compared to reference dates. Paths without a timestamp are never
+/// selected.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TimeFilter {
+    pub source: TimeSource,
+    /// Only select paths strictly older than this date
+    pub older: Option<NaiveDateTime>,
+    /// Only select paths strictly newer than this date
+    pub newer: Option<NaiveDateTime>,
+}
+
+impl TimeFilter {
+    /// Create a filter comparing against the timestamps of reference files.
+    pub fn from_references(
+        source: TimeSource,
+        older: Option<&Path>,
+        newer: Option<&Path>,
+    ) -> Result<TimeFilter, String> {
+        let cache = MetadataCache::new();
+        let reference_time = |reference: Option<&Path>| match reference {
+            Some(reference) => match source.timestamp(reference, &cache) {
+                Some(time) => Ok(Some(time)),
+                None => Err(format!(
+                    "Cannot read {} of reference file {}",
+                    match source {
+                        TimeSource::Mtime => "modification time",
+                        TimeSource::Exif => "EXIF date",
+                    },
+                    reference.display()
+                )),
+            },
+            None => Ok(None),
+        };
+
+        Ok(TimeFilter {
+            source,
+            older: reference_time(older)?,
+            newer: reference_time(newer)?,
+        })
+    }
+
+    /// Check if the path is selected by the filter.
+    pub fn matches(&self, path: &Path, cache: &MetadataCache) -> bool {
+        let time = match self.source.timestamp(path, cache) {
+            Some(time) => time,
+            None => return false,
+        };
+        self.older.is_none_or(|older| time < older) && self.newer.is_none_or(|newer| time > newer)
+    }
+}
+
+/// Capture group referenced by index or name
+#[derive(Clone, Debug, PartialEq)]
+pub enum CaptureGroup {
+    Index(usize),
+    Name(String),
+}
+
+/// Select paths whose capture group is a number within an inclusive range. Paths not matching the
+/// expression or with a non-numeric capture are never selected.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CaptureRange {
+    pub group: CaptureGroup,
+    pub min: Option<u64>,
+    pub max: Option<u64>,
+}
+
+impl CaptureRange {
+    /// Parse a range like `1:100-299` or `episode:100-`. Any bound can be omitted.
+    pub fn parse(value: &str) -> Result<CaptureRange, String> {
+        let invalid = || format!("Invalid capture range '{}', use GROUP:MIN-MAX", value);
+        let (group, range) = value.split_once(':').ok_or_else(invalid)?;
+        let (min, max) = range.split_once('-').ok_or_else(invalid)?;
+        let bound = |bound: &str| match bound.trim() {
+            "" => Ok(None),
+            bound => bound.parse::<u64>().map(Some).map_err(|_| invalid()),
+        };
+
+        let group = match group.trim() {
+            "" => return Err(invalid()),
+            group => match group.parse::<usize>() {
+                Ok(index) => CaptureGroup::Index(index),
+                Err(_) => CaptureGroup::Name(group.to_string()),
+            },
+        };
+        let (min, max) = (bound(min)?, bound(max)?);
+        if min.zip(max).is_some_and(|(min, max)| min > max) {
+            return Err(invalid());
+        }
+        Ok(CaptureRange { group, min, max })
+    }
+
+    /// Check that the capture group exists in the expression.
+    pub fn validate(&self, expression: &Regex) -> Result<(), String> {
+        let exists = match &self.group {
+            CaptureGroup::Index(index) => *index < expression.captures_len(),
+            CaptureGroup::Name(name) => expression.capture_names().flatten().any(|n| n == name),
+        };
+        if exists {
+            Ok(())
+        } else {
+            Err(format!(
+                "Capture group '{}' not found in the expression",
+                match &self.group {
+                    CaptureGroup::Index(index) => index.to_string(),
+                    CaptureGroup::Name(name) => name.clone(),
+                }
+            ))
+        }
+    }
+
+    /// Check if the first match of the expression in the file name is selected by the range.
+    pub fn matches(&self, file_name: &str, expression: &Regex) -> bool {
+        let captures = match expression.captures(file_name) {
+            Some(captures) => captures,
+            None => return false,
+        };
+        let capture = match &self.group {
+            CaptureGroup::Index(index) => captures.get(*index),
+            CaptureGroup::Name(name) => captures.name(name),
+        };
+        match capture.and_then(|capture| capture.as_str().parse::<u64>().ok()) {
+            Some(number) => {
+                self.min.is_none_or(|min| number >= min) && self.max.is_none_or(|max| number <= max)
+            }
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    extern crate tempfile;
+    use super::*;
+    use crate::exif::test::jpeg_with_date;
+    use std::fs;
+
+    #[test]
+    fn exif_time_filter() {
+        let tempdir = tempfile::tempdir().expect("Error creating temp directory");
+        println!("Running test in '{:?}'", tempdir);
+        let path = |name: &str| tempdir.path().join(name);
+
+        fs::write(path("reference.jpg"), jpeg_with_date("2021:06:15 10:30:00")).unwrap();
+        fs::write(path("before.jpg"), jpeg_with_date("2021:06:14 23:59:59")).unwrap();
+        fs::write(path("after.jpg"), jpeg_with_date("2021:06:15 10:30:01")).unwrap();
+        fs::write(path("no_exif.jpg"), "").unwrap();
+
+        let cache = MetadataCache::new();
+        let older =
+            TimeFilter::from_references(TimeSource::Exif, Some(&path("reference.jpg")), None)
+                .unwrap();
+        assert!(older.matches(&path("before.jpg"), &cache));
+        assert!(!older.matches(&path("reference.jpg"), &cache));
+        assert!(!older.matches(&path("after.jpg"), &cache));
+        assert!(!older.matches(&path("no_exif.jpg"), &cache));
+
+        let newer =
+            TimeFilter::from_references(TimeSource::Exif, None, Some(&path("reference.jpg")))
+                .unwrap();
+        assert!(!newer.matches(&path("before.jpg"), &cache));
+        assert!(newer.matches(&path("after.jpg"), &cache));
+
+        // Reference files must have the timestamp
+        assert!(
+            TimeFilter::from_references(TimeSource::Exif, Some(&path("no_exif.jpg")), None)
+                .is_err()
+        );
+        assert!(
+            TimeFilter::from_references(TimeSource::Mtime, Some(&path("missing")), None).is_err()
+        );
+    }
+
+    #[test]
+    fn mtime_time_filter() {
+        let tempdir = tempfile::tempdir().expect("Error creating temp directory");
+        println!("Running test in '{:?}'", tempdir);
+        let old = tempdir.path().join("old.txt");
+        let new = tempdir.path().join("new.txt");
+        fs::write(&old, "").unwrap();
+        fs::write(&new, "").unwrap();
+        let old_time = std::time::SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1 << 30);
+        fs::File::options()
+            .write(true)
+            .open(&old)
+            .unwrap()
+            .set_modified(old_time)
+            .unwrap();
+
+        let cache = MetadataCache::new();
+        let filter = TimeFilter::from_references(TimeSource::Mtime, Some(&new), None).unwrap();
+        assert!(filter.matches(&old, &cache));
+        assert!(!filter.matches(&new, &cache));
+    }
+
+    #[test]
+    fn capture_range() {
+        let range = CaptureRange::parse("1:100-299").unwrap();
+        assert_eq!(range.group, CaptureGroup::Index(1));
+        assert_eq!((range.min, range.max), (Some(100), Some(299)));
+        assert_eq!(CaptureRange::parse("ep:-9").unwrap().max, Some(9));
+        assert!(CaptureRange::parse("1:100").is_err());
+        assert!(CaptureRange::parse(":1-2").is_err());
+        assert!(CaptureRange::parse("1:a-2").is_err());
+        assert!(CaptureRange::parse("1:3-2").is_err());
+
+        let expression = Regex::new(r"e(?P<ep>\d+)").unwrap();
+        assert!(range.validate(&expression).is_ok());
+        assert!(CaptureRange::parse("ep:1-2")
+            .unwrap()
+            .validate(&expression)
+            .is_ok());
+        assert!(CaptureRange::parse("2:1-2")
+            .unwrap()
+            .validate(&expression)
+            .is_err());
+        assert!(CaptureRange::parse("x:1-2")
+            .unwrap()
+            .validate(&expression)
+            .is_err());
+
+        assert!(range.matches("show-e100.mkv", &expression));
+        assert!(range.matches("show-e0299.mkv", &expression));
+        assert!(!range.matches("show-e99.mkv", &expression));
+        assert!(!range.matches("show-e300.mkv", &expression));
+        assert!(!range.matches("show.mkv", &expression));
+    }
+}
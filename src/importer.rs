@@ -0,0 +1,133 @@
+use crate::dumpfile::{self, Dump};
+use crate::error::*;
+use crate::fileutils::normalize_path;
+use crate::solver::{Operation, Operations};
+use chrono::{DateTime, Local};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Format of the files read by `from-file`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    /// Dump files written by rnr
+    Dump,
+    /// Verbose output of mmv, with `SOURCE -> TARGET` lines
+    Mmv,
+    /// Verbose output of the Perl `rename` (`SOURCE renamed as TARGET`) and the util-linux one
+    /// (`` `SOURCE' -> `TARGET' ``)
+    RenameLog,
+}
+
+impl Format {
+    pub fn from_str(format: &str) -> std::result::Result<Format, String> {
+        match format {
+            "dump" => Ok(Format::Dump),
+            "mmv" => Ok(Format::Mmv),
+            "rename-log" => Ok(Format::RenameLog),
+            _ => Err(format!(
+                "Unknown format '{}', use dump, mmv or rename-log",
+                format
+            )),
+        }
+    }
+}
+
+/// Read the operations of a file in the given format. Logs of other tools have no date, the
+/// modification time of the log is used instead.
+pub fn read(path: &Path, format: Format) -> Result<Dump> {
+    if format == Format::Dump {
+        return dumpfile::read_dump(path);
+    }
+    let filename = path.to_string_lossy().to_string();
+    let content = fs::read_to_string(path).map_err(|_| Error {
+        kind: ErrorKind::ReadFile,
+        value: Some(filename.clone()),
+    })?;
+    let operations = parse(&content, format).map_err(|err| Error {
+        kind: ErrorKind::LogParse,
+        value: Some(format!("{}\n{}", filename, err)),
+    })?;
+    let date = fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+        .map(|modified| DateTime::<Local>::from(modified).naive_local());
+    Ok(Dump {
+        date,
+        run_id: None,
+        operations,
+    })
+}
+
+/// Parse the operations of a log written by another tool. Empty lines are skipped.
+pub fn parse(content: &str, format: Format) -> std::result::Result<Operations, String> {
+    let mut operations = Operations::new();
+    for (index, line) in content.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let paths = match format {
+            Format::Dump => None,
+            // Applied renames are followed by their status
+            Format::Mmv => split_once(line.strip_suffix(" : done").unwrap_or(line), " -> "),
+            Format::RenameLog => match line
+                .strip_prefix('`')
+                .and_then(|line| line.strip_suffix('\''))
+            {
+                Some(quoted) => split_once(quoted, "' -> `"),
+                None => split_once(line, " renamed as "),
+            },
+        };
+        let (source, target) = paths
+            .ok_or_else(|| format!("Line {}: expected a rename, found '{}'", index + 1, line))?;
+        operations.push(Operation {
+            source: normalize_path(&PathBuf::from(source)),
+            target: normalize_path(&PathBuf::from(target)),
+            tag: None,
+            link: None,
+        });
+    }
+    Ok(operations)
+}
+
+/// Split a line at a separator found exactly once, other lines are ambiguous.
+fn split_once<'a>(line: &'a str, separator: &str) -> Option<(&'a str, &'a str)> {
+    match line.matches(separator).count() {
+        1 => line.split_once(separator),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_logs() {
+        let paths = |operations: Operations| -> Vec<(PathBuf, PathBuf)> {
+            operations
+                .into_iter()
+                .map(|operation| (operation.source, operation.target))
+                .collect()
+        };
+        let expected = vec![
+            (PathBuf::from("a.txt"), PathBuf::from("b.txt")),
+            (PathBuf::from("dir/c d.txt"), PathBuf::from("dir/e.txt")),
+        ];
+
+        let mmv = "a.txt -> b.txt : done\n\ndir/c d.txt -> dir/e.txt\n";
+        assert_eq!(paths(parse(mmv, Format::Mmv).unwrap()), expected);
+
+        let perl = "a.txt renamed as b.txt\ndir/c d.txt renamed as dir/e.txt\n";
+        assert_eq!(paths(parse(perl, Format::RenameLog).unwrap()), expected);
+        let util_linux = "`a.txt' -> `b.txt'\n`dir/c d.txt' -> `dir/e.txt'\n";
+        assert_eq!(
+            paths(parse(util_linux, Format::RenameLog).unwrap()),
+            expected
+        );
+
+        // Lines that are not renames, or where the separator is ambiguous, are errors
+        let err = parse("a.txt -> b.txt\nmmv: nothing done\n", Format::Mmv).unwrap_err();
+        assert!(err.starts_with("Line 2:"));
+        assert!(parse("a -> b -> c\n", Format::Mmv).is_err());
+    }
+}
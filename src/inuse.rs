@@ -0,0 +1,151 @@
+use std::collections::HashSet;
+use std::path::PathBuf;
+
+/// Get the paths of the list currently opened by any process. Returns an error if it cannot be
+/// detected in this platform.
+///
+/// * Linux: open file descriptors and memory mapped files of all visible processes are read from
+///   `/proc`. Processes of other users are only visible with enough privileges.
+/// * Windows: files are opened without sharing, this fails if any other process has them open.
+pub fn in_use_paths(paths: &[PathBuf]) -> Result<HashSet<PathBuf>, String> {
+    #[cfg(target_os = "linux")]
+    {
+        use std::os::unix::fs::MetadataExt;
+
+        let open_files = linux::open_files();
+        Ok(paths
+            .iter()
+            .filter(|path| {
+                path.symlink_metadata()
+                    .map(|metadata| open_files.contains(&(metadata.dev(), metadata.ino())))
+                    .unwrap_or(false)
+            })
+            .cloned()
+            .collect())
+    }
+
+    #[cfg(windows)]
+    {
+        use std::fs::OpenOptions;
+        use std::os::windows::fs::OpenOptionsExt;
+        const ERROR_SHARING_VIOLATION: i32 = 32;
+
+        Ok(paths
+            .iter()
+            .filter(|path| !path.is_dir())
+            .filter(
+                |path| match OpenOptions::new().read(true).share_mode(0).open(path) {
+                    Err(err) => err.raw_os_error() == Some(ERROR_SHARING_VIOLATION),
+                    Ok(_) => false,
+                },
+            )
+            .cloned()
+            .collect())
+    }
+
+    #[cfg(not(any(target_os = "linux", windows)))]
+    {
+        let _ = paths;
+        Err("detecting files in use is not supported in this platform".to_string())
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use std::collections::HashSet;
+    use std::fs;
+    use std::os::unix::fs::MetadataExt;
+
+    /// Device and inode of a file
+    pub type FileId = (u64, u64);
+
+    /// Get the files opened or memory mapped by all visible processes.
+    pub fn open_files() -> HashSet<FileId> {
+        let mut open_files = HashSet::new();
+        let processes = match fs::read_dir("/proc") {
+            Ok(processes) => processes,
+            Err(_) => return open_files,
+        };
+
+        for process in processes.flatten() {
+            let is_pid = process
+                .file_name()
+                .to_str()
+                .is_some_and(|name| name.bytes().all(|b| b.is_ascii_digit()));
+            if !is_pid {
+                continue;
+            }
+
+            // Descriptors of processes from other users are not readable without privileges
+            if let Ok(descriptors) = fs::read_dir(process.path().join("fd")) {
+                for descriptor in descriptors.flatten() {
+                    if let Ok(metadata) = fs::metadata(descriptor.path()) {
+                        open_files.insert((metadata.dev(), metadata.ino()));
+                    }
+                }
+            }
+            if let Ok(maps) = fs::read_to_string(process.path().join("maps")) {
+                open_files.extend(maps.lines().filter_map(parse_map_line));
+            }
+        }
+
+        open_files
+    }
+
+    /// Parse a `/proc/<pid>/maps` line: `address perms offset major:minor inode path`. Anonymous
+    /// mappings have inode 0.
+    fn parse_map_line(line: &str) -> Option<FileId> {
+        let mut fields = line.split_whitespace().skip(3);
+        let (major, minor) = fields.next()?.split_once(':')?;
+        let inode = fields.next()?.parse::<u64>().ok()?;
+        if inode == 0 {
+            return None;
+        }
+        let major = u32::from_str_radix(major, 16).ok()?;
+        let minor = u32::from_str_radix(minor, 16).ok()?;
+        Some((libc::makedev(major, minor), inode))
+    }
+
+    #[cfg(test)]
+    mod test {
+        use super::*;
+
+        #[test]
+        fn parse_maps() {
+            assert_eq!(
+                parse_map_line(
+                    "7f1c2a000000-7f1c2a022000 r--p 00000000 fd:01 1234 /usr/lib/libc.so.6"
+                ),
+                Some((libc::makedev(0xfd, 0x01), 1234))
+            );
+            assert_eq!(
+                parse_map_line("7ffd4b1e0000-7ffd4b201000 rw-p 00000000 00:00 0 [stack]"),
+                None
+            );
+            assert_eq!(parse_map_line("garbage"), None);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    extern crate tempfile;
+    use super::*;
+    use std::fs;
+
+    #[test]
+    #[cfg(any(target_os = "linux", windows))]
+    fn detect_in_use() {
+        let tempdir = tempfile::tempdir().expect("Error creating temp directory");
+        println!("Running test in '{:?}'", tempdir);
+        let open = tempdir.path().join("open_file");
+        let closed = tempdir.path().join("closed_file");
+        fs::File::create(&closed).expect("Error creating mock file...");
+
+        // Keep the file open while checking
+        let _file = fs::File::create(&open).expect("Error creating mock file...");
+        let in_use = in_use_paths(&[open.clone(), closed.clone()]).unwrap();
+        assert!(in_use.contains(&open));
+        assert!(!in_use.contains(&closed));
+    }
+}
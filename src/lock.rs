@@ -0,0 +1,214 @@
+use crate::error::*;
+use crate::output::Printer;
+use crate::warning::Warning;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+
+/// Advisory locks held by a run while it plans and renames, released when dropped.
+///
+/// Directories containing the renamed paths and their targets are locked exclusively and their
+/// ancestors are locked shared, so runs over the same directory or over nested directories exclude
+/// each other while runs over unrelated directories go on in parallel. Other programs are not
+/// affected.
+pub struct SessionLock {
+    /// Locked directories, with the open file holding the lock
+    held: BTreeMap<PathBuf, (File, Kind)>,
+    paths: Vec<PathBuf>,
+}
+
+/// Lock requested on a directory
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Kind {
+    Shared,
+    Exclusive,
+}
+
+impl SessionLock {
+    /// Lock the directories containing the paths. Fails if another run holds a conflicting lock,
+    /// unless `wait` is set, then a warning is printed and the lock is awaited.
+    pub fn acquire(paths: &[PathBuf], wait: bool, printer: &Printer) -> Result<SessionLock> {
+        let mut lock = SessionLock {
+            held: BTreeMap::new(),
+            paths: Vec::new(),
+        };
+        lock.extend(paths, wait, printer)?;
+        Ok(lock)
+    }
+
+    /// Lock the directories containing more paths, like the targets of the planned operations,
+    /// keeping the locks already held. Shared locks are upgraded to exclusive ones when needed.
+    pub fn extend(&mut self, paths: &[PathBuf], wait: bool, printer: &Printer) -> Result<()> {
+        self.paths.extend_from_slice(paths);
+        // Directories are locked in path order, ancestors first, so waiting runs never deadlock
+        for (dir, kind) in lock_plan(&self.paths) {
+            let file = match self.held.remove(&dir) {
+                Some((file, Kind::Exclusive)) => {
+                    self.held.insert(dir, (file, Kind::Exclusive));
+                    continue;
+                }
+                Some((file, held)) if held == kind => {
+                    self.held.insert(dir, (file, held));
+                    continue;
+                }
+                Some((file, _)) => file,
+                None => match File::open(&dir) {
+                    Ok(file) => file,
+                    // Directories that cannot be read are reported by the renaming itself
+                    Err(_) => continue,
+                },
+            };
+            if !try_lock(&file, kind, false) {
+                if !wait {
+                    return Err(Error {
+                        kind: ErrorKind::Locked,
+                        value: Some(format!(
+                            "'{}', use --wait-lock to wait for it",
+                            dir.display()
+                        )),
+                    });
+                }
+                printer.print_warning(
+                    Warning::LockWait,
+                    &format!(
+                        "Waiting for another rnr run renaming in '{}'",
+                        dir.display()
+                    ),
+                );
+                try_lock(&file, kind, true);
+            }
+            self.held.insert(dir, (file, kind));
+        }
+        Ok(())
+    }
+}
+
+/// Get the directories to lock for the paths. The topmost parent directories are locked
+/// exclusively, their descendants are covered by them, and all their ancestors are locked shared.
+/// Parents not created yet, like the directories of `--move-to`, are covered by their closest
+/// existing ancestor.
+fn lock_plan(paths: &[PathBuf]) -> BTreeMap<PathBuf, Kind> {
+    let mut parents: Vec<PathBuf> = paths
+        .iter()
+        .filter_map(|path| {
+            let parent = match path.parent() {
+                Some(parent) if !parent.as_os_str().is_empty() => parent,
+                _ => Path::new("."),
+            };
+            parent
+                .canonicalize()
+                .ok()
+                .or_else(|| existing_ancestor(parent))
+        })
+        .collect();
+    parents.sort();
+    parents.dedup();
+
+    let mut plan = BTreeMap::new();
+    for parent in &parents {
+        if parent.ancestors().skip(1).any(|dir| plan.contains_key(dir)) {
+            continue;
+        }
+        plan.insert(parent.clone(), Kind::Exclusive);
+    }
+    let exclusive: Vec<PathBuf> = plan.keys().cloned().collect();
+    for dir in &exclusive {
+        for ancestor in dir.ancestors().skip(1) {
+            plan.entry(ancestor.to_path_buf()).or_insert(Kind::Shared);
+        }
+    }
+    plan
+}
+
+/// Get the closest existing ancestor of a missing directory. The root of the file system is not
+/// used, it would exclude every other run.
+fn existing_ancestor(dir: &Path) -> Option<PathBuf> {
+    let dir = std::path::absolute(dir).ok()?;
+    dir.ancestors()
+        .skip(1)
+        .take_while(|ancestor| ancestor.parent().is_some())
+        .find_map(|ancestor| ancestor.canonicalize().ok())
+}
+
+/// Lock an open directory, blocking until it is available if `wait` is set. Returns false if it is
+/// locked by another run. Platforms without `flock` are never locked.
+#[cfg(unix)]
+fn try_lock(file: &File, kind: Kind, wait: bool) -> bool {
+    use std::os::unix::io::AsRawFd;
+
+    let mut operation = match kind {
+        Kind::Shared => libc::LOCK_SH,
+        Kind::Exclusive => libc::LOCK_EX,
+    };
+    if !wait {
+        operation |= libc::LOCK_NB;
+    }
+    loop {
+        // SAFETY: the descriptor is owned by the file, which outlives the call
+        if unsafe { libc::flock(file.as_raw_fd(), operation) } == 0 {
+            return true;
+        }
+        let err = std::io::Error::last_os_error();
+        if err.kind() != std::io::ErrorKind::Interrupted {
+            // Only conflicting locks stop the run, file systems without locks are not locked
+            return err.raw_os_error() != Some(libc::EWOULDBLOCK);
+        }
+    }
+}
+
+#[cfg(not(unix))]
+fn try_lock(_file: &File, _kind: Kind, _wait: bool) -> bool {
+    true
+}
+
+#[cfg(test)]
+#[cfg(unix)]
+mod test {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn session_lock() {
+        let tempdir = tempfile::tempdir().expect("Error creating temp directory");
+        println!("Running test in '{:?}'", tempdir);
+        let root = tempdir.path().canonicalize().unwrap();
+        for dir in ["a/b", "c"] {
+            fs::create_dir_all(root.join(dir)).unwrap();
+        }
+        let file = |path: &str| root.join(path).join("file");
+        let printer = Printer::silent();
+
+        // Nested directories are covered by the topmost one
+        let plan = lock_plan(&[file("a"), file("a/b")]);
+        assert_eq!(plan.get(&root.join("a")), Some(&Kind::Exclusive));
+        assert_eq!(plan.get(&root.join("a/b")), None);
+        assert_eq!(plan.get(&root), Some(&Kind::Shared));
+
+        let lock = SessionLock::acquire(&[file("a/b")], false, &printer).unwrap();
+        // Same and enclosing directories conflict, unrelated ones do not
+        let err = SessionLock::acquire(&[file("a/b")], false, &printer)
+            .err()
+            .unwrap();
+        assert_eq!(err.kind, ErrorKind::Locked);
+        assert!(SessionLock::acquire(&[file("a")], false, &printer).is_err());
+        assert!(SessionLock::acquire(&[file("c")], false, &printer).is_ok());
+
+        drop(lock);
+        assert!(SessionLock::acquire(&[file("a")], false, &printer).is_ok());
+
+        // Targets lock their directories too, missing ones through their closest ancestor
+        let mut lock = SessionLock::acquire(&[file("a/b")], false, &printer).unwrap();
+        lock.extend(&[file("c"), file("c/new/dir")], false, &printer)
+            .unwrap();
+        assert!(SessionLock::acquire(&[file("c")], false, &printer).is_err());
+        assert!(SessionLock::acquire(&[file("a/b")], false, &printer).is_err());
+
+        // Shared ancestors are upgraded when they are renamed into
+        lock.extend(&[file("")], false, &printer).unwrap();
+        drop(lock);
+        let lock = SessionLock::acquire(&[file("c")], false, &printer).unwrap();
+        let mut other = SessionLock::acquire(&[file("a")], false, &printer).unwrap();
+        assert!(other.extend(&[file("c/new")], false, &printer).is_err());
+        drop(lock);
+    }
+}
@@ -6,6 +6,8 @@ extern crate ansi_term;
 extern crate any_ascii;
 extern crate chrono;
 extern crate difference;
+#[cfg(unix)]
+extern crate libc;
 extern crate path_abs;
 extern crate regex;
 extern crate serde;
@@ -18,16 +20,46 @@ extern crate clap;
 #[macro_use]
 extern crate serde_derive;
 
+use error::Error;
+use output::Printer;
 use renamer::Renamer;
+use server::Server;
+use std::path::{Path, PathBuf};
 
 mod app;
+mod backups;
+mod casefold;
 mod config;
+mod driver;
 mod dumpfile;
 mod error;
+mod exif;
 mod fileutils;
+mod filters;
+mod importer;
+mod inuse;
+mod lock;
+mod metadata;
 mod output;
+mod pathlen;
+mod plan;
+mod progress;
 mod renamer;
+mod report;
+mod review;
+mod rules;
+mod runid;
+mod safety;
+mod sample;
+mod server;
+mod settings;
+mod simulate;
 mod solver;
+mod space;
+mod stems;
+mod template;
+mod validate;
+mod warning;
 
 fn main() {
     // Read arguments
@@ -39,34 +71,133 @@ fn main() {
         }
     };
 
-    if !config.force {
+    // Serve requests until input is closed
+    if let config::RunMode::Serve = config.run_mode {
+        let stdin = std::io::stdin();
+        if let Err(err) = Server::new(&config).serve(stdin.lock(), std::io::stdout()) {
+            eprintln!(
+                "{}{}",
+                err.description(),
+                err.value.as_deref().unwrap_or("")
+            );
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    // Tag operations of a dump file without renaming
+    if let config::RunMode::Review(path) = &config.run_mode {
+        let stdin = std::io::stdin();
+        if let Err(err) = review::review(Path::new(path), stdin.lock(), std::io::stdout()) {
+            exit_with_error(&config.printer, &err);
+        }
+        return;
+    }
+
+    // Compare two plans without renaming
+    if let config::RunMode::PlanDiff { old, new } = &config.run_mode {
+        if let Err(err) = plan::print_diff(Path::new(old), Path::new(new), &config.printer) {
+            exit_with_error(&config.printer, &err);
+        }
+        return;
+    }
+
+    // Describe the dump format for scripts generating dump files
+    if let config::RunMode::DumpSpec = &config.run_mode {
+        println!("{}", dumpfile::spec());
+        return;
+    }
+
+    // Check a replacement template without renaming
+    if let config::RunMode::ValidateTemplate {
+        template,
+        expression,
+    } = &config.run_mode
+    {
+        let expression = expression.as_deref();
+        if let Err(err) =
+            validate::print_validation(template, expression, &config.settings, &config.printer)
+        {
+            exit_with_error(&config.printer, &err);
+        }
+        return;
+    }
+
+    if config.simulation.is_some() {
+        let info = &config.printer.colors.info;
+        config
+            .printer
+            .print(&format!("{}", info.paint("This is a SIMULATION")));
+    } else if !config.force {
         let info = &config.printer.colors.info;
         config
             .printer
             .print(&format!("{}", info.paint("This is a DRY-RUN")));
     }
+    config
+        .printer
+        .verbose(&format!("Run ID: {}", config.run_id));
+
+    // Remove old backups and dump files
+    if let config::RunMode::PruneBackups {
+        paths,
+        recursive,
+        policy,
+    } = &config.run_mode
+    {
+        let paths: Vec<PathBuf> = paths.iter().map(PathBuf::from).collect();
+        let driver = config.driver.as_ref();
+        for path in backups::prune_candidates(&paths, *recursive, policy, driver) {
+            if config.force {
+                if let Err(err) = backups::remove(&path, driver) {
+                    exit_with_error(&config.printer, &err);
+                }
+            }
+            config.printer.print(&format!(
+                "{}{}",
+                config.printer.colors.info.paint("Remove "),
+                config
+                    .printer
+                    .colors
+                    .source
+                    .paint(path.display().to_string())
+            ));
+        }
+        return;
+    }
 
     // Configure renamer
     let renamer = match Renamer::new(&config) {
         Ok(renamer) => renamer,
-        Err(err) => {
-            config.printer.print_error(&err);
-            std::process::exit(1);
-        }
+        Err(err) => exit_with_error(&config.printer, &err),
     };
 
     // Generate operations
     let operations = match renamer.process() {
         Ok(operations) => operations,
-        Err(err) => {
-            config.printer.print_error(&err);
-            std::process::exit(1);
-        }
+        Err(err) => exit_with_error(&config.printer, &err),
     };
 
     // Batch rename operations
     if let Err(err) = renamer.batch_rename(operations) {
-        config.printer.print_error(&err);
-        std::process::exit(1);
+        exit_with_error(&config.printer, &err);
+    }
+    renamer.print_slowest();
+    config.printer.print_warning_summary();
+
+    // Print the resulting tree of the simulation
+    if let Some(simulation) = &config.simulation {
+        let tree = simulation.render(config.driver.as_ref(), &config.run_id);
+        match simulation.format {
+            simulate::Format::Json => println!("{}", tree),
+            simulate::Format::Tree => config.printer.print(&tree),
+        }
     }
 }
+
+/// Print an error and the summary of the warnings printed before it, and exit with failure
+fn exit_with_error(printer: &Printer, err: &Error) -> ! {
+    printer.print_error(err);
+    printer.print_warning_summary();
+    std::process::exit(1);
+}
@@ -0,0 +1,333 @@
+use crate::driver::{Entry, EntryKind, FsDriver, LocalDriver};
+use crate::exif;
+use crate::fileutils::PathList;
+use chrono::NaiveDateTime;
+use std::cell::{Cell, RefCell};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+
+/// Per-run cache of file metadata keyed by path. File system queries, EXIF parsing and content
+/// hashing are done at most once per path while planning, no matter how many times path cleanup,
+/// replacement placeholders or the solver need them. Operations change the file system, so a cache must not outlive the
+/// planning of a single run.
+pub struct MetadataCache {
+    driver: Arc<dyn FsDriver>,
+    metadata: RefCell<HashMap<PathBuf, Option<Entry>>>,
+    symlink_metadata: RefCell<HashMap<PathBuf, Option<Entry>>>,
+    exif_dates: RefCell<HashMap<PathBuf, Option<NaiveDateTime>>>,
+    content_hashes: RefCell<HashMap<PathBuf, Option<u64>>>,
+    /// Kinds of the paths listed by walks, given by the directory listings
+    kinds: RefCell<HashMap<PathBuf, EntryKind>>,
+    hits: Cell<usize>,
+    misses: Cell<usize>,
+}
+
+/// Values read ahead of planning by `MetadataCache::prefetch`
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Prefetch {
+    /// Metadata without following symlinks, used to check every path
+    pub symlink_metadata: bool,
+    /// Metadata following symlinks, used by modification times
+    pub metadata: bool,
+    pub exif_dates: bool,
+}
+
+/// Values of a single path read by a prefetch thread
+type Prefetched = (
+    PathBuf,
+    Option<Option<Entry>>,
+    Option<Option<Entry>>,
+    Option<Option<NaiveDateTime>>,
+);
+
+/// Cache usage counters
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct CacheStats {
+    pub hits: usize,
+    pub misses: usize,
+}
+
+impl MetadataCache {
+    /// Create a cache for the local file system.
+    pub fn new() -> MetadataCache {
+        MetadataCache::with_driver(Arc::new(LocalDriver))
+    }
+
+    /// Create a cache reading metadata with the given driver.
+    pub fn with_driver(driver: Arc<dyn FsDriver>) -> MetadataCache {
+        MetadataCache {
+            driver,
+            metadata: RefCell::default(),
+            symlink_metadata: RefCell::default(),
+            exif_dates: RefCell::default(),
+            content_hashes: RefCell::default(),
+            kinds: RefCell::default(),
+            hits: Cell::default(),
+            misses: Cell::default(),
+        }
+    }
+
+    /// Get path metadata following symlinks.
+    pub fn metadata(&self, path: &Path) -> Option<Entry> {
+        self.cached(&self.metadata, path, |path| self.driver.metadata(path).ok())
+    }
+
+    /// Get path metadata without following symlinks.
+    pub fn symlink_metadata(&self, path: &Path) -> Option<Entry> {
+        self.cached(&self.symlink_metadata, path, |path| {
+            self.driver.symlink_metadata(path).ok()
+        })
+    }
+
+    /// Get the kind of a path without following symlinks, from the walk that listed it or from
+    /// its metadata otherwise.
+    pub fn kind(&self, path: &Path) -> Option<EntryKind> {
+        let listed = self.kinds.borrow().get(path).copied();
+        if listed.is_some() {
+            self.hits.set(self.hits.get() + 1);
+            return listed;
+        }
+        self.symlink_metadata(path).map(|metadata| metadata.kind)
+    }
+
+    /// Check if the kind of a path is known from the walk that listed it.
+    pub fn is_listed(&self, path: &Path) -> bool {
+        self.kinds.borrow().contains_key(path)
+    }
+
+    /// List paths like `FsDriver::walk`, keeping the kinds of the listed entries so checking them
+    /// does not query each path again.
+    pub fn walk(
+        &self,
+        root: &Path,
+        max_depth: Option<usize>,
+        filter: &dyn Fn(&Path, usize) -> bool,
+    ) -> PathList {
+        let entries = self.driver.walk_entries(root, max_depth, filter);
+        let mut kinds = self.kinds.borrow_mut();
+        entries
+            .into_iter()
+            .map(|entry| {
+                kinds.insert(entry.path.clone(), entry.kind);
+                entry.path
+            })
+            .collect()
+    }
+
+    /// Get EXIF capture date of an image.
+    pub fn exif_date(&self, path: &Path) -> Option<NaiveDateTime> {
+        self.cached(&self.exif_dates, path, |path| {
+            let data = self.driver.read(path, exif::EXIF_READ_LIMIT).ok()?;
+            exif::read_date(&data)
+        })
+    }
+
+    /// Get a hash of the content of a file, not available for directories and symlinks. Hashes
+    /// are only comparable within the same run.
+    pub fn content_hash(&self, path: &Path) -> Option<u64> {
+        self.cached(&self.content_hashes, path, |path| {
+            if self.symlink_metadata(path)?.kind != EntryKind::File {
+                return None;
+            }
+            let data = self.driver.read(path, u64::MAX).ok()?;
+            let mut hasher = DefaultHasher::new();
+            data.hash(&mut hasher);
+            Some(hasher.finish())
+        })
+    }
+
+    /// Check if the paths reference the same entry. Case variants in case sensitive volumes of
+    /// systems usually case insensitive may be distinct files with the same size and times, so
+    /// their metadata and contents must match too.
+    pub fn same_file(&self, source: &Path, target: &Path) -> bool {
+        self.driver.same_file(source, target)
+            && self.symlink_metadata(source) == self.symlink_metadata(target)
+            && self.content_hash(source) == self.content_hash(target)
+    }
+
+    /// Read the metadata of the paths with up to `concurrency` parallel readers and store it, so
+    /// planning does not wait for each read in turn. Values already cached are read again and
+    /// replaced.
+    pub fn prefetch(&self, paths: &[PathBuf], prefetch: Prefetch, concurrency: usize) {
+        let next = AtomicUsize::new(0);
+        let driver = self.driver.as_ref();
+        let read = |path: &Path| -> Prefetched {
+            let metadata = prefetch.metadata.then(|| driver.metadata(path).ok());
+            let exif_date = prefetch.exif_dates.then(|| {
+                let data = driver.read(path, exif::EXIF_READ_LIMIT).ok();
+                data.and_then(|data| exif::read_date(&data))
+            });
+            let symlink_metadata = prefetch
+                .symlink_metadata
+                .then(|| driver.symlink_metadata(path).ok());
+            (path.to_path_buf(), symlink_metadata, metadata, exif_date)
+        };
+        // Readers take the next pending path, so slow reads do not hold back a whole batch
+        let values: Vec<Prefetched> = thread::scope(|scope| {
+            let readers: Vec<_> = (0..concurrency.clamp(1, paths.len().max(1)))
+                .map(|_| {
+                    scope.spawn(|| {
+                        let mut values = Vec::new();
+                        while let Some(path) = paths.get(next.fetch_add(1, Ordering::Relaxed)) {
+                            values.push(read(path));
+                        }
+                        values
+                    })
+                })
+                .collect();
+            readers
+                .into_iter()
+                .flat_map(|reader| reader.join().unwrap_or_default())
+                .collect()
+        });
+
+        for (path, symlink_metadata, metadata, exif_date) in values {
+            let mut reads = 0;
+            if let Some(symlink_metadata) = symlink_metadata {
+                self.symlink_metadata
+                    .borrow_mut()
+                    .insert(path.clone(), symlink_metadata);
+                reads += 1;
+            }
+            if let Some(metadata) = metadata {
+                self.metadata.borrow_mut().insert(path.clone(), metadata);
+                reads += 1;
+            }
+            if let Some(exif_date) = exif_date {
+                self.exif_dates.borrow_mut().insert(path, exif_date);
+                reads += 1;
+            }
+            self.misses.set(self.misses.get() + reads);
+        }
+    }
+
+    /// Get cache usage counters.
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            hits: self.hits.get(),
+            misses: self.misses.get(),
+        }
+    }
+
+    /// Get a value from the given map or load it and store it for next queries.
+    fn cached<T: Clone>(
+        &self,
+        map: &RefCell<HashMap<PathBuf, T>>,
+        path: &Path,
+        load: impl FnOnce(&Path) -> T,
+    ) -> T {
+        if let Some(value) = map.borrow().get(path) {
+            self.hits.set(self.hits.get() + 1);
+            return value.clone();
+        }
+        self.misses.set(self.misses.get() + 1);
+        let value = load(path);
+        map.borrow_mut().insert(path.to_path_buf(), value.clone());
+        value
+    }
+}
+
+#[cfg(test)]
+mod test {
+    extern crate tempfile;
+    use super::*;
+    use crate::driver::MemoryDriver;
+    use std::fs;
+
+    #[test]
+    fn cache_metadata() {
+        let tempdir = tempfile::tempdir().expect("Error creating temp directory");
+        println!("Running test in '{:?}'", tempdir);
+        let file = tempdir.path().join("test_file");
+        let missing = tempdir.path().join("missing_file");
+        fs::File::create(&file).expect("Error creating mock file...");
+
+        let cache = MetadataCache::new();
+        assert!(cache.symlink_metadata(&file).is_some());
+        assert!(cache.metadata(&file).is_some());
+        assert!(cache.symlink_metadata(&missing).is_none());
+        assert_eq!(cache.stats(), CacheStats { hits: 0, misses: 3 });
+
+        // Values are not read again, even if the file system changes
+        fs::remove_file(&file).unwrap();
+        assert!(cache.symlink_metadata(&file).is_some());
+        assert!(cache.symlink_metadata(&missing).is_none());
+        assert!(cache.exif_date(&missing).is_none());
+        assert!(cache.exif_date(&missing).is_none());
+        assert_eq!(cache.stats(), CacheStats { hits: 3, misses: 4 });
+    }
+
+    #[test]
+    fn prefetch_metadata() {
+        let tempdir = tempfile::tempdir().expect("Error creating temp directory");
+        println!("Running test in '{:?}'", tempdir);
+        let paths: Vec<PathBuf> = (0..10)
+            .map(|index| tempdir.path().join(format!("file_{}", index)))
+            .collect();
+        for path in &paths[..9] {
+            fs::File::create(path).expect("Error creating mock file...");
+        }
+
+        let cache = MetadataCache::new();
+        let prefetch = Prefetch {
+            symlink_metadata: true,
+            metadata: true,
+            exif_dates: false,
+        };
+        cache.prefetch(&paths, prefetch, 4);
+        assert_eq!(
+            cache.stats(),
+            CacheStats {
+                hits: 0,
+                misses: 20
+            }
+        );
+
+        // Planning reads prefetched values from the cache
+        for path in &paths[..9] {
+            assert!(cache.symlink_metadata(path).is_some());
+            assert!(cache.metadata(path).is_some());
+        }
+        assert!(cache.symlink_metadata(&paths[9]).is_none());
+        assert!(cache.exif_date(&paths[0]).is_none());
+        assert_eq!(
+            cache.stats(),
+            CacheStats {
+                hits: 19,
+                misses: 21
+            }
+        );
+    }
+
+    #[test]
+    fn cache_content_hashes() {
+        let driver = Arc::new(MemoryDriver::new());
+        driver.create_file(Path::new("/data/a.txt"), b"content");
+        driver.create_file(Path::new("/data/b.txt"), b"content");
+        driver.create_file(Path::new("/data/c.txt"), b"other content");
+
+        let cache = MetadataCache::with_driver(driver.clone());
+        let hash = cache.content_hash(Path::new("/data/a.txt"));
+        assert!(hash.is_some());
+        assert_eq!(hash, cache.content_hash(Path::new("/data/b.txt")));
+        assert_ne!(hash, cache.content_hash(Path::new("/data/c.txt")));
+        assert!(cache.content_hash(Path::new("/data")).is_none());
+        assert!(cache.content_hash(Path::new("/data/missing.txt")).is_none());
+        assert_eq!(hash, cache.content_hash(Path::new("/data/a.txt")));
+        assert_eq!(
+            cache.stats(),
+            CacheStats {
+                hits: 1,
+                misses: 10
+            }
+        );
+
+        assert!(cache.same_file(Path::new("/data/a.txt"), Path::new("/data/./a.txt")));
+        assert!(!cache.same_file(Path::new("/data/a.txt"), Path::new("/data/b.txt")));
+    }
+}
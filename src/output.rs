@@ -2,7 +2,17 @@ use ansi_term::Colour::*;
 use ansi_term::Style;
 use difference::{Changeset, Difference};
 use crate::error::*;
-use std::path::Path;
+use crate::solver::Operation;
+use crate::warning::Warning;
+use std::collections::BTreeMap;
+use std::env;
+use std::io::IsTerminal;
+use std::path::{Component, Path, PathBuf, MAIN_SEPARATOR};
+use std::sync::Mutex;
+use unicode_width::UnicodeWidthStr;
+
+/// Separator printed between source and target columns
+const ARROW: &str = " -> ";
 
 #[derive(PartialEq)]
 enum PrinterMode {
@@ -11,9 +21,65 @@ enum PrinterMode {
     Color,
 }
 
+/// Amount of information printed in non-silent modes
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+pub enum Level {
+    /// Print only warnings and errors, successful operations are not printed
+    QuietSuccess,
+    Normal,
+    Verbose,
+    /// Print internal details useful to diagnose problems
+    Debug,
+}
+
+/// How paths of printed operations are quoted
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Quote {
+    /// Quote and escape only ambiguous names, like names starting with a dash
+    Escape,
+    /// Quote complete paths for POSIX shells, so they can be pasted into a terminal or script
+    Shell,
+}
+
+impl Quote {
+    fn apply(&self, name: &str) -> String {
+        match self {
+            Quote::Escape => escape_name(name),
+            Quote::Shell => shell_quote(name),
+        }
+    }
+}
+
 pub struct Printer {
     pub colors: Colors,
     mode: PrinterMode,
+    level: Level,
+    quote: Quote,
+    /// Warning categories not printed, they are still counted
+    suppressed: Vec<Warning>,
+    /// Number of warnings of each category
+    warnings: Mutex<BTreeMap<Warning, usize>>,
+}
+
+/// Column layout shared by all the operations printed in a batch
+#[derive(Debug, PartialEq)]
+pub struct Layout {
+    /// Common parent directory elided from printed paths
+    prefix: PathBuf,
+    /// Width of the source column
+    source_width: usize,
+    /// Terminal width, if known
+    max_width: Option<usize>,
+}
+
+impl Layout {
+    /// Get the common parent directory with a trailing separator, roots already end with one
+    fn header(&self) -> Option<String> {
+        if self.prefix.as_os_str().is_empty() {
+            return None;
+        }
+        Some(self.prefix.join("").display().to_string())
+    }
 }
 
 pub struct Colors {
@@ -40,6 +106,10 @@ impl Printer {
         Printer {
             colors,
             mode: PrinterMode::Color,
+            level: Level::Normal,
+            quote: Quote::Escape,
+            suppressed: Vec::new(),
+            warnings: Mutex::new(BTreeMap::new()),
         }
     }
 
@@ -57,6 +127,10 @@ impl Printer {
         Printer {
             colors,
             mode: PrinterMode::NoColor,
+            level: Level::Normal,
+            quote: Quote::Escape,
+            suppressed: Vec::new(),
+            warnings: Mutex::new(BTreeMap::new()),
         }
     }
 
@@ -74,11 +148,33 @@ impl Printer {
         Printer {
             colors,
             mode: PrinterMode::Silent,
+            level: Level::Normal,
+            quote: Quote::Escape,
+            suppressed: Vec::new(),
+            warnings: Mutex::new(BTreeMap::new()),
         }
     }
 
-    /// Print string to Stdout when printer is not in silent mode
+    /// Set amount of information printed
+    pub fn set_level(&mut self, level: Level) {
+        self.level = level;
+    }
+
+    /// Set how paths of operations are quoted
+    pub fn set_quote(&mut self, quote: Quote) {
+        self.quote = quote;
+    }
+
+    /// Set warning categories that are counted but not printed
+    pub fn set_suppressed(&mut self, suppressed: Vec<Warning>) {
+        self.suppressed = suppressed;
+    }
+
+    /// Print string to Stdout when printer is not in silent or quiet success mode
     pub fn print(&self, message: &str) {
+        if self.level < Level::Normal {
+            return;
+        }
         match self.mode {
             PrinterMode::Color | PrinterMode::NoColor => {
                 println!("{}", message);
@@ -97,6 +193,64 @@ impl Printer {
         }
     }
 
+    /// Print warning to Stderr when printer is not in silent mode and its category is not
+    /// suppressed
+    pub fn print_warning(&self, category: Warning, message: &str) {
+        *self.warnings.lock().unwrap().entry(category).or_insert(0) += 1;
+        if self.suppressed.contains(&category) {
+            return;
+        }
+        self.eprint(&format!(
+            "{}{}",
+            self.colors.warn.paint("Warning: "),
+            message
+        ));
+    }
+
+    /// Print the number of warnings of each category, if any, to Stderr
+    pub fn print_warning_summary(&self) {
+        if let Some(summary) = self.warning_summary() {
+            self.eprint(&format!(
+                "{}{}",
+                self.colors.warn.paint("Warnings: "),
+                summary
+            ));
+        }
+    }
+
+    fn warning_summary(&self) -> Option<String> {
+        let warnings = self.warnings.lock().unwrap();
+        if warnings.is_empty() {
+            return None;
+        }
+        let counts: Vec<String> = warnings
+            .iter()
+            .map(|(category, count)| {
+                let suppressed = if self.suppressed.contains(category) {
+                    " (suppressed)"
+                } else {
+                    ""
+                };
+                format!("{} {}{}", count, category.name(), suppressed)
+            })
+            .collect();
+        Some(counts.join(", "))
+    }
+
+    /// Print information to Stdout when printer is in verbose or debug level
+    pub fn verbose(&self, message: &str) {
+        if self.level >= Level::Verbose {
+            self.print(&format!("{}{}", self.colors.info.paint("Info: "), message));
+        }
+    }
+
+    /// Print debug information to Stderr when printer is in debug level
+    pub fn debug(&self, message: &str) {
+        if self.level >= Level::Debug {
+            self.eprint(&format!("{}{}", self.colors.info.paint("Debug: "), message));
+        }
+    }
+
     /// Print error pretty printed
     pub fn print_error(&self, error: &Error) {
         let error_value = error.value.to_owned().unwrap_or_else(|| String::from(""));
@@ -109,17 +263,42 @@ impl Printer {
         ));
     }
 
-    /// Pretty print operation
-    pub fn print_operation(&self, source: &Path, target: &Path) {
-        // Avoid any additional processing costs if silent mode
-        if self.mode == PrinterMode::Silent {
+    /// Compute the layout to print the given operations aligned in two columns. The common parent
+    /// directory is elided and the source column is sized to fit the terminal width.
+    pub fn layout(&self, operations: &[Operation]) -> Layout {
+        let max_width = if self.mode == PrinterMode::Silent {
+            None
+        } else {
+            terminal_width()
+        };
+        layout(operations, max_width, self.quote)
+    }
+
+    /// Print the common parent directory of a layout, if any
+    pub fn print_layout_header(&self, layout: &Layout) {
+        if let Some(header) = layout.header() {
+            self.print(&format!(
+                "{}{}",
+                self.colors.info.paint("In "),
+                self.colors.info.paint(header)
+            ));
+        }
+    }
+
+    /// Pretty print operation. Symlinks are followed by the path they point to.
+    pub fn print_operation(&self, operation: &Operation, layout: &Layout) {
+        // Avoid any additional processing costs if nothing is printed
+        if self.mode == PrinterMode::Silent || self.level < Level::Normal {
             return;
         }
 
-        let mut source_parent = source.parent().unwrap().to_string_lossy().to_string();
-        let mut source_name = source.file_name().unwrap().to_string_lossy().to_string();
-        let mut target_parent = target.parent().unwrap().to_string_lossy().to_string();
-        let mut target_name = target.file_name().unwrap().to_string_lossy().to_string();
+        let source = strip_prefix(&operation.source, &layout.prefix);
+        let target = strip_prefix(&operation.target, &layout.prefix);
+        let (mut source_parent, mut source_name) = split_name(source, self.quote);
+        let (mut target_parent, mut target_name) = split_name(target, self.quote);
+        // Widths are computed before adding color escape sequences
+        let source_width = display_width(&source_parent) + display_width(&source_name);
+        let target_width = display_width(&target_parent) + display_width(&target_name);
 
         // Avoid diffing if not coloring output
         if self.mode == PrinterMode::Color {
@@ -134,24 +313,40 @@ impl Printer {
         source_name = self.colors.source.paint(&source_name).to_string();
 
         if !source_parent.is_empty() {
-            source_parent = self
-                .colors
-                .source
-                .paint(format!("{}/", source_parent))
-                .to_string();
+            source_parent = self.colors.source.paint(source_parent).to_string();
         }
         if !target_parent.is_empty() {
-            target_parent = self
-                .colors
-                .target
-                .paint(format!("{}/", target_parent))
-                .to_string();
+            target_parent = self.colors.target.paint(target_parent).to_string();
         }
+        let link = match &operation.link {
+            Some(link) => {
+                let link = format!(
+                    " (link{}{})",
+                    ARROW,
+                    self.quote.apply(&link.to_string_lossy())
+                );
+                self.colors.info.paint(link).to_string()
+            }
+            None => String::new(),
+        };
 
-        self.print(&format!(
-            "{}{} -> {}{}",
-            source_parent, source_name, target_parent, target_name
-        ));
+        // Rows that do not fit the columns print the target in a new line to avoid wrapping
+        let fits = source_width <= layout.source_width
+            && layout.max_width.is_none_or(|max_width| {
+                layout.source_width + ARROW.len() + target_width <= max_width
+            });
+        if fits {
+            let padding = " ".repeat(layout.source_width - source_width);
+            self.print(&format!(
+                "{}{}{}{}{}{}{}",
+                source_parent, source_name, padding, ARROW, target_parent, target_name, link
+            ));
+        } else {
+            self.print(&format!(
+                "{}{}\n   {}{}{}{}",
+                source_parent, source_name, ARROW, target_parent, target_name, link
+            ));
+        }
     }
 
     /// Generate a colored diff from the given strings
@@ -178,3 +373,280 @@ impl Printer {
         colored_string
     }
 }
+
+/// Compute operations layout for the given terminal width. Shell quoted paths are printed
+/// complete, without eliding their common parent.
+fn layout(operations: &[Operation], max_width: Option<usize>, quote: Quote) -> Layout {
+    let prefix = match quote {
+        Quote::Escape => common_parent(operations),
+        Quote::Shell => PathBuf::new(),
+    };
+    let source_widths = operations.iter().map(|operation| {
+        let (parent, name) = split_name(strip_prefix(&operation.source, &prefix), quote);
+        display_width(&parent) + display_width(&name)
+    });
+    // Long sources would push all targets out of the screen, leave them out of the column
+    let source_width = match max_width {
+        Some(max_width) => {
+            let column_limit = max_width.saturating_sub(ARROW.len()) / 2;
+            source_widths.filter(|width| *width <= column_limit).max()
+        }
+        None => source_widths.max(),
+    };
+
+    Layout {
+        prefix,
+        source_width: source_width.unwrap_or(0),
+        max_width,
+    }
+}
+
+/// Get the deepest directory containing all sources and targets. The current directory is never
+/// considered a common parent, relative paths are printed as they are.
+fn common_parent(operations: &[Operation]) -> PathBuf {
+    let mut parents = operations
+        .iter()
+        .flat_map(|operation| [&operation.source, &operation.target])
+        .map(|path| path.parent().unwrap_or_else(|| Path::new("")));
+    let first = match parents.next() {
+        Some(first) => first,
+        None => return PathBuf::new(),
+    };
+
+    let mut common: Vec<Component> = first.components().collect();
+    for parent in parents {
+        let shared = common
+            .iter()
+            .zip(parent.components())
+            .take_while(|(a, b)| **a == *b)
+            .count();
+        common.truncate(shared);
+    }
+    if common == [Component::CurDir] {
+        common.clear();
+    }
+    common.into_iter().collect()
+}
+
+/// Remove the layout prefix from a path
+fn strip_prefix<'a>(path: &'a Path, prefix: &Path) -> &'a Path {
+    path.strip_prefix(prefix).unwrap_or(path)
+}
+
+/// Split path in quoted parent, including the trailing separator, and quoted file name
+fn split_name(path: &Path, quote: Quote) -> (String, String) {
+    let name = path
+        .file_name()
+        .map(|name| quote.apply(&name.to_string_lossy()))
+        .unwrap_or_default();
+    match path
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+    {
+        Some(parent) => (
+            format!(
+                "{}{}",
+                quote.apply(&parent.to_string_lossy()),
+                MAIN_SEPARATOR
+            ),
+            name,
+        ),
+        None => (String::new(), name),
+    }
+}
+
+/// Get the number of terminal columns used to display a string. Wide East Asian characters and
+/// emoji take two columns, combining marks and zero width characters none.
+fn display_width(text: &str) -> usize {
+    text.width()
+}
+
+/// Get terminal width when printing to a terminal. `COLUMNS` environment variable takes
+/// precedence over the size reported by the terminal.
+fn terminal_width() -> Option<usize> {
+    if !std::io::stdout().is_terminal() {
+        return None;
+    }
+    if let Some(columns) = env::var("COLUMNS")
+        .ok()
+        .and_then(|columns| columns.parse::<usize>().ok())
+    {
+        return Some(columns);
+    }
+
+    #[cfg(unix)]
+    {
+        let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+        // SAFETY: TIOCGWINSZ only writes into the provided winsize struct
+        let result = unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) };
+        if result == 0 && size.ws_col > 0 {
+            return Some(size.ws_col as usize);
+        }
+    }
+    None
+}
+
+/// Quote and escape names that could be ambiguous in the output: names starting with a dash,
+/// with leading or trailing whitespace or containing control characters like newlines.
+fn escape_name(name: &str) -> String {
+    let is_ambiguous = name.starts_with('-')
+        || name.starts_with(char::is_whitespace)
+        || name.ends_with(char::is_whitespace)
+        || name.chars().any(char::is_control);
+    if is_ambiguous {
+        format!("{:?}", name)
+    } else {
+        name.to_string()
+    }
+}
+
+/// Quote text for POSIX shells using single quotes, only if it contains special characters.
+/// Single quotes and control characters are written outside the quotes, as `\'` and `$'\n'`.
+fn shell_quote(text: &str) -> String {
+    let is_safe = |c: char| c.is_alphanumeric() || "_-.,:/@%+=".contains(c);
+    if !text.is_empty() && text.chars().all(is_safe) {
+        return text.to_string();
+    }
+
+    let mut quoted = String::new();
+    let mut is_open = false;
+    for c in text.chars() {
+        if c == '\'' || c.is_control() {
+            if is_open {
+                quoted.push('\'');
+                is_open = false;
+            }
+            match c {
+                '\'' => quoted.push_str("\\'"),
+                '\n' => quoted.push_str("$'\\n'"),
+                '\t' => quoted.push_str("$'\\t'"),
+                '\r' => quoted.push_str("$'\\r'"),
+                c if c.is_ascii() => quoted.push_str(&format!("$'\\x{:02x}'", c as u32)),
+                c => quoted.push_str(&format!("$'\\u{:04x}'", c as u32)),
+            }
+        } else {
+            if !is_open {
+                quoted.push('\'');
+                is_open = true;
+            }
+            quoted.push(c);
+        }
+    }
+    if is_open {
+        quoted.push('\'');
+    }
+    if quoted.is_empty() {
+        quoted.push_str("''");
+    }
+    quoted
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn operation(source: &str, target: &str) -> Operation {
+        Operation {
+            source: PathBuf::from(source),
+            target: PathBuf::from(target),
+            tag: None,
+            link: None,
+        }
+    }
+
+    #[test]
+    fn layout_columns() {
+        let operations = vec![
+            operation("/data/photos/a.jpg", "/data/photos/b.jpg"),
+            operation("/data/photos/2021/long.jpg", "/data/photos/2021/short.jpg"),
+        ];
+        let unlimited = layout(&operations, None, Quote::Escape);
+        assert_eq!(unlimited.prefix, PathBuf::from("/data/photos"));
+        assert_eq!(unlimited.source_width, display_width("2021/long.jpg"));
+
+        // Sources wider than half the terminal are left out of the column
+        let narrow = layout(&operations, Some(24), Quote::Escape);
+        assert_eq!(narrow.source_width, display_width("a.jpg"));
+
+        // Current directory is not elided
+        let operations = vec![operation("./a", "./b"), operation("./c", "./d")];
+        assert_eq!(
+            layout(&operations, None, Quote::Escape).prefix,
+            PathBuf::new()
+        );
+        assert_eq!(layout(&[], None, Quote::Escape).source_width, 0);
+
+        // Shell quoted paths are printed complete
+        let operations = vec![operation("/data/a b", "/data/c")];
+        let quoted = layout(&operations, None, Quote::Shell);
+        assert_eq!(quoted.prefix, PathBuf::new());
+        assert_eq!(quoted.source_width, display_width("/data/'a b'"));
+    }
+
+    #[test]
+    fn layout_header() {
+        let operations = vec![operation("/data/a", "/data/b")];
+        let header = layout(&operations, None, Quote::Escape).header();
+        assert_eq!(header, Some(format!("/data{}", MAIN_SEPARATOR)));
+        // Root directories are not followed by another separator
+        let operations = vec![operation("/a", "/b")];
+        let header = layout(&operations, None, Quote::Escape).header();
+        assert_eq!(header, Some("/".to_string()));
+        let operations = vec![operation("a", "b")];
+        assert_eq!(layout(&operations, None, Quote::Escape).header(), None);
+    }
+
+    #[test]
+    fn display_widths() {
+        assert_eq!(display_width("file.txt"), 8);
+        assert_eq!(display_width("日本語.txt"), 10);
+        assert_eq!(display_width("e\u{301}"), 1);
+        assert_eq!(display_width("🎉"), 2);
+    }
+
+    #[test]
+    fn warning_summary() {
+        let mut printer = Printer::silent();
+        assert_eq!(printer.warning_summary(), None);
+        printer.set_suppressed(vec![Warning::InUse]);
+        printer.print_warning(Warning::NameTooLong, "long");
+        printer.print_warning(Warning::InUse, "in use");
+        printer.print_warning(Warning::InUse, "in use");
+        // Suppressed warnings are still counted
+        assert_eq!(
+            printer.warning_summary().unwrap(),
+            "2 in-use (suppressed), 1 name-too-long"
+        );
+
+        assert_eq!(Warning::from_str("xattr-tag"), Ok(Warning::XattrTag));
+        assert!(Warning::from_str("other").is_err());
+    }
+
+    #[test]
+    fn escape_names() {
+        assert_eq!(escape_name("file.txt"), "file.txt");
+        assert_eq!(escape_name("with space.txt"), "with space.txt");
+        assert_eq!(escape_name(""), "");
+        assert_eq!(escape_name("-"), "\"-\"");
+        assert_eq!(escape_name("-rf"), "\"-rf\"");
+        assert_eq!(escape_name(" file"), "\" file\"");
+        assert_eq!(escape_name("new\nline"), "\"new\\nline\"");
+        assert_eq!(escape_name("tab\t\"quote\""), "\"tab\\t\\\"quote\\\"\"");
+    }
+
+    #[test]
+    fn shell_quotes() {
+        assert_eq!(shell_quote("file-1.txt"), "file-1.txt");
+        assert_eq!(shell_quote("日本語.txt"), "日本語.txt");
+        assert_eq!(shell_quote(""), "''");
+        assert_eq!(shell_quote("with space.txt"), "'with space.txt'");
+        assert_eq!(shell_quote("$HOME*"), "'$HOME*'");
+        assert_eq!(shell_quote("it's"), "'it'\\''s'");
+        assert_eq!(shell_quote("new\nline"), "'new'$'\\n''line'");
+        assert_eq!(shell_quote("\u{1b}"), "$'\\x1b'");
+        assert_eq!(
+            split_name(Path::new("my dir/a&b"), Quote::Shell),
+            ("'my dir'/".to_string(), "'a&b'".to_string())
+        );
+    }
+}
@@ -0,0 +1,234 @@
+use crate::driver::FsDriver;
+use crate::metadata::MetadataCache;
+use crate::solver::Operation;
+use std::collections::{HashMap, HashSet};
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+
+/// Maximum length of a path, in bytes (UTF-16 units on Windows)
+#[cfg(target_os = "linux")]
+pub const MAX_PATH_LEN: usize = 4096;
+#[cfg(target_os = "macos")]
+pub const MAX_PATH_LEN: usize = 1024;
+#[cfg(windows)]
+pub const MAX_PATH_LEN: usize = 260;
+#[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+pub const MAX_PATH_LEN: usize = 1024;
+
+/// Maximum length of a file name, in bytes (UTF-16 units on Windows)
+pub const MAX_NAME_LEN: usize = 255;
+
+#[cfg(windows)]
+const UNIT: &str = "characters";
+#[cfg(not(windows))]
+const UNIT: &str = "bytes";
+
+/// Get warnings for targets whose final absolute path or file name exceeds the OS limits. Final
+/// paths include the renames of their parent directories applied later in the same run, and the
+/// paths inside renamed directories are checked too.
+pub fn check(
+    operations: &[Operation],
+    cache: &MetadataCache,
+    driver: &dyn FsDriver,
+) -> Vec<String> {
+    let current_dir = std::env::current_dir().unwrap_or_default();
+    let mut warnings = Vec::new();
+    for path in final_paths(operations, cache, driver) {
+        let path = current_dir.join(path);
+        if let Some(name) = path.file_name().filter(|name| length(name) > MAX_NAME_LEN) {
+            warnings.push(format!(
+                "File name longer than the limit of {} {} ({}): {}",
+                MAX_NAME_LEN,
+                UNIT,
+                length(name),
+                path.display()
+            ));
+        } else if length(path.as_os_str()) > MAX_PATH_LEN {
+            warnings.push(format!(
+                "Path longer than the limit of {} {} ({}): {}",
+                MAX_PATH_LEN,
+                UNIT,
+                length(path.as_os_str()),
+                path.display()
+            ));
+        }
+    }
+    warnings
+}
+
+/// Renamed directories by source, with the index and target of their operation
+type DirRenames<'a> = HashMap<&'a Path, (usize, &'a Path)>;
+
+/// Get the path of every target, and of every path inside a renamed directory, once all the
+/// operations are applied.
+fn final_paths(
+    operations: &[Operation],
+    cache: &MetadataCache,
+    driver: &dyn FsDriver,
+) -> Vec<PathBuf> {
+    let dirs: DirRenames = operations
+        .iter()
+        .enumerate()
+        .filter(|(_, operation)| {
+            cache
+                .symlink_metadata(&operation.source)
+                .is_some_and(|entry| entry.is_dir())
+        })
+        .map(|(index, operation)| {
+            (
+                operation.source.as_path(),
+                (index, operation.target.as_path()),
+            )
+        })
+        .collect();
+
+    let mut paths: Vec<PathBuf> = operations
+        .iter()
+        .enumerate()
+        .map(|(index, operation)| final_path(&operation.target, Some(index), &dirs))
+        .collect();
+
+    // Each tree is walked once from its topmost renamed directory, renamed paths in it were
+    // already checked as targets
+    let sources: HashSet<&Path> = operations
+        .iter()
+        .map(|operation| operation.source.as_path())
+        .collect();
+    for dir in dirs.keys() {
+        if dir
+            .ancestors()
+            .skip(1)
+            .any(|parent| dirs.contains_key(parent))
+        {
+            continue;
+        }
+        for path in driver.walk(dir, None, &|_, _| true).into_iter().skip(1) {
+            if !sources.contains(path.as_path()) {
+                paths.push(final_path(&path, None, &dirs));
+            }
+        }
+    }
+    paths
+}
+
+/// Get the path of a path once its parent directories renamed after the given operation are
+/// renamed, in the order of their operations.
+fn final_path(path: &Path, after: Option<usize>, dirs: &DirRenames) -> PathBuf {
+    let mut path = path.to_path_buf();
+    let mut after = after;
+    loop {
+        let renamed = path
+            .ancestors()
+            .skip(1)
+            .filter_map(|parent| dirs.get(parent).map(|rename| (parent, rename)))
+            .filter(|(_, (index, _))| after.is_none_or(|after| *index > after))
+            .min_by_key(|(_, (index, _))| *index);
+        match renamed {
+            Some((parent, (index, target))) => {
+                let relative = path.strip_prefix(parent).unwrap_or(&path).to_path_buf();
+                path = target.join(relative);
+                after = Some(*index);
+            }
+            None => return path,
+        }
+    }
+}
+
+#[cfg(windows)]
+fn length(value: &OsStr) -> usize {
+    use std::os::windows::ffi::OsStrExt;
+    value.encode_wide().count()
+}
+
+#[cfg(not(windows))]
+fn length(value: &OsStr) -> usize {
+    value.len()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::driver::MemoryDriver;
+    use std::sync::Arc;
+
+    fn operation(source: &str, target: &str) -> Operation {
+        Operation {
+            source: PathBuf::from(source),
+            target: PathBuf::from(target),
+            tag: None,
+            link: None,
+        }
+    }
+
+    #[test]
+    fn nested_path_lengths() {
+        let driver = Arc::new(MemoryDriver::new());
+        driver.create_file(Path::new("/data/dir/file"), b"");
+        let cache = MetadataCache::with_driver(driver.clone());
+        let driver = driver.as_ref();
+        let long_dir = format!("/data/{}", "d".repeat(MAX_NAME_LEN));
+        let long_file = "f".repeat(MAX_NAME_LEN);
+
+        // Both names fit, but the file ends up in the renamed directory
+        let operations = vec![
+            operation("/data/dir/file", &format!("/data/dir/{}", long_file)),
+            operation("/data/dir", &long_dir),
+        ];
+        assert_eq!(
+            final_paths(&operations, &cache, driver)[0],
+            PathBuf::from(format!("{}/{}", long_dir, long_file))
+        );
+        let too_deep = (0..MAX_PATH_LEN / MAX_NAME_LEN)
+            .map(|_| long_file.as_str())
+            .collect::<Vec<_>>()
+            .join("/");
+        let operations = vec![
+            operation("/data/dir/file", &format!("/data/dir/{}", too_deep)),
+            operation("/data/dir", &long_dir),
+        ];
+        let warnings = check(&operations, &cache, driver);
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].starts_with("Path longer"));
+
+        let operations = vec![operation(
+            "/data/dir/file",
+            &format!("/data/dir/{}x", long_file),
+        )];
+        assert!(check(&operations, &cache, driver)[0].starts_with("File name longer"));
+        assert!(check(&[operation("/data/dir", "/data/other")], &cache, driver).is_empty());
+    }
+
+    #[test]
+    fn renamed_dir_contents() {
+        let driver = Arc::new(MemoryDriver::new());
+        let long_file = "f".repeat(MAX_NAME_LEN);
+        let nested = format!("/data/dir/sub/{}", long_file);
+        driver.create_file(Path::new(&nested), b"");
+        driver.create_file(Path::new("/data/dir/sub/renamed"), b"");
+        let cache = MetadataCache::with_driver(driver.clone());
+        let driver = driver.as_ref();
+
+        // Paths inside the directory are not renamed themselves but move with it
+        let long_dir = (0..MAX_PATH_LEN / MAX_NAME_LEN)
+            .map(|_| "d".repeat(MAX_NAME_LEN))
+            .collect::<Vec<_>>()
+            .join("/");
+        let operations = vec![
+            operation("/data/dir/sub/renamed", "/data/dir/sub/short"),
+            operation("/data/dir/sub", "/data/dir/other"),
+            operation("/data/dir", &format!("/data/{}", long_dir)),
+        ];
+        let mut paths = final_paths(&operations, &cache, driver);
+        paths.sort();
+        assert_eq!(
+            paths,
+            vec![
+                PathBuf::from(format!("/data/{}", long_dir)),
+                PathBuf::from(format!("/data/{}/other", long_dir)),
+                PathBuf::from(format!("/data/{}/other/{}", long_dir, long_file)),
+                PathBuf::from(format!("/data/{}/other/short", long_dir)),
+            ]
+        );
+        assert_eq!(check(&operations, &cache, driver).len(), 4);
+    }
+}
@@ -0,0 +1,210 @@
+use crate::dumpfile;
+use crate::error::*;
+use crate::output::Printer;
+use crate::solver::Operation;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+/// Change of an operation between two plans, identified by its source
+#[derive(Debug, PartialEq)]
+pub enum Change {
+    Added {
+        source: PathBuf,
+        target: PathBuf,
+    },
+    Removed {
+        source: PathBuf,
+        target: PathBuf,
+    },
+    Changed {
+        source: PathBuf,
+        old_target: PathBuf,
+        new_target: PathBuf,
+    },
+}
+
+/// Compare the operations of two plans by their source paths. Changes are sorted by source and
+/// unchanged operations are not included.
+pub fn diff(old: &[Operation], new: &[Operation]) -> Vec<Change> {
+    let targets = |operations: &[Operation]| -> BTreeMap<PathBuf, PathBuf> {
+        operations
+            .iter()
+            .map(|operation| (operation.source.clone(), operation.target.clone()))
+            .collect()
+    };
+    let (mut old, new) = (targets(old), targets(new));
+
+    let mut changes = Vec::new();
+    for (source, new_target) in new {
+        match old.remove(&source) {
+            Some(old_target) if old_target == new_target => {}
+            Some(old_target) => changes.push(Change::Changed {
+                source,
+                old_target,
+                new_target,
+            }),
+            None => changes.push(Change::Added {
+                source,
+                target: new_target,
+            }),
+        }
+    }
+    changes.extend(
+        old.into_iter()
+            .map(|(source, target)| Change::Removed { source, target }),
+    );
+    changes.sort_by(|a, b| a.source().cmp(b.source()));
+    changes
+}
+
+impl Change {
+    pub fn source(&self) -> &Path {
+        match self {
+            Change::Added { source, .. }
+            | Change::Removed { source, .. }
+            | Change::Changed { source, .. } => source,
+        }
+    }
+}
+
+/// Print the operations added, removed or changed in the new dump file compared to the old one.
+pub fn print_diff(old_file: &Path, new_file: &Path, printer: &Printer) -> Result<()> {
+    let old = dumpfile::read_dump(old_file)?.operations;
+    let new = dumpfile::read_dump(new_file)?.operations;
+    let changes = diff(&old, &new);
+    let colors = &printer.colors;
+
+    let (mut added, mut removed, mut changed) = (0, 0, 0);
+    for change in &changes {
+        let line = match change {
+            Change::Added { source, target } => {
+                added += 1;
+                colors
+                    .target
+                    .paint(format!("+ {} -> {}", source.display(), target.display()))
+            }
+            Change::Removed { source, target } => {
+                removed += 1;
+                colors
+                    .error
+                    .paint(format!("- {} -> {}", source.display(), target.display()))
+            }
+            Change::Changed {
+                source,
+                old_target,
+                new_target,
+            } => {
+                changed += 1;
+                colors.warn.paint(format!(
+                    "~ {} -> {} (was {})",
+                    source.display(),
+                    new_target.display(),
+                    old_target.display()
+                ))
+            }
+        };
+        printer.print(&line.to_string());
+    }
+
+    printer.print(&format!(
+        "{}{} added, {} removed, {} changed, {} unchanged",
+        colors.info.paint("Plan diff: "),
+        added,
+        removed,
+        changed,
+        new.len() - added - changed
+    ));
+    Ok(())
+}
+
+/// Get a content hash of the plan, `sha256:` followed by the hex digest. Operations are hashed in
+/// order as NUL separated source, target, symlink content and tag, empty when missing, so the same
+/// plan always gets the same hash and any change that alters what is renamed, or how, gets a
+/// different one.
+pub fn hash(operations: &[Operation]) -> String {
+    let mut hasher = Sha256::new();
+    for operation in operations {
+        let link = operation.link.as_deref().unwrap_or(Path::new(""));
+        for path in [&*operation.source, &*operation.target, link] {
+            hasher.update(path.as_os_str().as_encoded_bytes());
+            hasher.update([0]);
+        }
+        hasher.update(operation.tag.map_or("", |tag| tag.name()));
+        hasher.update([0]);
+    }
+    let digest: String = hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+    format!("sha256:{}", digest)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::solver::Tag;
+
+    fn operations(pairs: &[(&str, &str)]) -> Vec<Operation> {
+        pairs
+            .iter()
+            .map(|(source, target)| Operation {
+                source: PathBuf::from(source),
+                target: PathBuf::from(target),
+                tag: None,
+                link: None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn plan_diff() {
+        let old = operations(&[("a", "a1"), ("b", "b1"), ("c", "c1")]);
+        let new = operations(&[("d", "d1"), ("c", "c2"), ("a", "a1")]);
+        assert_eq!(
+            diff(&old, &new),
+            vec![
+                Change::Removed {
+                    source: PathBuf::from("b"),
+                    target: PathBuf::from("b1"),
+                },
+                Change::Changed {
+                    source: PathBuf::from("c"),
+                    old_target: PathBuf::from("c1"),
+                    new_target: PathBuf::from("c2"),
+                },
+                Change::Added {
+                    source: PathBuf::from("d"),
+                    target: PathBuf::from("d1"),
+                },
+            ]
+        );
+        assert!(diff(&old, &old).is_empty());
+    }
+
+    #[test]
+    fn plan_hash() {
+        assert_eq!(
+            hash(&[]),
+            "sha256:e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+
+        let plan = operations(&[("a", "a1"), ("b", "b1")]);
+        assert_eq!(hash(&plan), hash(&plan.clone()));
+        assert!(hash(&plan).starts_with("sha256:"));
+        // Order and path boundaries are part of the plan
+        assert_ne!(hash(&plan), hash(&operations(&[("b", "b1"), ("a", "a1")])));
+        assert_ne!(
+            hash(&operations(&[("a", "b/c")])),
+            hash(&operations(&[("a/b", "c")]))
+        );
+        // Symlink contents and tags change what is applied
+        let mut changed = plan.clone();
+        changed[0].link = Some(PathBuf::from("a.txt"));
+        assert_ne!(hash(&plan), hash(&changed));
+        let mut tagged = plan.clone();
+        tagged[0].tag = Some(Tag::Never);
+        assert_ne!(hash(&plan), hash(&tagged));
+    }
+}
@@ -0,0 +1,150 @@
+use std::io::Write;
+use std::time::{Duration, Instant};
+
+/// Width of the progress bar, without counters
+const BAR_WIDTH: usize = 30;
+
+/// How progress is displayed while renaming
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Mode {
+    /// Bar redrawn in place, for terminals
+    Bar,
+    /// One `processed/total` line at a time with the run ID, without control characters, for logs
+    Plain,
+}
+
+impl Mode {
+    pub fn from_str(mode: &str) -> Result<Mode, String> {
+        match mode {
+            "bar" => Ok(Mode::Bar),
+            "plain" => Ok(Mode::Plain),
+            _ => Err(format!(
+                "Unknown progress mode '{}', use bar or plain",
+                mode
+            )),
+        }
+    }
+
+    /// Minimum time between two updates
+    fn interval(&self) -> Duration {
+        match self {
+            Mode::Bar => Duration::from_millis(100),
+            Mode::Plain => Duration::from_secs(1),
+        }
+    }
+}
+
+/// Progress of a batch of operations. Updates are rate-limited, except the last one.
+pub struct Progress {
+    mode: Mode,
+    total: usize,
+    run_id: String,
+    interval: Duration,
+    last_update: Option<Instant>,
+    /// A bar was drawn without ending its line
+    unfinished_bar: bool,
+}
+
+impl Progress {
+    pub fn new(mode: Mode, total: usize, run_id: &str) -> Progress {
+        Progress {
+            mode,
+            total,
+            run_id: run_id.to_string(),
+            interval: mode.interval(),
+            last_update: None,
+            unfinished_bar: false,
+        }
+    }
+
+    /// Report the number of processed operations if the last update is old enough.
+    pub fn update<W: Write>(&mut self, processed: usize, output: &mut W) {
+        let now = Instant::now();
+        let is_due = self
+            .last_update
+            .is_none_or(|last| now.duration_since(last) >= self.interval);
+        if !is_due && processed < self.total {
+            return;
+        }
+        self.last_update = Some(now);
+
+        let percent = (processed * 100).checked_div(self.total).unwrap_or(100);
+        // Progress is informative only, failing to print it must not stop renaming
+        let _ = match self.mode {
+            Mode::Plain => writeln!(
+                output,
+                "[{}] Progress: {}/{} ({}%)",
+                self.run_id, processed, self.total, percent
+            ),
+            Mode::Bar => {
+                let filled = (processed * BAR_WIDTH)
+                    .checked_div(self.total)
+                    .unwrap_or(BAR_WIDTH);
+                self.unfinished_bar = processed < self.total;
+                let end = if self.unfinished_bar { "" } else { "\n" };
+                write!(
+                    output,
+                    "\r[{}{}] {}/{} ({}%){}",
+                    "#".repeat(filled),
+                    "-".repeat(BAR_WIDTH - filled),
+                    processed,
+                    self.total,
+                    percent,
+                    end
+                )
+            }
+        };
+        let _ = output.flush();
+    }
+}
+
+impl Drop for Progress {
+    /// End the line of a bar interrupted by an error, so the error is printed on its own line.
+    fn drop(&mut self) {
+        if self.unfinished_bar {
+            eprintln!();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn plain_progress() {
+        let mut output = Vec::new();
+        let mut progress = Progress::new(Mode::Plain, 4, "test");
+        for processed in 1..=4 {
+            progress.update(processed, &mut output);
+        }
+        // Intermediate updates are skipped within the interval, the last one is always printed
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "[test] Progress: 1/4 (25%)\n[test] Progress: 4/4 (100%)\n"
+        );
+
+        let mut output = Vec::new();
+        let mut progress = Progress::new(Mode::Plain, 2, "test");
+        progress.interval = Duration::ZERO;
+        progress.update(1, &mut output);
+        progress.update(2, &mut output);
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "[test] Progress: 1/2 (50%)\n[test] Progress: 2/2 (100%)\n"
+        );
+    }
+
+    #[test]
+    fn bar_progress() {
+        let mut output = Vec::new();
+        let mut progress = Progress::new(Mode::Bar, 2, "test");
+        progress.interval = Duration::ZERO;
+        progress.update(1, &mut output);
+        progress.update(2, &mut output);
+        let output = String::from_utf8(output).unwrap();
+        let half = format!("\r[{}{}] 1/2 (50%)", "#".repeat(15), "-".repeat(15));
+        assert!(output.starts_with(&half));
+        assert!(output.ends_with(&format!("\r[{}] 2/2 (100%)\n", "#".repeat(30))));
+    }
+}
@@ -1,55 +1,317 @@
 use any_ascii::any_ascii;
+use crate::backups::BackupIndex;
 use crate::config::{Config, ReplaceMode, RunMode};
+use crate::driver::FsDriver;
 use crate::dumpfile;
 use crate::error::*;
-use crate::fileutils::{cleanup_paths, create_backup, get_paths};
+use crate::fileutils::{
+    canonicalize_parent, cleanup_paths, create_backup, get_paths, is_stdin_consumed,
+    normalize_path, PathList,
+};
+use crate::filters::TimeSource;
+use crate::importer;
+use crate::inuse::in_use_paths;
+use crate::lock::SessionLock;
+use crate::metadata::{MetadataCache, Prefetch};
+use crate::output::Layout;
+use crate::pathlen;
+use crate::plan;
+use crate::progress::Progress;
+use crate::rules::glob_match;
+use crate::safety::open_terminal;
+use crate::simulate;
 use crate::solver;
+use crate::space;
+use crate::stems;
+use crate::template::{Group, Template};
+use crate::warning::Warning;
+use chrono::{NaiveDateTime, Timelike};
+use regex::Captures;
 use solver::{Operation, Operations, RenameMap};
-use std::fs;
+use std::cell::{Cell, RefCell};
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BinaryHeap, HashMap};
+use std::io;
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Extended attribute storing the origin of renamed files
+pub const XATTR_NAME: &str = "user.rnr.origin";
 
 pub struct Renamer {
     config: Arc<Config>,
+    deadline: Option<Instant>,
+    /// Dump file and the operations stored in it
+    dump: RefCell<Option<(PathBuf, Operations)>>,
+    /// Metadata read while planning
+    cache: MetadataCache,
+    /// Slowest applied operations, in a min-heap bounded by `--slowest`
+    timings: RefCell<BinaryHeap<Reverse<Timing>>>,
+    /// Locks on the renamed directories, held until the renamer is dropped
+    lock: RefCell<Option<SessionLock>>,
+    /// Placeholders without a value for the path being replaced
+    missing: RefCell<Vec<String>>,
+    /// Backups found while rotating them
+    backups: RefCell<BackupIndex>,
+    /// Number of operations applied by the last batch
+    applied: Cell<usize>,
+    /// Operations of the last batch that failed or were skipped
+    failures: RefCell<Vec<Failure>>,
+}
+
+/// Origin of a renamed file, stored as JSON in an extended attribute
+#[derive(Serialize)]
+struct Origin<'a> {
+    source: PathBuf,
+    run_id: &'a str,
+    date: String,
+}
+
+/// Time spent applying an operation, including its backup. Timings are ordered by duration first.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Timing {
+    pub duration: Duration,
+    pub source: PathBuf,
+    pub target: PathBuf,
+}
+
+/// Operation that could not be applied and why
+#[derive(Clone, Debug)]
+pub struct Failure {
+    pub source: PathBuf,
+    pub target: PathBuf,
+    pub message: String,
 }
 
 impl Renamer {
     pub fn new(config: &Arc<Config>) -> Result<Renamer> {
         Ok(Renamer {
             config: config.clone(),
+            deadline: config.timeout.map(|timeout| Instant::now() + timeout),
+            dump: RefCell::new(None),
+            cache: MetadataCache::with_driver(config.driver.clone()),
+            timings: RefCell::new(BinaryHeap::new()),
+            lock: RefCell::new(None),
+            missing: RefCell::new(Vec::new()),
+            backups: RefCell::new(BackupIndex::default()),
+            applied: Cell::new(0),
+            failures: RefCell::new(Vec::new()),
         })
     }
 
     /// Process path batch
     pub fn process(&self) -> Result<Operations> {
         let operations = match self.config.run_mode {
-            RunMode::Simple(_) | RunMode::Recursive { .. } => {
+            RunMode::Simple(_) | RunMode::Contents(_) | RunMode::Recursive { .. } => {
                 // Get paths
-                let mut input_paths = get_paths(&self.config.run_mode);
+                let mut input_paths = get_paths(&self.config.run_mode, &self.cache);
+                self.lock(&input_paths)?;
+
+                // Read metadata ahead with parallel readers, slow network shares benefit the most.
+                // Each step only reads what it uses for the paths kept by the previous ones.
+                let prefetch = |paths: &[PathBuf], prefetch: Prefetch| {
+                    let concurrency = self.config.io_concurrency;
+                    if concurrency > 1 && prefetch != Prefetch::default() {
+                        self.cache.prefetch(paths, prefetch, concurrency);
+                    }
+                };
+                let checks = Prefetch {
+                    symlink_metadata: true,
+                    ..Prefetch::default()
+                };
+                let unlisted: PathList = input_paths
+                    .iter()
+                    .filter(|path| !self.cache.is_listed(path))
+                    .cloned()
+                    .collect();
+                prefetch(&unlisted, checks);
 
                 // Remove directories and on existing paths from the list
-                cleanup_paths(&mut input_paths, self.config.dirs);
+                cleanup_paths(&mut input_paths, self.config.dirs, &self.cache);
+
+                // Keep only paths selected by time filters
+                let filters = self.filter_prefetch();
+                if let Some(time_filter) = &self.config.time_filter {
+                    prefetch(&input_paths, filters);
+                    input_paths.retain(|path| time_filter.matches(path, &self.cache));
+                }
+
+                // Keep only paths whose captures are within the ranges
+                let ranges = &self.config.capture_ranges;
+                match &self.config.replace_mode {
+                    ReplaceMode::RegExp { expression, .. } if !ranges.is_empty() => {
+                        input_paths.retain(|path| {
+                            let file_name = path.file_name().and_then(|name| name.to_str());
+                            file_name.is_some_and(|file_name| {
+                                ranges
+                                    .iter()
+                                    .all(|range| range.matches(file_name, expression))
+                            })
+                        });
+                    }
+                    _ => {}
+                }
 
-                // Relate original names with their targets
-                let rename_map = self.get_rename_map(&input_paths)?;
+                if self.config.skip_in_use {
+                    self.remove_in_use(&mut input_paths);
+                }
 
-                // Solve renaming operation ordering to avoid conflicts
-                solver::solve_rename_order(&rename_map)?
+                // Values used by replacements are only read for the paths they rename
+                let replacements = self.replacement_prefetch();
+                let replacements = Prefetch {
+                    metadata: replacements.metadata && !filters.metadata,
+                    exif_dates: replacements.exif_dates && !filters.exif_dates,
+                    ..Prefetch::default()
+                };
+                if replacements != Prefetch::default() {
+                    let replace_mode = &self.config.replace_mode;
+                    let matched: PathList = input_paths
+                        .iter()
+                        .filter(|path| is_matched(path, replace_mode))
+                        .cloned()
+                        .collect();
+                    prefetch(&matched, replacements);
+                }
+
+                // Relate original names with their targets and solve renaming operation ordering
+                // to avoid conflicts, each pass is planned over the names of the previous one
+                let mut operations = match &self.config.replace_mode {
+                    ReplaceMode::Passes(passes) => self.plan_passes(input_paths, passes)?,
+                    replace_mode => {
+                        let rename_map =
+                            self.get_rename_map(&input_paths, replace_mode, &self.cache)?;
+                        solver::solve_rename_order(&rename_map, &self.cache)?
+                    }
+                };
+
+                // Keep a random sample to spot-check the pattern, with the whole chains of the
+                // sampled renames so the sample can be applied on its own
+                if let Some(sample) = &self.config.sample {
+                    let planned = operations.len();
+                    operations = sample.select(operations);
+                    let info = &self.config.printer.colors.info;
+                    self.config.printer.print(&format!(
+                        "{}",
+                        info.paint(format!(
+                            "Sample of {} of {} renames, repeat it with --sample-seed {}",
+                            operations.len(),
+                            planned,
+                            sample.seed
+                        ))
+                    ));
+                }
+
+                let stats = self.cache.stats();
+                self.config.printer.debug(&format!(
+                    "Metadata cache: {} hits, {} misses",
+                    stats.hits, stats.misses
+                ));
+                operations
             }
-            RunMode::FromFile { ref path, undo } => {
+            RunMode::FromFile {
+                ref path,
+                undo,
+                ref tags,
+                exclude_newer,
+                format,
+            } => {
                 // Read operations from file
-                let operations = dumpfile::read_from_file(&PathBuf::from(path))?;
+                let dump = importer::read(&PathBuf::from(path), format)?;
+                let mut operations = dump.operations;
+                let paths: PathList = operations
+                    .iter()
+                    .flat_map(|operation| [operation.source.clone(), operation.target.clone()])
+                    .collect();
+                self.lock(&paths)?;
                 if undo {
-                    solver::revert_operations(&operations)?
-                } else {
-                    operations
+                    operations = solver::revert_operations(&operations)?;
+                }
+                let planned = operations.clone();
+                if let Some(tags) = tags {
+                    operations.retain(|operation| tags.contains(&operation.tag));
                 }
+                if undo && exclude_newer {
+                    self.remove_newer(&mut operations, dump.date, path)?;
+                }
+                // Partial plans keep their order, removed operations must not break the rest
+                if operations.len() < planned.len() {
+                    solver::check_subset(&planned, &operations)?;
+                }
+                operations
             }
+            // Server mode generates operations per request and review mode does not rename
+            RunMode::Serve
+            | RunMode::Review(_)
+            | RunMode::PlanDiff { .. }
+            | RunMode::DumpSpec
+            | RunMode::ValidateTemplate { .. }
+            | RunMode::PruneBackups { .. } => Operations::new(),
         };
+        self.finish(operations)
+    }
+
+    /// Dump and report planned operations
+    fn finish(&self, mut operations: Operations) -> Result<Operations> {
+        // Record symlinks, so they are displayed and dumped with the path they point to, and
+        // restored as they were
+        for operation in operations.iter_mut() {
+            if operation.link.is_none() {
+                operation.link = self.config.driver.read_link(&operation.source).ok();
+            }
+        }
+        if self.config.collide_ignore_ext {
+            stems::check(&operations, self.config.driver.as_ref())?;
+        }
+        let safety = &self.config.safety;
+        let plan_hash = plan::hash(&operations);
+        if !operations.is_empty() {
+            let info = &self.config.printer.colors.info;
+            self.config
+                .printer
+                .print(&format!("{}{}", info.paint("Plan hash: "), plan_hash));
+        }
+        safety.check(&operations)?;
+        self.lock_operations(&operations)?;
+        // Backups copy files, fail before renaming anything if they do not fit
+        if self.config.force && self.config.backup {
+            space::check_backups(&operations, self.config.driver.as_ref())?;
+        }
+        // Warn before renaming anything, instead of failing partway through
+        for warning in pathlen::check(&operations, &self.cache, self.config.driver.as_ref()) {
+            self.config
+                .printer
+                .print_warning(Warning::NameTooLong, &warning);
+        }
+        // Confirm before dumping, so nothing is left behind when declined
+        if safety.confirm && self.config.force && !operations.is_empty() {
+            let printer = &self.config.printer;
+            let layout = printer.layout(&operations);
+            printer.print_layout_header(&layout);
+            for operation in &operations {
+                printer.print_operation(operation, &layout);
+            }
+            if is_stdin_consumed() {
+                safety.ask(operations.len(), open_terminal()?, io::stdout())?;
+            } else {
+                safety.ask(operations.len(), io::stdin().lock(), io::stdout())?;
+            }
+        }
 
         // Dump operations into a file if required
         if self.config.dump {
-            dumpfile::dump_to_file(&operations)?;
+            // Paths are resolved before renaming anything, parent directories may be renamed too
+            let dump_operations = if self.config.abs {
+                absolute_operations(&operations)?
+            } else {
+                operations.clone()
+            };
+            let dump_file = dumpfile::dump_to_file(&dump_operations, &self.config.run_id)?;
+            *self.dump.borrow_mut() = Some((dump_file, dump_operations));
+        }
+
+        if let Some(report) = &self.config.report {
+            report.write(&operations, self.config.force, &self.config.run_id)?;
         }
 
         Ok(operations)
@@ -57,44 +319,551 @@ impl Renamer {
 
     /// Rename an operation batch
     pub fn batch_rename(&self, operations: Operations) -> Result<()> {
-        for operation in operations {
-            self.rename(&operation)?;
+        let printer = &self.config.printer;
+        let layout = printer.layout(&operations);
+        printer.print_layout_header(&layout);
+
+        let mut progress = match self.config.progress {
+            Some(mode) if self.config.force => {
+                Some(Progress::new(mode, operations.len(), &self.config.run_id))
+            }
+            _ => None,
+        };
+
+        // Operations whose errors were downgraded to warnings, or skipped because they depend on
+        // one of them
+        let mut ignored = Vec::new();
+        self.applied.set(0);
+        self.failures.borrow_mut().clear();
+        for (index, operation) in operations.iter().enumerate() {
+            if self.is_timed_out() {
+                self.dump_applied(index, &ignored)?;
+                return Err(Error {
+                    kind: ErrorKind::Timeout,
+                    value: Some(format!(
+                        "stopped after {} of {} operations, next was {} -> {}",
+                        index,
+                        operations.len(),
+                        operation.source.display(),
+                        operation.target.display()
+                    )),
+                });
+            }
+            let start = Instant::now();
+            let mut failed = ignored.iter().map(|&failed| &operations[failed]);
+            match failed.find(|failed| depends_on(operation, failed)) {
+                Some(failed) => {
+                    let message = format!(
+                        "depends on the failed {} -> {}",
+                        failed.source.display(),
+                        failed.target.display()
+                    );
+                    printer.print_warning(
+                        Warning::IgnoredError,
+                        &format!(
+                            "Skipped {} -> {}, it {}",
+                            operation.source.display(),
+                            operation.target.display(),
+                            message
+                        ),
+                    );
+                    self.record_failure(operation, message);
+                    ignored.push(index);
+                }
+                None => match self.rename(operation, &layout) {
+                    Ok(()) => self.applied.set(self.applied.get() + 1),
+                    Err(err) if self.is_error_ignored(&operation.source) => {
+                        let message = format!(
+                            "{}{}",
+                            err.description(),
+                            err.value.as_deref().unwrap_or("")
+                        );
+                        printer.print_warning(
+                            Warning::IgnoredError,
+                            &format!("Ignored error: {}", message),
+                        );
+                        self.record_failure(operation, message);
+                        ignored.push(index);
+                    }
+                    Err(err) => {
+                        let message = format!(
+                            "{}{}",
+                            err.description(),
+                            err.value.as_deref().unwrap_or("")
+                        );
+                        self.record_failure(operation, message);
+                        return Err(err);
+                    }
+                },
+            }
+            if let Some(slowest) = self.config.slowest {
+                if self.config.force && ignored.last() != Some(&index) {
+                    let mut timings = self.timings.borrow_mut();
+                    timings.push(Reverse(Timing {
+                        duration: start.elapsed(),
+                        source: operation.source.clone(),
+                        target: operation.target.clone(),
+                    }));
+                    if timings.len() > slowest {
+                        timings.pop();
+                    }
+                }
+            }
+            if let Some(progress) = progress.as_mut() {
+                progress.update(index + 1, &mut io::stderr());
+            }
+        }
+
+        if !ignored.is_empty() {
+            self.dump_applied(operations.len(), &ignored)?;
+            printer.print_warning(
+                Warning::IgnoredError,
+                &format!(
+                    "{} of {} operations failed or were skipped and were ignored",
+                    ignored.len(),
+                    operations.len()
+                ),
+            );
+        }
+        Ok(())
+    }
+
+    /// Remember an operation of the batch that could not be applied.
+    fn record_failure(&self, operation: &Operation, message: String) {
+        self.failures.borrow_mut().push(Failure {
+            source: operation.source.clone(),
+            target: operation.target.clone(),
+            message,
+        });
+    }
+
+    /// Number of operations applied by the last batch.
+    pub fn applied(&self) -> usize {
+        self.applied.get()
+    }
+
+    /// Operations of the last batch that failed, or were skipped because they depend on a failed
+    /// one.
+    pub fn failures(&self) -> Vec<Failure> {
+        self.failures.borrow().clone()
+    }
+
+    /// Keep only the operations applied before the given index in the dump file, without the
+    /// failed ones, to be able to undo them.
+    fn dump_applied(&self, end: usize, failed: &[usize]) -> Result<()> {
+        if let Some((dump_file, dump_operations)) = self.dump.borrow().as_ref() {
+            let applied: Operations = dump_operations[..end]
+                .iter()
+                .enumerate()
+                .filter(|(index, _)| !failed.contains(index))
+                .map(|(_, operation)| operation.clone())
+                .collect();
+            dumpfile::write_to_file(dump_file, &applied, Some(&self.config.run_id))?;
+        }
+        Ok(())
+    }
+
+    /// Check if errors renaming this path are downgraded to warnings. Globs are matched against
+    /// the file name, or against the whole path if they contain a separator.
+    fn is_error_ignored(&self, path: &Path) -> bool {
+        let file_name = path.file_name().map(|name| name.to_string_lossy());
+        let full_path = path.to_string_lossy().replace('\\', "/");
+        self.config.ignore_errors.iter().any(|glob| {
+            if glob.contains('/') {
+                glob_match(glob, &full_path)
+            } else {
+                file_name
+                    .as_ref()
+                    .is_some_and(|name| glob_match(glob, name))
+            }
+        })
+    }
+
+    /// Get the slowest applied operations, up to the configured number, slowest first.
+    pub fn slowest(&self) -> Vec<Timing> {
+        let timings = self.timings.borrow().clone().into_sorted_vec();
+        timings.into_iter().map(|Reverse(timing)| timing).collect()
+    }
+
+    /// Print the slowest applied operations and how long they took.
+    pub fn print_slowest(&self) {
+        let slowest = self.slowest();
+        if slowest.is_empty() {
+            return;
+        }
+        let printer = &self.config.printer;
+        let colors = &printer.colors;
+        printer.print(&format!("{}", colors.info.paint("Slowest operations:")));
+        for timing in slowest {
+            printer.print(&format!(
+                "{:>10}  {} -> {}",
+                format!("{:.1?}", timing.duration),
+                colors.source.paint(timing.source.display().to_string()),
+                colors.target.paint(timing.target.display().to_string())
+            ));
+        }
+    }
+
+    /// Plan passes one after another, each one over the names produced by the previous one. Passes
+    /// are applied to an in-memory snapshot, so every pass is solved against the renamed tree. All
+    /// the operations are returned together, so they are dumped and undone as a single unit.
+    fn plan_passes(&self, mut paths: PathList, passes: &[ReplaceMode]) -> Result<Operations> {
+        let roots = simulate::roots(&self.config.run_mode).map_err(|err| Error {
+            kind: ErrorKind::ReadFile,
+            value: Some(err),
+        })?;
+        let snapshot = Arc::new(simulate::snapshot(
+            &roots,
+            &self.config.run_mode,
+            self.config.driver.as_ref(),
+        ));
+
+        let mut operations = Operations::new();
+        for (index, pass) in passes.iter().enumerate() {
+            // Metadata is read from the snapshot, where renamed paths exist
+            let cache = MetadataCache::with_driver(snapshot.clone());
+            let rename_map = self.get_rename_map(&paths, pass, &cache)?;
+            let pass_operations = solver::solve_rename_order(&rename_map, &cache)?;
+            self.config.printer.debug(&format!(
+                "Pass {}: {} operations",
+                index + 1,
+                pass_operations.len()
+            ));
+
+            for operation in &pass_operations {
+                snapshot
+                    .rename(&operation.source, &operation.target)
+                    .map_err(|err| Error {
+                        kind: ErrorKind::Rename,
+                        value: Some(format!(
+                            "{} -> {}\npass {}: {}",
+                            operation.source.display(),
+                            operation.target.display(),
+                            index + 1,
+                            err
+                        )),
+                    })?;
+                // Renamed paths and their descendants are planned with the new names
+                for path in paths.iter_mut() {
+                    if let Ok(relative) = path.strip_prefix(&operation.source) {
+                        *path = if relative.as_os_str().is_empty() {
+                            operation.target.clone()
+                        } else {
+                            operation.target.join(relative)
+                        };
+                    }
+                }
+            }
+            operations.extend(pass_operations);
+        }
+        Ok(operations)
+    }
+
+    /// Get the values read by the time filter, to read them ahead.
+    fn filter_prefetch(&self) -> Prefetch {
+        let time_source = self.config.time_filter.as_ref().map(|filter| filter.source);
+        Prefetch {
+            metadata: time_source == Some(TimeSource::Mtime),
+            exif_dates: time_source == Some(TimeSource::Exif),
+            ..Prefetch::default()
+        }
+    }
+
+    /// Get the values read by the replacement and the group key, to read them ahead.
+    fn replacement_prefetch(&self) -> Prefetch {
+        let uses = |name: &str| {
+            uses_placeholder(&self.config.replace_mode, name)
+                || self.config.group_by.as_ref().is_some_and(|key| {
+                    key.placeholders()
+                        .iter()
+                        .any(|placeholder| placeholder == name)
+                })
+        };
+        Prefetch {
+            metadata: uses("mtime"),
+            exif_dates: uses("exif.date"),
+            ..Prefetch::default()
+        }
+    }
+
+    /// Lock the directories of the paths against other runs before planning, when renaming for real
+    fn lock(&self, paths: &[PathBuf]) -> Result<()> {
+        if self.config.force && self.config.simulation.is_none() {
+            let lock = SessionLock::acquire(paths, self.config.wait_lock, &self.config.printer)?;
+            *self.lock.borrow_mut() = Some(lock);
         }
         Ok(())
     }
 
-    /// Replace file name matches in the given path using stored config.
-    fn replace_match(&self, path: &Path) -> PathBuf {
+    /// Lock the directories of the sources and targets of the operations, adding them to the
+    /// locks taken while planning, so runs moving paths into the same directory exclude each
+    /// other too.
+    pub fn lock_operations(&self, operations: &[Operation]) -> Result<()> {
+        let paths: PathList = operations
+            .iter()
+            .flat_map(|operation| [operation.source.clone(), operation.target.clone()])
+            .collect();
+        let mut lock = self.lock.borrow_mut();
+        match lock.as_mut() {
+            Some(lock) => lock.extend(&paths, self.config.wait_lock, &self.config.printer),
+            None => {
+                drop(lock);
+                self.lock(&paths)
+            }
+        }
+    }
+
+    /// Remove paths opened by other processes from the list warning about each one of them
+    fn remove_in_use(&self, paths: &mut PathList) {
+        let printer = &self.config.printer;
+        match in_use_paths(paths) {
+            Ok(in_use) => paths.retain(|path| {
+                if in_use.contains(path) {
+                    printer.print_warning(
+                        Warning::InUse,
+                        &format!("Skipping file in use {}", path.display()),
+                    );
+                    false
+                } else {
+                    true
+                }
+            }),
+            Err(err) => printer.print_warning(
+                Warning::InUse,
+                &format!("Cannot skip files in use, {}", err),
+            ),
+        }
+    }
+
+    /// Remove operations whose source was modified after the dump date warning about each one
+    fn remove_newer(
+        &self,
+        operations: &mut Operations,
+        date: Option<NaiveDateTime>,
+        dump_file: &str,
+    ) -> Result<()> {
+        let date = match date {
+            Some(date) => date,
+            None => {
+                return Err(Error {
+                    kind: ErrorKind::JsonParse,
+                    value: Some(format!("{}\nmissing or invalid date", dump_file)),
+                })
+            }
+        };
+        let printer = &self.config.printer;
+        let mut targets: HashMap<&Path, Vec<usize>> = HashMap::new();
+        for (index, operation) in operations.iter().enumerate() {
+            targets.entry(&operation.target).or_default().push(index);
+        }
+        let newer: Vec<bool> = (0..operations.len())
+            .map(|index| {
+                // Sources renamed by earlier operations are checked where they are now
+                let path = current_path(operations, index, &targets);
+                // Dump dates are stored with seconds precision
+                let modified = TimeSource::Mtime
+                    .timestamp(&path, &self.cache)
+                    .and_then(|modified| modified.with_nanosecond(0));
+                let newer = modified.is_some_and(|modified| modified > date);
+                if newer {
+                    printer.print_warning(
+                        Warning::ModifiedAfterDump,
+                        &format!("Skipping path modified after the dump {}", path.display()),
+                    );
+                }
+                newer
+            })
+            .collect();
+        let mut newer = newer.into_iter();
+        operations.retain(|_| !newer.next().unwrap_or(false));
+        Ok(())
+    }
+
+    /// Check if the time budget of the run is exhausted
+    fn is_timed_out(&self) -> bool {
+        self.deadline
+            .map(|deadline| Instant::now() >= deadline)
+            .unwrap_or(false)
+    }
+
+    /// Replace file name matches in the given path using the given replace mode.
+    fn replace_match(
+        &self,
+        path: &Path,
+        replace_mode: &ReplaceMode,
+        group: Option<Group>,
+        cache: &MetadataCache,
+    ) -> PathBuf {
         let file_name = path.file_name().unwrap().to_str().unwrap();
         let parent = path.parent();
 
-        let target_name = match &self.config.replace_mode {
+        let target_name = match replace_mode {
             ReplaceMode::RegExp {
                 expression,
                 replacement,
                 limit,
             } => expression
-                .replacen(file_name, *limit, &replacement[..])
+                .replacen(file_name, *limit, |captures: &Captures| {
+                    self.render(replacement, captures, path, group, cache)
+                })
                 .to_string(),
+            ReplaceMode::Rules(rules) => {
+                // Rules are scoped by the original name and chained in order
+                rules.iter().filter(|rule| rule.matches(file_name)).fold(
+                    file_name.to_string(),
+                    |name, rule| {
+                        rule.expression
+                            .replacen(&name, rule.limit, |captures: &Captures| {
+                                self.render(&rule.replacement, captures, path, group, cache)
+                            })
+                            .to_string()
+                    },
+                )
+            }
+            // Passes are planned one by one, a single path goes through all of them
+            ReplaceMode::Passes(passes) => {
+                return passes.iter().fold(path.to_path_buf(), |path, pass| {
+                    self.replace_match(&path, pass, group, cache)
+                });
+            }
             ReplaceMode::ToASCII => any_ascii(file_name),
         };
 
+        // Separators in replacements are mapped to the platform ones
         match parent {
-            None => PathBuf::from(target_name),
-            Some(path) => path.join(Path::new(&target_name)),
+            None => normalize_path(Path::new(&target_name)),
+            Some(path) => normalize_path(&path.join(Path::new(&target_name))),
         }
     }
 
+    /// Render a replacement, recording its placeholders without a value for the path
+    fn render(
+        &self,
+        replacement: &Template,
+        captures: &Captures,
+        path: &Path,
+        group: Option<Group>,
+        cache: &MetadataCache,
+    ) -> String {
+        let rendered = replacement.render_checked(captures, path, group, cache);
+        self.missing.borrow_mut().extend(rendered.missing);
+        rendered.text
+    }
+
+    /// Number the paths matched by the replace mode within their groups. Groups are sorted by the
+    /// `--group-by` key rendered for each path, all paths are in a single group without it.
+    fn group_paths(
+        &self,
+        paths: &[PathBuf],
+        replace_mode: &ReplaceMode,
+        cache: &MetadataCache,
+    ) -> HashMap<PathBuf, Group> {
+        let mut keys: BTreeMap<String, Vec<&PathBuf>> = BTreeMap::new();
+        for path in paths {
+            let file_name = match path.file_name().and_then(|name| name.to_str()) {
+                Some(file_name) => file_name,
+                None => continue,
+            };
+            let key = match replace_mode {
+                ReplaceMode::RegExp { expression, .. } => match expression.captures(file_name) {
+                    Some(captures) => match &self.config.group_by {
+                        Some(group_by) => group_by.render(&captures, path, None, cache),
+                        None => String::new(),
+                    },
+                    None => continue,
+                },
+                ReplaceMode::Rules(rules)
+                    if !rules.iter().any(|rule| {
+                        rule.matches(file_name) && rule.expression.is_match(file_name)
+                    }) =>
+                {
+                    continue
+                }
+                _ => String::new(),
+            };
+            keys.entry(key).or_default().push(path);
+        }
+
+        // Positions among all the paths, so paths skipped later leave their number unused
+        let mut sorted: Vec<&PathBuf> = paths.iter().collect();
+        sorted.sort();
+        let positions: HashMap<&PathBuf, usize> = sorted
+            .into_iter()
+            .enumerate()
+            .map(|(idx, path)| (path, idx + 1))
+            .collect();
+
+        let mut groups = HashMap::new();
+        for (index, mut group_paths) in keys.into_values().enumerate() {
+            group_paths.sort();
+            for (seq, path) in group_paths.into_iter().enumerate() {
+                let group = Group {
+                    index: index + 1,
+                    seq: seq + 1,
+                    idx: positions[path],
+                };
+                groups.insert(path.clone(), group);
+            }
+        }
+        groups
+    }
+
     /// Get hash map containing all replacements to be done
-    fn get_rename_map(&self, paths: &[PathBuf]) -> Result<RenameMap> {
+    fn get_rename_map(
+        &self,
+        paths: &[PathBuf],
+        replace_mode: &ReplaceMode,
+        cache: &MetadataCache,
+    ) -> Result<RenameMap> {
         let printer = &self.config.printer;
         let colors = &printer.colors;
 
         let mut rename_map = RenameMap::new();
         let mut error_string = String::new();
+        let groups = if has_counters(replace_mode) {
+            self.group_paths(paths, replace_mode, cache)
+        } else {
+            HashMap::new()
+        };
 
-        for path in paths {
-            let target = self.replace_match(path);
+        for (index, path) in paths.iter().enumerate() {
+            if self.is_timed_out() {
+                return Err(Error {
+                    kind: ErrorKind::Timeout,
+                    value: Some(format!(
+                        "planning stopped after {} of {} paths",
+                        index,
+                        paths.len()
+                    )),
+                });
+            }
+            let mut target =
+                self.replace_match(path, replace_mode, groups.get(path).copied(), cache);
+            let mut missing = self.missing.take();
+            missing.sort();
+            missing.dedup();
+            for placeholder in missing {
+                printer.print_warning(
+                    Warning::MissingMetadata,
+                    &format!(
+                        "No {{{}}} value for {}, it is left empty",
+                        placeholder,
+                        path.display()
+                    ),
+                );
+            }
+            // Empty names would rename paths onto their parent directory
+            if target.file_name().is_none() || Some(target.as_path()) == path.parent() {
+                return Err(Error {
+                    kind: ErrorKind::EmptyName,
+                    value: Some(path.display().to_string()),
+                });
+            }
+            if let Some(dir) = &self.config.move_to {
+                if is_matched(path, replace_mode) {
+                    target = move_target(&target, dir);
+                }
+            }
             // Discard paths with no changes
             if target != *path {
                 if let Some(old_path) = rename_map.insert(target.clone(), path.clone()) {
@@ -125,31 +894,66 @@ impl Renamer {
 
     /// Rename path in the filesystem or simply print renaming information. Checks if target
     /// filename exists before renaming.
-    fn rename(&self, operation: &Operation) -> Result<()> {
+    fn rename(&self, operation: &Operation, layout: &Layout) -> Result<()> {
         let printer = &self.config.printer;
         let colors = &printer.colors;
+        let driver = self.config.driver.as_ref();
 
         if self.config.force {
             // Create a backup before actual renaming
             if self.config.backup {
-                match create_backup(&operation.source) {
-                    Ok(backup) => printer.print(&format!(
-                        "{} Backup created - {}",
-                        colors.info.paint("Info: "),
-                        colors.source.paint(format!(
-                            "{} -> {}",
-                            operation.source.display(),
-                            backup.display()
-                        ))
-                    )),
-                    Err(err) => {
-                        return Err(err);
+                let backup = create_backup(&operation.source, driver)?;
+                printer.print(&format!(
+                    "{} Backup created - {}",
+                    colors.info.paint("Info: "),
+                    colors.source.paint(format!(
+                        "{} -> {}",
+                        operation.source.display(),
+                        backup.display()
+                    ))
+                ));
+                if let Some(keep) = self.config.backup_keep {
+                    let mut index = self.backups.borrow_mut();
+                    for backup in index.rotate(&operation.source, &backup, keep, driver)? {
+                        printer.print(&format!(
+                            "{} Backup removed - {}",
+                            colors.info.paint("Info: "),
+                            colors.source.paint(backup.display().to_string())
+                        ));
                     }
                 }
             }
 
+            // Symlinks are renamed themselves, never the files they point to
+            if operation.link.is_some() && driver.read_link(&operation.source).is_err() {
+                return Err(Error {
+                    kind: ErrorKind::Rename,
+                    value: Some(format!(
+                        "{} -> {}\nsource is not a symlink anymore",
+                        operation.source.display(),
+                        operation.target.display()
+                    )),
+                });
+            }
+
+            // Create the directory of moved paths on first use
+            let move_dir = operation
+                .target
+                .parent()
+                .filter(|_| self.config.move_to.is_some());
+            if let Some(dir) = move_dir.filter(|dir| !dir.as_os_str().is_empty()) {
+                if driver.metadata(dir).is_err() {
+                    driver
+                        .create_dir_all(dir, self.config.dir_mode)
+                        .map_err(|err| Error {
+                            kind: ErrorKind::CreateFile,
+                            value: Some(format!("{}\n{}", dir.display(), err)),
+                        })?;
+                }
+            }
+
             // Rename paths in the filesystem
-            if let Err(err) = fs::rename(&operation.source, &operation.target) {
+            if let Err(err) = driver.rename(&operation.source, &operation.target) {
                 return Err(Error {
                     kind: ErrorKind::Rename,
                     value: Some(format!(
@@ -160,15 +964,175 @@ impl Renamer {
                     )),
                 });
             } else {
-                printer.print_operation(&operation.source, &operation.target);
+                printer.print_operation(operation, layout);
+            }
+
+            if let Some(link) = &operation.link {
+                self.restore_link(&operation.target, link)?;
+            } else if self.config.xattr_tag {
+                // Symlinks are not tagged, Linux does not allow user attributes on them
+                self.tag(operation);
             }
         } else {
             // Just print info in dry-run mode
-            printer.print_operation(&operation.source, &operation.target);
+            printer.print_operation(operation, layout);
         }
 
         Ok(())
     }
+
+    /// Record the origin of a renamed path in an extended attribute. Failures are warnings, the
+    /// path is already renamed.
+    fn tag(&self, operation: &Operation) {
+        let origin = Origin {
+            source: std::path::absolute(&operation.source)
+                .unwrap_or_else(|_| operation.source.clone()),
+            run_id: &self.config.run_id,
+            date: chrono::Local::now()
+                .format(dumpfile::DATE_FORMAT)
+                .to_string(),
+        };
+        let driver = self.config.driver.as_ref();
+        let result = serde_json::to_vec(&origin)
+            .map_err(|err| err.to_string())
+            .and_then(|value| {
+                driver
+                    .set_xattr(&operation.target, XATTR_NAME, &value)
+                    .map_err(|err| err.to_string())
+            });
+        if let Err(err) = result {
+            self.config.printer.print_warning(
+                Warning::XattrTag,
+                &format!(
+                    "Cannot tag {} with its origin: {}",
+                    operation.target.display(),
+                    err
+                ),
+            );
+        }
+    }
+
+    /// Point the symlink to the recorded content if it was changed after the dump was created
+    fn restore_link(&self, path: &Path, link: &Path) -> Result<()> {
+        let driver = self.config.driver.as_ref();
+        if driver.read_link(path).is_ok_and(|current| current == link) {
+            return Ok(());
+        }
+        driver
+            .remove_file(path)
+            .and_then(|_| driver.symlink(link, path))
+            .map_err(|err| Error {
+                kind: ErrorKind::Rename,
+                value: Some(format!("{} -> {}\n{}", path.display(), link.display(), err)),
+            })?;
+        self.config.printer.print_warning(
+            Warning::SymlinkRestored,
+            &format!("Restored symlink {} -> {}", path.display(), link.display()),
+        );
+        Ok(())
+    }
+}
+
+/// Check if the replacements of the replace mode number paths within groups.
+fn has_counters(replace_mode: &ReplaceMode) -> bool {
+    match replace_mode {
+        ReplaceMode::RegExp { replacement, .. } => replacement.has_counters(),
+        ReplaceMode::Rules(rules) => rules.iter().any(|rule| rule.replacement.has_counters()),
+        ReplaceMode::Passes(passes) => passes.iter().any(has_counters),
+        ReplaceMode::ToASCII => false,
+    }
+}
+
+/// Check if the replacements of the replace mode use the named placeholder.
+fn uses_placeholder(replace_mode: &ReplaceMode, name: &str) -> bool {
+    let uses = |template: &Template| template.placeholders().iter().any(|used| used == name);
+    match replace_mode {
+        ReplaceMode::RegExp { replacement, .. } => uses(replacement),
+        ReplaceMode::Rules(rules) => rules.iter().any(|rule| uses(&rule.replacement)),
+        ReplaceMode::Passes(passes) => passes.iter().any(|pass| uses_placeholder(pass, name)),
+        ReplaceMode::ToASCII => false,
+    }
+}
+
+/// Check if the file name of a path is matched by the replace mode, even if it is unchanged.
+fn is_matched(path: &Path, replace_mode: &ReplaceMode) -> bool {
+    let file_name = match path.file_name().and_then(|name| name.to_str()) {
+        Some(file_name) => file_name,
+        None => return false,
+    };
+    match replace_mode {
+        ReplaceMode::RegExp { expression, .. } => expression.is_match(file_name),
+        ReplaceMode::Rules(rules) => rules
+            .iter()
+            .any(|rule| rule.matches(file_name) && rule.expression.is_match(file_name)),
+        ReplaceMode::Passes(passes) => passes.iter().any(|pass| is_matched(path, pass)),
+        ReplaceMode::ToASCII => true,
+    }
+}
+
+/// Check if an operation cannot be applied after another one failed: its target is still taken by
+/// the failed source, or it renames paths expected inside the failed target.
+fn depends_on(operation: &Operation, failed: &Operation) -> bool {
+    operation.target == failed.source
+        || operation.source.starts_with(&failed.target)
+        || operation.target.starts_with(&failed.target)
+}
+
+/// Get the path of the source of an operation before any operation is applied. Earlier operations
+/// producing the source or one of its parents are followed back, latest first.
+fn current_path(
+    operations: &[Operation],
+    index: usize,
+    targets: &HashMap<&Path, Vec<usize>>,
+) -> PathBuf {
+    let mut path = operations[index].source.clone();
+    let mut before = index;
+    loop {
+        let renamed = path
+            .ancestors()
+            .filter_map(|ancestor| {
+                let indexes = targets.get(ancestor)?;
+                let earlier = indexes.partition_point(|index| *index < before);
+                let index = *indexes.get(earlier.checked_sub(1)?)?;
+                Some((path.strip_prefix(ancestor).ok()?.to_path_buf(), index))
+            })
+            .max_by_key(|(_, index)| *index);
+        match renamed {
+            Some((relative, index)) => {
+                let source = &operations[index].source;
+                path = if relative.as_os_str().is_empty() {
+                    source.clone()
+                } else {
+                    source.join(relative)
+                };
+                before = index;
+            }
+            None => return path,
+        }
+    }
+}
+
+/// Get the path of a target moved into the directory, which is relative to the parent of the
+/// target unless it is absolute.
+fn move_target(target: &Path, dir: &Path) -> PathBuf {
+    let file_name = target.file_name().unwrap_or_default();
+    let parent = target.parent().unwrap_or_else(|| Path::new(""));
+    normalize_path(&parent.join(dir).join(file_name))
+}
+
+/// Get operations with canonical absolute paths, so they can be used from any directory
+pub fn absolute_operations(operations: &[Operation]) -> Result<Operations> {
+    operations
+        .iter()
+        .map(|operation| {
+            Ok(Operation {
+                source: canonicalize_parent(&operation.source)?,
+                target: canonicalize_parent(&operation.target)?,
+                tag: operation.tag,
+                link: operation.link.clone(),
+            })
+        })
+        .collect()
 }
 
 #[cfg(test)]
@@ -176,12 +1140,59 @@ mod test {
     extern crate tempfile;
     use super::*;
     use crate::config::RunMode;
+    use crate::driver::{DirMode, FsDriver, LocalDriver, MemoryDriver};
     use crate::output::Printer;
+    use crate::rules;
+    use crate::safety::Safety;
+    use crate::sample::Sample;
+    use crate::settings::Settings;
+    use crate::template::Template;
     use regex::Regex;
     use std::fs;
     use std::path::Path;
     use std::process;
     use std::sync::Arc;
+    use std::time::Duration;
+
+    /// Get a configuration renaming with force and no other options, tests change what they need.
+    fn test_config(
+        run_mode: RunMode,
+        replace_mode: ReplaceMode,
+        driver: Arc<dyn FsDriver>,
+    ) -> Config {
+        Config {
+            force: true,
+            backup: false,
+            backup_keep: None,
+            dirs: false,
+            dump: false,
+            abs: false,
+            report: None,
+            time_filter: None,
+            capture_ranges: Vec::new(),
+            sample: None,
+            group_by: None,
+            move_to: None,
+            dir_mode: DirMode::Umask,
+            xattr_tag: false,
+            io_concurrency: 1,
+            skip_in_use: false,
+            collide_ignore_ext: false,
+            wait_lock: false,
+            ignore_errors: Vec::new(),
+            timeout: None,
+            progress: None,
+            slowest: None,
+            safety: Safety::default(),
+            run_mode,
+            replace_mode,
+            printer: Printer::silent(),
+            settings: Settings::default(),
+            driver,
+            simulation: None,
+            run_id: "test".to_string(),
+        }
+    }
 
     #[test]
     fn renamer() {
@@ -219,17 +1230,17 @@ mod test {
 
         // Create config
         let mock_config = Arc::new(Config {
-            force: true,
             backup: true,
-            dirs: false,
-            dump: false,
-            run_mode: RunMode::Simple(mock_files),
-            replace_mode: ReplaceMode::RegExp {
-                expression: Regex::new("test").unwrap(),
-                replacement: "passed".to_string(),
-                limit: 1,
-            },
             printer: Printer::color(),
+            ..test_config(
+                RunMode::Simple(mock_files),
+                ReplaceMode::RegExp {
+                    expression: Regex::new("test").unwrap(),
+                    replacement: Template::parse("passed").unwrap(),
+                    limit: 1,
+                },
+                Arc::new(LocalDriver),
+            )
         });
 
         // Run renamer
@@ -265,6 +1276,103 @@ mod test {
         assert!(Path::new(&format!("{}/test_file_2.txt.bk", mock_dir)).exists());
     }
 
+    #[test]
+    fn renamer_memory() {
+        let driver = Arc::new(MemoryDriver::new());
+        let temp_path = "/data";
+
+        // Generate a mock directory tree and files
+        //
+        // - temp_path
+        //     |
+        //     - test_file_1.txt
+        //     |
+        //     - test_file_2.txt
+        //     |
+        //     - mock_dir
+        //         |
+        //         - test_file_1.txt
+        //         |
+        //         - test_file_2.txt
+        //
+        let mock_dir = format!("{}/mock_dir", temp_path);
+        let mock_files: Vec<String> = vec![
+            format!("{}/test_file_1.txt", temp_path),
+            format!("{}/test_file_2.txt", temp_path),
+            format!("{}/test_file_1.txt", mock_dir),
+            format!("{}/test_file_2.txt", mock_dir),
+        ];
+
+        // Create directory tree and files in the filesystem
+        driver.create_dir(Path::new(&mock_dir));
+        for file in &mock_files {
+            driver.create_file(Path::new(file), b"");
+        }
+
+        // Create config
+        let mock_config = Arc::new(Config {
+            backup: true,
+            printer: Printer::color(),
+            ..test_config(
+                RunMode::Simple(mock_files),
+                ReplaceMode::RegExp {
+                    expression: Regex::new("test").unwrap(),
+                    replacement: Template::parse("passed").unwrap(),
+                    limit: 1,
+                },
+                driver.clone(),
+            )
+        });
+
+        // Run renamer
+        let renamer = match Renamer::new(&mock_config) {
+            Ok(renamer) => renamer,
+            Err(err) => {
+                mock_config.printer.print_error(&err);
+                process::exit(1);
+            }
+        };
+        let operations = match renamer.process() {
+            Ok(operations) => operations,
+            Err(err) => {
+                mock_config.printer.print_error(&err);
+                process::exit(1);
+            }
+        };
+        if let Err(err) = renamer.batch_rename(operations) {
+            mock_config.printer.print_error(&err);
+            process::exit(1);
+        }
+
+        // Check renamed files
+        assert!(driver
+            .metadata(Path::new(&format!("{}/passed_file_1.txt", temp_path)))
+            .is_ok());
+        assert!(driver
+            .metadata(Path::new(&format!("{}/passed_file_2.txt", temp_path)))
+            .is_ok());
+        assert!(driver
+            .metadata(Path::new(&format!("{}/passed_file_1.txt", mock_dir)))
+            .is_ok());
+        assert!(driver
+            .metadata(Path::new(&format!("{}/passed_file_2.txt", mock_dir)))
+            .is_ok());
+
+        // Check backup files
+        assert!(driver
+            .metadata(Path::new(&format!("{}/test_file_1.txt.bk", temp_path)))
+            .is_ok());
+        assert!(driver
+            .metadata(Path::new(&format!("{}/test_file_2.txt.bk", temp_path)))
+            .is_ok());
+        assert!(driver
+            .metadata(Path::new(&format!("{}/test_file_1.txt.bk", mock_dir)))
+            .is_ok());
+        assert!(driver
+            .metadata(Path::new(&format!("{}/test_file_2.txt.bk", mock_dir)))
+            .is_ok());
+    }
+
     #[test]
     fn replace_limit() {
         let tempdir = tempfile::tempdir().expect("Error creating temp directory");
@@ -277,17 +1385,16 @@ mod test {
         }
 
         let mock_config = Arc::new(Config {
-            force: true,
-            backup: false,
-            dirs: false,
-            dump: false,
-            run_mode: RunMode::Simple(mock_files),
-            replace_mode: ReplaceMode::RegExp {
-                expression: Regex::new("a").unwrap(),
-                replacement: "b".to_string(),
-                limit: 0,
-            },
             printer: Printer::color(),
+            ..test_config(
+                RunMode::Simple(mock_files),
+                ReplaceMode::RegExp {
+                    expression: Regex::new("a").unwrap(),
+                    replacement: Template::parse("b").unwrap(),
+                    limit: 0,
+                },
+                Arc::new(LocalDriver),
+            )
         });
 
         let renamer = match Renamer::new(&mock_config) {
@@ -313,6 +1420,119 @@ mod test {
         assert!(Path::new(&format!("{}/replbce_bll_bbbbb.txt", temp_path)).exists());
     }
 
+    #[test]
+    fn replace_limit_memory() {
+        let driver = Arc::new(MemoryDriver::new());
+        let temp_path = "/data";
+
+        let mock_files: Vec<String> = vec![format!("{}/replace_all_aaaaa.txt", temp_path)];
+        for file in &mock_files {
+            driver.create_file(Path::new(file), b"");
+        }
+
+        let mock_config = Arc::new(Config {
+            printer: Printer::color(),
+            ..test_config(
+                RunMode::Simple(mock_files),
+                ReplaceMode::RegExp {
+                    expression: Regex::new("a").unwrap(),
+                    replacement: Template::parse("b").unwrap(),
+                    limit: 0,
+                },
+                driver.clone(),
+            )
+        });
+
+        let renamer = match Renamer::new(&mock_config) {
+            Ok(renamer) => renamer,
+            Err(err) => {
+                mock_config.printer.print_error(&err);
+                process::exit(1);
+            }
+        };
+        let operations = match renamer.process() {
+            Ok(operations) => operations,
+            Err(err) => {
+                mock_config.printer.print_error(&err);
+                process::exit(1);
+            }
+        };
+        if let Err(err) = renamer.batch_rename(operations) {
+            mock_config.printer.print_error(&err);
+            process::exit(1);
+        }
+
+        // Check renamed files
+        let renamed = format!("{}/replbce_bll_bbbbb.txt", temp_path);
+        assert!(driver.metadata(Path::new(&renamed)).is_ok());
+    }
+
+    #[test]
+    fn passes() {
+        let driver = Arc::new(MemoryDriver::new());
+        let temp_path = "/data";
+
+        let mock_files: Vec<String> = vec![
+            format!("{}/a b.txt", temp_path),
+            format!("{}/c  d.txt", temp_path),
+        ];
+        for file in &mock_files {
+            driver.create_file(Path::new(file), b"");
+        }
+        let settings = Settings::default();
+        let pass = |content: &str| ReplaceMode::Rules(rules::parse(content, &settings, 0).unwrap());
+
+        let mock_config = Arc::new(Config {
+            force: false,
+            ..test_config(
+                RunMode::Simple(mock_files.clone()),
+                ReplaceMode::Passes(vec![
+                    pass("pattern = \\s+\nreplacement = _"),
+                    pass("pattern = _\nreplacement = -"),
+                ]),
+                driver.clone(),
+            )
+        });
+        let renamer = Renamer::new(&mock_config).unwrap();
+        let operations = renamer.process().unwrap();
+
+        // Second pass renames the names produced by the first one, nothing is renamed in dry-run
+        let mut operations: Vec<(PathBuf, PathBuf)> = operations
+            .into_iter()
+            .map(|operation| (operation.source, operation.target))
+            .collect();
+        operations[..2].sort();
+        operations[2..].sort();
+        let path = |name: &str| Path::new(temp_path).join(name);
+        assert_eq!(
+            operations,
+            vec![
+                (path("a b.txt"), path("a_b.txt")),
+                (path("c  d.txt"), path("c_d.txt")),
+                (path("a_b.txt"), path("a-b.txt")),
+                (path("c_d.txt"), path("c-d.txt")),
+            ]
+        );
+        assert!(driver.metadata(&path("a b.txt")).is_ok());
+
+        // Samples go through all the passes of the drawn file
+        let mock_config = Arc::new(Config {
+            sample: Some(Sample::new(1, Some(7))),
+            ..test_config(
+                RunMode::Simple(mock_files),
+                ReplaceMode::Passes(vec![
+                    pass("pattern = \\s+\nreplacement = _"),
+                    pass("pattern = _\nreplacement = -"),
+                ]),
+                driver.clone(),
+            )
+        });
+        let renamer = Renamer::new(&mock_config).unwrap();
+        let operations = renamer.process().unwrap();
+        assert_eq!(operations.len(), 2);
+        assert_eq!(operations[0].target, operations[1].source);
+    }
+
     #[test]
     fn to_ascii() {
         let tempdir = tempfile::tempdir().expect("Error creating temp directory");
@@ -328,13 +1548,12 @@ mod test {
         }
 
         let mock_config = Arc::new(Config {
-            force: true,
-            backup: false,
-            dirs: false,
-            dump: false,
-            run_mode: RunMode::Simple(mock_files),
-            replace_mode: ReplaceMode::ToASCII,
             printer: Printer::color(),
+            ..test_config(
+                RunMode::Simple(mock_files),
+                ReplaceMode::ToASCII,
+                Arc::new(LocalDriver),
+            )
         });
 
         let renamer = match Renamer::new(&mock_config) {
@@ -360,4 +1579,580 @@ mod test {
         assert!(Path::new(&format!("{}/non-ascii-lower.txt", temp_path)).exists());
         assert!(Path::new(&format!("{}/NON-ASCII-UPPER.txt", temp_path)).exists());
     }
+
+    #[test]
+    fn to_ascii_memory() {
+        let driver = Arc::new(MemoryDriver::new());
+        let temp_path = "/data";
+
+        let mock_files: Vec<String> = vec![
+            format!("{}/ǹön-âścîı-lower.txt", temp_path),
+            format!("{}/ǸÖN-ÂŚCÎI-UPPER.txt", temp_path),
+        ];
+        for file in &mock_files {
+            driver.create_file(Path::new(file), b"");
+        }
+
+        let mock_config = Arc::new(Config {
+            printer: Printer::color(),
+            ..test_config(
+                RunMode::Simple(mock_files),
+                ReplaceMode::ToASCII,
+                driver.clone(),
+            )
+        });
+
+        let renamer = match Renamer::new(&mock_config) {
+            Ok(renamer) => renamer,
+            Err(err) => {
+                mock_config.printer.print_error(&err);
+                process::exit(1);
+            }
+        };
+        let operations = match renamer.process() {
+            Ok(operations) => operations,
+            Err(err) => {
+                mock_config.printer.print_error(&err);
+                process::exit(1);
+            }
+        };
+        if let Err(err) = renamer.batch_rename(operations) {
+            mock_config.printer.print_error(&err);
+            process::exit(1);
+        }
+
+        // Check renamed files
+        assert!(driver
+            .metadata(Path::new(&format!("{}/non-ascii-lower.txt", temp_path)))
+            .is_ok());
+        assert!(driver
+            .metadata(Path::new(&format!("{}/NON-ASCII-UPPER.txt", temp_path)))
+            .is_ok());
+    }
+
+    #[test]
+    fn timeout() {
+        let driver = Arc::new(MemoryDriver::new());
+        let temp_path = "/data";
+
+        let mock_files: Vec<String> = vec![format!("{}/test_file.txt", temp_path)];
+        for file in &mock_files {
+            driver.create_file(Path::new(file), b"");
+        }
+
+        let mock_config = Arc::new(Config {
+            timeout: Some(Duration::ZERO),
+            ..test_config(
+                RunMode::Simple(mock_files),
+                ReplaceMode::RegExp {
+                    expression: Regex::new("test").unwrap(),
+                    replacement: Template::parse("passed").unwrap(),
+                    limit: 1,
+                },
+                driver.clone(),
+            )
+        });
+
+        let renamer = Renamer::new(&mock_config).unwrap();
+        let error = renamer.process().unwrap_err();
+        assert_eq!(error.kind, ErrorKind::Timeout);
+
+        // Nothing is renamed once the budget is exhausted
+        let operations = vec![Operation {
+            source: PathBuf::from(format!("{}/test_file.txt", temp_path)),
+            target: PathBuf::from(format!("{}/passed_file.txt", temp_path)),
+            tag: None,
+            link: None,
+        }];
+        let error = renamer.batch_rename(operations).unwrap_err();
+        assert_eq!(error.kind, ErrorKind::Timeout);
+        assert!(driver
+            .metadata(Path::new(&format!("{}/test_file.txt", temp_path)))
+            .is_ok());
+    }
+
+    #[test]
+    fn undo_symlinks() {
+        let tempdir = tempfile::tempdir().expect("Error creating temp directory");
+        println!("Running test in '{:?}'", tempdir);
+        let dump_file = tempdir.path().join("dump.json");
+        let driver = Arc::new(MemoryDriver::new());
+        let link = PathBuf::from("/data/test_link");
+        let renamed = PathBuf::from("/data/passed_link");
+
+        // Dangling relative link renamed and re-pointed after the dump was created
+        driver.create_dir(Path::new("/data"));
+        driver
+            .symlink(Path::new("../missing/target"), &renamed)
+            .unwrap();
+        let operations = vec![Operation {
+            source: link.clone(),
+            target: renamed.clone(),
+            tag: None,
+            link: Some(PathBuf::from("missing/target")),
+        }];
+        dumpfile::write_to_file(&dump_file, &operations, None).unwrap();
+
+        let mock_config = Arc::new(Config {
+            backup: true,
+            ..test_config(
+                RunMode::FromFile {
+                    path: dump_file.to_string_lossy().to_string(),
+                    undo: true,
+                    tags: None,
+                    exclude_newer: false,
+                    format: importer::Format::Dump,
+                },
+                ReplaceMode::ToASCII,
+                driver.clone(),
+            )
+        });
+        let renamer = Renamer::new(&mock_config).unwrap();
+        let operations = renamer.process().unwrap();
+        renamer.batch_rename(operations).unwrap();
+
+        // Link is restored without following it, and its backup is a link too
+        assert_eq!(
+            driver.read_link(&link).unwrap(),
+            PathBuf::from("missing/target")
+        );
+        assert!(driver.metadata(&link).is_err());
+        assert!(driver.symlink_metadata(&renamed).is_err());
+        assert_eq!(
+            driver.read_link(Path::new("/data/passed_link.bk")).unwrap(),
+            PathBuf::from("../missing/target")
+        );
+    }
+
+    #[test]
+    fn undo_exclude_newer() {
+        let tempdir = tempfile::tempdir().expect("Error creating temp directory");
+        println!("Running test in '{:?}'", tempdir);
+        let dump_file = tempdir.path().join("dump.json");
+        let driver = Arc::new(MemoryDriver::new());
+        let path = |name: &str| Path::new("/data").join(name);
+
+        // Dump created in 2020, only one renamed file was modified later
+        driver.create_file(&path("renamed_old"), b"");
+        driver.create_file(&path("renamed_new"), b"");
+        let old_time = std::time::SystemTime::UNIX_EPOCH + Duration::from_secs(1 << 30);
+        driver.set_modified(&path("renamed_old"), old_time).unwrap();
+        let operations = ["old", "new"]
+            .iter()
+            .map(|name| Operation {
+                source: path(&format!("original_{}", name)),
+                target: path(&format!("renamed_{}", name)),
+                tag: None,
+                link: None,
+            })
+            .collect();
+        let dump = dumpfile::Dump {
+            date: chrono::NaiveDate::from_ymd_opt(2020, 1, 1)
+                .and_then(|date| date.and_hms_opt(0, 0, 0)),
+            run_id: None,
+            operations,
+        };
+        dumpfile::write_dump(&dump_file, &dump).unwrap();
+
+        let mock_config = Arc::new(test_config(
+            RunMode::FromFile {
+                path: dump_file.to_string_lossy().to_string(),
+                undo: true,
+                tags: None,
+                exclude_newer: true,
+                format: importer::Format::Dump,
+            },
+            ReplaceMode::ToASCII,
+            driver.clone(),
+        ));
+        let renamer = Renamer::new(&mock_config).unwrap();
+        let operations = renamer.process().unwrap();
+        renamer.batch_rename(operations).unwrap();
+
+        assert!(driver.metadata(&path("original_old")).is_ok());
+        assert!(driver.metadata(&path("renamed_new")).is_ok());
+        assert!(driver.metadata(&path("original_new")).is_err());
+    }
+
+    #[test]
+    fn undo_exclude_newer_renamed_dir() {
+        let tempdir = tempfile::tempdir().expect("Error creating temp directory");
+        let dump_file = tempdir.path().join("dump.json");
+        let driver = Arc::new(MemoryDriver::new());
+        let path = |name: &str| Path::new("/data").join(name);
+
+        // Dump of dx/fx -> dx/fy, dx -> dy and zx -> zy, the file in dy was modified later
+        driver.create_file(&path("dy/fy"), b"");
+        driver.create_file(&path("zy"), b"");
+        let old_time = std::time::SystemTime::UNIX_EPOCH + Duration::from_secs(1 << 30);
+        driver.set_modified(&path("dy"), old_time).unwrap();
+        driver.set_modified(&path("zy"), old_time).unwrap();
+        let operations = [("dx/fx", "dx/fy"), ("dx", "dy"), ("zx", "zy")]
+            .iter()
+            .map(|(source, target)| Operation {
+                source: path(source),
+                target: path(target),
+                tag: None,
+                link: None,
+            })
+            .collect();
+        let dump = dumpfile::Dump {
+            date: chrono::NaiveDate::from_ymd_opt(2020, 1, 1)
+                .and_then(|date| date.and_hms_opt(0, 0, 0)),
+            run_id: None,
+            operations,
+        };
+        dumpfile::write_dump(&dump_file, &dump).unwrap();
+
+        let mock_config = Arc::new(test_config(
+            RunMode::FromFile {
+                path: dump_file.to_string_lossy().to_string(),
+                undo: true,
+                tags: None,
+                exclude_newer: true,
+                format: importer::Format::Dump,
+            },
+            ReplaceMode::ToASCII,
+            driver.clone(),
+        ));
+        let renamer = Renamer::new(&mock_config).unwrap();
+        let operations = renamer.process().unwrap();
+        renamer.batch_rename(operations).unwrap();
+
+        // The file is checked in dy, where it is before the directory is renamed back
+        assert!(driver.metadata(&path("dx/fy")).is_ok());
+        assert!(driver.metadata(&path("dx/fx")).is_err());
+        assert!(driver.metadata(&path("zx")).is_ok());
+    }
+
+    #[test]
+    fn group_by() {
+        let driver = Arc::new(MemoryDriver::new());
+        let path = |name: &str| Path::new("/photos").join(name);
+        let names = [
+            "2024-05-02_b.jpg",
+            "2024-05-01_z.jpg",
+            "2024-05-02_a.jpg",
+            "2024-05-01_y.jpg",
+            "notes.txt",
+        ];
+        for name in names {
+            driver.create_file(&path(name), b"");
+        }
+
+        let mock_config = Arc::new(Config {
+            group_by: Some(Template::parse("$1").unwrap()),
+            ..test_config(
+                RunMode::Simple(
+                    names
+                        .iter()
+                        .map(|name| path(name).to_string_lossy().to_string())
+                        .collect(),
+                ),
+                ReplaceMode::RegExp {
+                    expression: Regex::new(r"^(\d{4}-\d{2}-\d{2})_\w+").unwrap(),
+                    replacement: Template::parse("day{group}_${1}_{seq:2}").unwrap(),
+                    limit: 1,
+                },
+                driver.clone(),
+            )
+        });
+        let renamer = Renamer::new(&mock_config).unwrap();
+        let operations = renamer.process().unwrap();
+        renamer.batch_rename(operations).unwrap();
+
+        // Paths are numbered by name within each day
+        for name in [
+            "day1_2024-05-01_01.jpg",
+            "day1_2024-05-01_02.jpg",
+            "day2_2024-05-02_01.jpg",
+            "day2_2024-05-02_02.jpg",
+            "notes.txt",
+        ] {
+            assert!(driver.symlink_metadata(&path(name)).is_ok(), "{}", name);
+        }
+    }
+
+    #[test]
+    fn move_to() {
+        let driver = Arc::new(MemoryDriver::new());
+        let path = |name: &str| Path::new("/inbox").join(name);
+        let names = ["a/scan_1.pdf", "b/scan_2.pdf", "b/scan.pdf", "b/notes.txt"];
+        for name in names {
+            driver.create_file(&path(name), b"");
+        }
+
+        let mock_config = Arc::new(Config {
+            move_to: Some(PathBuf::from("done")),
+            io_concurrency: 4,
+            ..test_config(
+                RunMode::Simple(
+                    names
+                        .iter()
+                        .map(|name| path(name).to_string_lossy().to_string())
+                        .collect(),
+                ),
+                ReplaceMode::RegExp {
+                    expression: Regex::new(r"^scan_?(\d*)").unwrap(),
+                    replacement: Template::parse("doc$1").unwrap(),
+                    limit: 1,
+                },
+                driver.clone(),
+            )
+        });
+        let renamer = Renamer::new(&mock_config).unwrap();
+        let operations = renamer.process().unwrap();
+        renamer.batch_rename(operations).unwrap();
+
+        // Matched paths are moved into a directory next to them, other paths are left in place
+        for name in [
+            "a/done/doc1.pdf",
+            "b/done/doc2.pdf",
+            "b/done/doc.pdf",
+            "b/notes.txt",
+        ] {
+            assert!(driver.symlink_metadata(&path(name)).is_ok(), "{}", name);
+        }
+        assert!(driver.symlink_metadata(&path("a/scan_1.pdf")).is_err());
+    }
+
+    #[test]
+    fn prefetch_used_values() {
+        let driver = Arc::new(MemoryDriver::new());
+        let names = ["a.jpg", "b.txt", "c.txt", "d"];
+        for name in names {
+            driver.create_file(&Path::new("/data").join(name), b"");
+        }
+
+        // Reading ahead must not read more than planning on demand
+        let stats = |io_concurrency: usize| {
+            let mock_config = Arc::new(Config {
+                io_concurrency,
+                force: false,
+                ..test_config(
+                    RunMode::Simple(names.iter().map(|name| format!("/data/{}", name)).collect()),
+                    ReplaceMode::RegExp {
+                        expression: Regex::new(r"\.jpg$").unwrap(),
+                        replacement: Template::parse("_{mtime}.jpg").unwrap(),
+                        limit: 1,
+                    },
+                    driver.clone(),
+                )
+            });
+            let renamer = Renamer::new(&mock_config).unwrap();
+            renamer.process().unwrap();
+            renamer.cache.stats()
+        };
+        assert_eq!(stats(4).misses, stats(1).misses);
+    }
+
+    #[test]
+    fn plan_symlinks() {
+        let driver = Arc::new(MemoryDriver::new());
+        let path = |name: &str| Path::new("/data").join(name);
+        driver.create_file(&path("file_1.txt"), b"");
+        driver
+            .symlink(Path::new("file_1.txt"), &path("link_1.txt"))
+            .unwrap();
+
+        let mock_config = Arc::new(Config {
+            force: false,
+            ..test_config(
+                RunMode::Simple(vec![
+                    "/data/file_1.txt".to_string(),
+                    "/data/link_1.txt".to_string(),
+                ]),
+                ReplaceMode::RegExp {
+                    expression: Regex::new("_1").unwrap(),
+                    replacement: Template::parse("_2").unwrap(),
+                    limit: 1,
+                },
+                driver.clone(),
+            )
+        });
+        let renamer = Renamer::new(&mock_config).unwrap();
+        let mut operations = renamer.process().unwrap();
+        operations.sort_by(|a, b| a.source.cmp(&b.source));
+
+        // Planned symlinks record the path they point to, files do not
+        assert_eq!(operations.len(), 2);
+        assert_eq!(operations[0].link, None);
+        assert_eq!(operations[1].link, Some(PathBuf::from("file_1.txt")));
+    }
+
+    #[test]
+    fn xattr_tag() {
+        let driver = Arc::new(MemoryDriver::new());
+        let path = |name: &str| Path::new("/data").join(name);
+        driver.create_file(&path("file_1.txt"), b"");
+        driver
+            .symlink(Path::new("file_1.txt"), &path("link_1.txt"))
+            .unwrap();
+
+        let mock_config = Arc::new(Config {
+            xattr_tag: true,
+            ..test_config(
+                RunMode::Simple(vec![
+                    "/data/file_1.txt".to_string(),
+                    "/data/link_1.txt".to_string(),
+                ]),
+                ReplaceMode::RegExp {
+                    expression: Regex::new("_1").unwrap(),
+                    replacement: Template::parse("_2").unwrap(),
+                    limit: 1,
+                },
+                driver.clone(),
+            )
+        });
+        let renamer = Renamer::new(&mock_config).unwrap();
+        let operations = renamer.process().unwrap();
+        renamer.batch_rename(operations).unwrap();
+
+        // Renamed files record where they come from, symlinks are not tagged
+        let origin = driver.xattr(&path("file_2.txt"), XATTR_NAME).unwrap();
+        let origin = String::from_utf8(origin).unwrap();
+        assert!(origin.contains(r#""source":"/data/file_1.txt""#));
+        assert!(origin.contains(r#""run_id":"test""#));
+        assert!(driver.xattr(&path("link_2.txt"), XATTR_NAME).is_none());
+    }
+
+    #[test]
+    fn ignore_errors() {
+        let tempdir = tempfile::tempdir().expect("Error creating temp directory");
+        println!("Running test in '{:?}'", tempdir);
+        let dump_file = tempdir.path().join("dump.json");
+        let driver = Arc::new(MemoryDriver::new());
+        let path = |name: &str| Path::new("/share").join(name);
+        driver.create_file(&path("a.txt"), b"");
+        driver.create_file(&path("c.txt"), b"");
+
+        let mock_config = |ignore_errors: Vec<String>| {
+            Arc::new(Config {
+                ignore_errors,
+                ..test_config(
+                    RunMode::Simple(Vec::new()),
+                    ReplaceMode::ToASCII,
+                    driver.clone(),
+                )
+            })
+        };
+        // Thumbnail database vanished after planning
+        let operation = |source: &str, target: &str| Operation {
+            source: path(source),
+            target: path(target),
+            tag: None,
+            link: None,
+        };
+        let operations = vec![
+            operation("a.txt", "b.txt"),
+            operation("Thumbs.db", "thumbs.db"),
+            operation("c.txt", "d.txt"),
+        ];
+
+        let renamer = Renamer::new(&mock_config(Vec::new())).unwrap();
+        assert!(renamer.batch_rename(operations.clone()).is_err());
+        driver.rename(&path("b.txt"), &path("a.txt")).unwrap();
+
+        let renamer = Renamer::new(&mock_config(vec!["*.db".to_string()])).unwrap();
+        *renamer.dump.borrow_mut() = Some((dump_file.clone(), operations.clone()));
+        renamer.batch_rename(operations).unwrap();
+        assert!(driver.symlink_metadata(&path("b.txt")).is_ok());
+        assert!(driver.symlink_metadata(&path("d.txt")).is_ok());
+
+        // Failed operations are removed from the dump file
+        let dump = dumpfile::read_dump(&dump_file).unwrap();
+        let sources: Vec<PathBuf> = dump.operations.into_iter().map(|op| op.source).collect();
+        assert_eq!(sources, vec![path("a.txt"), path("c.txt")]);
+    }
+
+    #[test]
+    fn ignore_errors_dependents() {
+        let driver = Arc::new(MemoryDriver::new());
+        let path = |name: &str| Path::new("/share").join(name);
+        driver.create_file(&path("a.txt"), b"a");
+        driver.create_file(&path("b.txt"), b"b");
+        driver.create_dir(&path("dir"));
+        driver.create_file(&path("dir/e.txt"), b"e");
+
+        let mock_config = Arc::new(Config {
+            ignore_errors: vec!["*".to_string()],
+            ..test_config(
+                RunMode::Simple(Vec::new()),
+                ReplaceMode::ToASCII,
+                driver.clone(),
+            )
+        });
+        let operation = |source: &str, target: &str| Operation {
+            source: path(source),
+            target: path(target),
+            tag: None,
+            link: None,
+        };
+        // Chain a -> b, b -> c in solved order, its first operation fails
+        let operations = vec![
+            operation("b.txt", "missing/c.txt"),
+            operation("a.txt", "b.txt"),
+            operation("dir", "missing/dir"),
+            operation("missing/dir/e.txt", "missing/dir/f.txt"),
+            operation("d.txt", "missing/dir/g.txt"),
+        ];
+        let renamer = Renamer::new(&mock_config).unwrap();
+        renamer.batch_rename(operations).unwrap();
+        assert_eq!(driver.read(&path("a.txt"), 1).unwrap(), b"a");
+        assert_eq!(driver.read(&path("b.txt"), 1).unwrap(), b"b");
+        assert!(driver.symlink_metadata(&path("dir/e.txt")).is_ok());
+    }
+
+    #[test]
+    fn slowest_operations() {
+        let driver = Arc::new(MemoryDriver::new());
+        let path = |name: &str| Path::new("/data").join(name);
+        let operations: Operations = (0..5)
+            .map(|index| {
+                driver.create_file(&path(&format!("{}.txt", index)), b"");
+                Operation {
+                    source: path(&format!("{}.txt", index)),
+                    target: path(&format!("{}.bin", index)),
+                    tag: None,
+                    link: None,
+                }
+            })
+            .collect();
+
+        let mock_config = Arc::new(Config {
+            slowest: Some(2),
+            ..test_config(
+                RunMode::Simple(Vec::new()),
+                ReplaceMode::ToASCII,
+                driver.clone(),
+            )
+        });
+        let renamer = Renamer::new(&mock_config).unwrap();
+        renamer.batch_rename(operations).unwrap();
+        // Only the slowest ones are kept, slowest first
+        let slowest = renamer.slowest();
+        assert_eq!(slowest.len(), 2);
+        assert!(slowest[0].duration >= slowest[1].duration);
+    }
+
+    #[test]
+    fn empty_name() {
+        let driver = Arc::new(MemoryDriver::new());
+        driver.create_file(Path::new("/data/file.txt"), b"");
+        let replace = |expression: &str| ReplaceMode::RegExp {
+            expression: Regex::new(expression).unwrap(),
+            replacement: Template::parse("").unwrap(),
+            limit: 0,
+        };
+        let run_mode = || RunMode::Simple(vec!["/data/file.txt".to_string()]);
+
+        // Removing the whole name would rename the file onto its parent directory
+        let mock_config = Arc::new(test_config(run_mode(), replace(".*"), driver.clone()));
+        let err = Renamer::new(&mock_config).unwrap().process().unwrap_err();
+        assert_eq!(err.kind, ErrorKind::EmptyName);
+
+        let mock_config = Arc::new(test_config(run_mode(), replace(r"\.txt"), driver.clone()));
+        let operations = Renamer::new(&mock_config).unwrap().process().unwrap();
+        assert_eq!(operations[0].target, PathBuf::from("/data/file"));
+    }
 }
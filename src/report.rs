@@ -0,0 +1,191 @@
+use crate::error::*;
+use crate::solver::Operation;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+
+/// Report with the planned operations written to a file.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Report {
+    pub format: ReportFormat,
+    pub path: PathBuf,
+}
+
+/// Supported report formats
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ReportFormat {
+    /// Standalone HTML page with a table that can be filtered and sorted
+    Html,
+}
+
+impl Report {
+    /// Parse a report definition with `FORMAT:PATH` syntax, e.g. `html:report.html`.
+    pub fn parse(value: &str) -> std::result::Result<Report, String> {
+        let (format, path) = match value.split_once(':') {
+            Some((format, path)) if !path.is_empty() => (format, path),
+            _ => return Err(format!("Invalid report '{}', use FORMAT:PATH", value)),
+        };
+        let format = match format {
+            "html" => ReportFormat::Html,
+            format => return Err(format!("Unknown report format '{}', use html", format)),
+        };
+        Ok(Report {
+            format,
+            path: PathBuf::from(path),
+        })
+    }
+
+    /// Write the report for the given operations.
+    pub fn write(&self, operations: &[Operation], force: bool, run_id: &str) -> Result<()> {
+        let create_error = |err: std::io::Error| Error {
+            kind: ErrorKind::CreateFile,
+            value: Some(format!("{}\n{}", self.path.display(), err)),
+        };
+
+        let file = File::create(&self.path).map_err(create_error)?;
+        let mut writer = BufWriter::new(file);
+        let content = match self.format {
+            ReportFormat::Html => render_html(operations, force, run_id),
+        };
+        writer
+            .write_all(content.as_bytes())
+            .and_then(|_| writer.flush())
+            .map_err(create_error)
+    }
+}
+
+/// Render operations as a standalone HTML page.
+fn render_html(operations: &[Operation], force: bool, run_id: &str) -> String {
+    let mode = if force { "Rename" } else { "Dry-run" };
+    let date = chrono::Local::now().format("%Y-%m-%d %H:%M:%S");
+
+    let mut rows = String::new();
+    for (index, operation) in operations.iter().enumerate() {
+        rows.push_str(&format!(
+            "<tr><td>{}</td><td>{}</td><td>{}</td></tr>\n",
+            index + 1,
+            escape_html(&operation.source.to_string_lossy()),
+            escape_html(&operation.target.to_string_lossy())
+        ));
+    }
+
+    format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+<meta charset="utf-8">
+<title>rnr {mode} report</title>
+<style>
+body {{ font-family: sans-serif; margin: 2em; }}
+input {{ width: 100%; padding: 0.5em; margin-bottom: 1em; box-sizing: border-box; }}
+table {{ border-collapse: collapse; width: 100%; }}
+th, td {{ border: 1px solid #ccc; padding: 0.3em 0.6em; text-align: left; font-family: monospace; white-space: pre; }}
+th {{ cursor: pointer; background: #eee; }}
+tr:nth-child(even) {{ background: #f7f7f7; }}
+</style>
+</head>
+<body>
+<h1>rnr {mode} report</h1>
+<p>{date} &middot; Run ID: {run_id} &middot; Operations: {count}</p>
+<input id="filter" type="search" placeholder="Filter operations">
+<table>
+<thead><tr><th>#</th><th>Source</th><th>Target</th></tr></thead>
+<tbody id="operations">
+{rows}</tbody>
+</table>
+<script>
+const body = document.getElementById("operations");
+document.getElementById("filter").addEventListener("input", (event) => {{
+  const text = event.target.value.toLowerCase();
+  for (const row of body.rows) {{
+    row.hidden = !row.textContent.toLowerCase().includes(text);
+  }}
+}});
+document.querySelectorAll("th").forEach((header, column) => {{
+  let ascending = true;
+  header.addEventListener("click", () => {{
+    const rows = Array.from(body.rows);
+    rows.sort((a, b) => {{
+      const x = a.cells[column].textContent;
+      const y = b.cells[column].textContent;
+      const order = column === 0 ? x - y : x.localeCompare(y);
+      return ascending ? order : -order;
+    }});
+    ascending = !ascending;
+    rows.forEach((row) => body.appendChild(row));
+  }});
+}});
+</script>
+</body>
+</html>
+"#,
+        mode = mode,
+        date = date,
+        run_id = escape_html(run_id),
+        count = operations.len(),
+        rows = rows
+    )
+}
+
+/// Escape text to be inserted in HTML content.
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod test {
+    extern crate tempfile;
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn parse_report() {
+        assert_eq!(
+            Report::parse("html:report.html").unwrap(),
+            Report {
+                format: ReportFormat::Html,
+                path: PathBuf::from("report.html"),
+            }
+        );
+        // Only the first colon separates the format
+        assert_eq!(
+            Report::parse("html:C:\\report.html").unwrap().path,
+            PathBuf::from("C:\\report.html")
+        );
+        assert!(Report::parse("report.html").is_err());
+        assert!(Report::parse("html:").is_err());
+        assert!(Report::parse("pdf:report.pdf").is_err());
+    }
+
+    #[test]
+    fn html_report() {
+        let tempdir = tempfile::tempdir().expect("Error creating temp directory");
+        println!("Running test in '{:?}'", tempdir);
+        let report = Report {
+            format: ReportFormat::Html,
+            path: tempdir.path().join("report.html"),
+        };
+        let operations = vec![Operation {
+            source: PathBuf::from("<script>.txt"),
+            target: PathBuf::from("a & b.txt"),
+            tag: None,
+            link: None,
+        }];
+        report.write(&operations, false, "run").unwrap();
+
+        let content = fs::read_to_string(&report.path).unwrap();
+        assert!(content.contains("<td>&lt;script&gt;.txt</td><td>a &amp; b.txt</td>"));
+        assert!(content.contains("Run ID: run &middot; Operations: 1"));
+    }
+}
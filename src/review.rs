@@ -0,0 +1,130 @@
+use crate::dumpfile;
+use crate::error::*;
+use crate::solver::Tag;
+use std::io::{BufRead, Write};
+use std::path::Path;
+
+/// Review the operations of a dump file asking a tag for each untagged operation. Tags are stored
+/// in the dump file after each answer, so a review can be stopped and resumed later.
+pub fn review<R: BufRead, W: Write>(dump_file: &Path, mut input: R, mut output: W) -> Result<()> {
+    let mut dump = dumpfile::read_dump(dump_file)?;
+    let total = dump.operations.len();
+    let output_error = |err: std::io::Error| Error {
+        kind: ErrorKind::CreateFile,
+        value: Some(format!("stdout\n{}", err)),
+    };
+
+    for index in 0..total {
+        if dump.operations[index].tag.is_some() {
+            continue;
+        }
+
+        let answer = loop {
+            write!(
+                output,
+                "[{}/{}] {} -> {}\nTag as (k)eep, (l)ater, (n)ever, (s)kip or (q)uit: ",
+                index + 1,
+                total,
+                dump.operations[index].source.display(),
+                dump.operations[index].target.display()
+            )
+            .and_then(|_| output.flush())
+            .map_err(output_error)?;
+
+            let mut line = String::new();
+            let read = input.read_line(&mut line).map_err(|err| Error {
+                kind: ErrorKind::ReadFile,
+                value: Some(format!("stdin\n{}", err)),
+            })?;
+            // Closed input stops the review
+            if read == 0 {
+                break Answer::Quit;
+            }
+            match line.trim() {
+                "k" | "keep" => break Answer::Tag(Tag::Keep),
+                "l" | "later" => break Answer::Tag(Tag::Later),
+                "n" | "never" => break Answer::Tag(Tag::Never),
+                "s" | "skip" => break Answer::Skip,
+                "q" | "quit" => break Answer::Quit,
+                _ => continue,
+            }
+        };
+
+        match answer {
+            Answer::Tag(tag) => {
+                dump.operations[index].tag = Some(tag);
+                dumpfile::write_dump(dump_file, &dump)?;
+            }
+            Answer::Skip => {}
+            Answer::Quit => break,
+        }
+    }
+
+    Ok(())
+}
+
+enum Answer {
+    Tag(Tag),
+    Skip,
+    Quit,
+}
+
+#[cfg(test)]
+mod test {
+    extern crate tempfile;
+    use super::*;
+    use crate::solver::Operation;
+    use std::io::Cursor;
+    use std::path::PathBuf;
+
+    #[test]
+    fn review_operations() {
+        let tempdir = tempfile::tempdir().expect("Error creating temp directory");
+        println!("Running test in '{:?}'", tempdir);
+        let dump_file = tempdir.path().join("dump.json");
+        let operations: Vec<Operation> = ["a", "b", "c", "d"]
+            .iter()
+            .map(|name| Operation {
+                source: PathBuf::from(name),
+                target: PathBuf::from(format!("{}.renamed", name)),
+                tag: None,
+                link: None,
+            })
+            .collect();
+        dumpfile::write_to_file(&dump_file, &operations, Some("run")).unwrap();
+
+        // Invalid answers are asked again and quitting keeps the answered tags
+        let mut output = Vec::new();
+        review(&dump_file, Cursor::new("k\nwhat\nn\ns\nq\n"), &mut output).unwrap();
+        let tags: Vec<Option<Tag>> = dumpfile::read_dump(&dump_file)
+            .unwrap()
+            .operations
+            .iter()
+            .map(|operation| operation.tag)
+            .collect();
+        assert_eq!(tags, vec![Some(Tag::Keep), Some(Tag::Never), None, None]);
+        assert!(String::from_utf8(output)
+            .unwrap()
+            .contains("[4/4] d -> d.renamed"));
+
+        // Resumed review only asks for untagged operations
+        let mut output = Vec::new();
+        review(&dump_file, Cursor::new("later\n"), &mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(!output.contains("[1/4]"));
+        assert!(output.contains("[3/4] c -> c.renamed"));
+        let tags: Vec<Option<Tag>> = dumpfile::read_dump(&dump_file)
+            .unwrap()
+            .operations
+            .iter()
+            .map(|operation| operation.tag)
+            .collect();
+        assert_eq!(
+            tags,
+            vec![Some(Tag::Keep), Some(Tag::Never), Some(Tag::Later), None]
+        );
+        // Run ID of the dump is kept
+        let dump = dumpfile::read_dump(&dump_file).unwrap();
+        assert_eq!(dump.run_id.as_deref(), Some("run"));
+    }
+}
@@ -0,0 +1,243 @@
+use crate::fileutils::{input_name, read_input};
+use crate::settings::{parse_value, Settings};
+use crate::template::Template;
+use regex::Regex;
+use std::path::Path;
+
+/// Rename rule read from a rules file. Rules files use an INI-like syntax where each section
+/// defines a rule scoped to the file names matching any of its comma separated globs. Keys outside
+/// of a section define a rule for all files:
+///
+/// ```text
+/// # Comments start with '#' or ';'
+/// pattern = "\s+"
+/// replacement = "_"
+///
+/// [*.jpg, *.jpeg]
+/// pattern = "^IMG_"
+/// replacement = "photo_"
+/// limit = 1
+/// ```
+///
+/// Rules are applied in order, each one to the output of the previous matching rule.
+pub struct Rule {
+    scope: Vec<String>,
+    pub expression: Regex,
+    pub replacement: Template,
+    pub limit: usize,
+}
+
+impl Rule {
+    /// Check if the rule applies to the given file name.
+    pub fn matches(&self, file_name: &str) -> bool {
+        self.scope.is_empty() || self.scope.iter().any(|glob| glob_match(glob, file_name))
+    }
+}
+
+/// Rule keys as written in the rules file before validation.
+#[derive(Default)]
+struct RuleKeys {
+    scope: Vec<String>,
+    pattern: Option<String>,
+    replacement: Option<String>,
+    limit: Option<usize>,
+    /// Line where the rule starts, for error messages
+    line: usize,
+}
+
+/// Read rules from a file, or the standard input if the path is `-`.
+pub fn read_from_file(
+    path: &Path,
+    settings: &Settings,
+    default_limit: usize,
+) -> Result<Vec<Rule>, String> {
+    let content = read_input(path)
+        .map_err(|err| format!("Cannot read rules file {}\n{}", input_name(path), err))?;
+    parse(&content, settings, default_limit)
+        .map_err(|err| format!("Invalid rules file {}\n{}", input_name(path), err))
+}
+
+/// Parse rules file content. Rules without `limit` key use the given default limit.
+pub fn parse(
+    content: &str,
+    settings: &Settings,
+    default_limit: usize,
+) -> Result<Vec<Rule>, String> {
+    let mut rules = vec![RuleKeys {
+        line: 1,
+        ..RuleKeys::default()
+    }];
+
+    for (index, line) in content.lines().enumerate() {
+        let line = line.trim();
+        let line_error = |message: &str| format!("Line {}: {}", index + 1, message);
+
+        if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+            continue;
+        }
+        if let Some(scope) = line.strip_prefix('[') {
+            let scope = match scope.strip_suffix(']') {
+                Some(scope) => scope,
+                None => return Err(line_error("unclosed section header")),
+            };
+            let scope: Vec<String> = scope
+                .split(',')
+                .map(|glob| glob.trim().to_string())
+                .filter(|glob| !glob.is_empty())
+                .collect();
+            if scope.is_empty() {
+                return Err(line_error("empty rule scope"));
+            }
+            rules.push(RuleKeys {
+                scope,
+                line: index + 1,
+                ..RuleKeys::default()
+            });
+            continue;
+        }
+
+        let (key, value) = match line.split_once('=') {
+            Some((key, value)) => (
+                key.trim(),
+                parse_value(value.trim()).map_err(|err| line_error(&err))?,
+            ),
+            None => return Err(line_error("expected 'key = value'")),
+        };
+        let rule = rules.last_mut().unwrap();
+        match key {
+            "pattern" => rule.pattern = Some(value),
+            "replacement" => rule.replacement = Some(value),
+            "limit" => match value.parse::<usize>() {
+                Ok(limit) => rule.limit = Some(limit),
+                Err(_) => return Err(line_error("limit is not an integer")),
+            },
+            key => return Err(line_error(&format!("unknown key '{}'", key))),
+        }
+    }
+
+    // Global rule is optional
+    if rules[0].pattern.is_none() && rules[0].replacement.is_none() && rules[0].limit.is_none() {
+        rules.remove(0);
+    }
+
+    rules
+        .into_iter()
+        .map(|keys| {
+            let rule_error = |message: String| format!("Rule at line {}: {}", keys.line, message);
+            let pattern = keys
+                .pattern
+                .ok_or_else(|| rule_error("missing 'pattern'".to_string()))?;
+            let replacement = keys
+                .replacement
+                .ok_or_else(|| rule_error("missing 'replacement'".to_string()))?;
+            Ok(Rule {
+                expression: Regex::new(&pattern).map_err(|err| rule_error(err.to_string()))?,
+                replacement: Template::parse_with_settings(&replacement, settings)
+                    .map_err(rule_error)?,
+                limit: keys.limit.unwrap_or(default_limit),
+                scope: keys.scope,
+            })
+        })
+        .collect()
+}
+
+/// Match a file name against a glob supporting `*` for any sequence of characters and `?` for a
+/// single character.
+pub fn glob_match(glob: &str, name: &str) -> bool {
+    let glob: Vec<char> = glob.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    let (mut glob_index, mut name_index) = (0, 0);
+    // Position of the last star and the name index it is matching from
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while name_index < name.len() {
+        match glob.get(glob_index) {
+            Some('*') => {
+                backtrack = Some((glob_index, name_index));
+                glob_index += 1;
+            }
+            Some(&c) if c == '?' || c == name[name_index] => {
+                glob_index += 1;
+                name_index += 1;
+            }
+            _ => match backtrack {
+                // Let the last star consume one more character
+                Some((star_index, star_name_index)) => {
+                    backtrack = Some((star_index, star_name_index + 1));
+                    glob_index = star_index + 1;
+                    name_index = star_name_index + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+    glob[glob_index..].iter().all(|c| *c == '*')
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_rules() {
+        let rules = parse(
+            r#"
+            # Applied to all files
+            pattern = "\s+"
+            replacement = "_"
+
+            [*.jpg, *.JPG]
+            pattern = "^IMG"
+            replacement = "photo"
+            limit = 0
+            "#,
+            &Settings::default(),
+            1,
+        )
+        .unwrap();
+        assert_eq!(rules.len(), 2);
+        assert!(rules[0].matches("notes.txt"));
+        assert_eq!(rules[0].limit, 1);
+        assert!(rules[1].matches("IMG_01.jpg"));
+        assert!(rules[1].matches("IMG_01.JPG"));
+        assert!(!rules[1].matches("IMG_01.png"));
+        assert_eq!(rules[1].limit, 0);
+
+        // Global rule is optional
+        let settings = Settings::default();
+        let rules = parse("[*.mp4]\npattern = a\nreplacement = b", &settings, 1).unwrap();
+        assert_eq!(rules.len(), 1);
+        assert!(!rules[0].matches("a.jpg"));
+    }
+
+    #[test]
+    fn parse_rules_errors() {
+        let no_placeholders = Settings::default();
+        assert!(parse("pattern = a", &no_placeholders, 1).is_err());
+        assert!(parse("[*.jpg]\nreplacement = a", &no_placeholders, 1).is_err());
+        assert!(parse("[*.jpg]\npattern = (\nreplacement = a", &no_placeholders, 1).is_err());
+        assert!(parse(
+            "[*.jpg]\npattern = a\nreplacement = {end}",
+            &no_placeholders,
+            1
+        )
+        .is_err());
+        assert!(parse("[*.jpg]\npattern = a\nlimit = x", &no_placeholders, 1).is_err());
+        assert!(parse("[*.jpg]\nunknown = a", &no_placeholders, 1).is_err());
+        assert!(parse("[]\npattern = a\nreplacement = b", &no_placeholders, 1).is_err());
+        assert!(parse("[*.jpg\npattern = a\nreplacement = b", &no_placeholders, 1).is_err());
+    }
+
+    #[test]
+    fn globs() {
+        assert!(glob_match("*", ""));
+        assert!(glob_match("*.jpg", "photo.jpg"));
+        assert!(glob_match("*.jpg", ".jpg"));
+        assert!(!glob_match("*.jpg", "photo.jpeg"));
+        assert!(glob_match("IMG_????.*", "IMG_0001.png"));
+        assert!(!glob_match("IMG_????.*", "IMG_001.png"));
+        assert!(glob_match("*a*b*", "xxaxxbxx"));
+        assert!(!glob_match("*a*b", "xxaxxbxx"));
+        assert!(glob_match("日本*", "日本語.txt"));
+    }
+}
@@ -0,0 +1,64 @@
+use std::collections::hash_map::RandomState;
+use std::env;
+use std::hash::{BuildHasher, Hasher};
+
+/// Environment variable to provide the run ID instead of generating it
+pub const RUN_ID_ENV: &str = "RNR_RUN_ID";
+
+/// Get the unique ID of this run. Automation can provide its own ID with `RNR_RUN_ID` environment
+/// variable to correlate artifacts of several steps, otherwise a random one is generated.
+pub fn run_id() -> Result<String, String> {
+    match env::var(RUN_ID_ENV) {
+        Ok(id) => validate(&id).map(|_| id),
+        Err(env::VarError::NotPresent) => Ok(generate()),
+        Err(env::VarError::NotUnicode(_)) => Err(format!("{} must be valid UTF-8", RUN_ID_ENV)),
+    }
+}
+
+/// Generate a random ID of 16 hexadecimal digits.
+fn generate() -> String {
+    // Hasher keys are randomly generated per process
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u128(
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|duration| duration.as_nanos())
+            .unwrap_or(0),
+    );
+    hasher.write_u32(std::process::id());
+    format!("{:016x}", hasher.finish())
+}
+
+/// Run IDs are part of file names, so only a safe set of characters is allowed.
+pub fn validate(id: &str) -> Result<(), String> {
+    let valid_char = |c: char| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.';
+    if id.is_empty() || id.len() > 64 || !id.chars().all(valid_char) || id.starts_with('.') {
+        return Err(format!(
+            "Invalid run ID '{}', use up to 64 letters, digits, '-', '_' or '.'",
+            id
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn generate_run_id() {
+        let id = generate();
+        assert_eq!(id.len(), 16);
+        assert!(validate(&id).is_ok());
+        assert_ne!(id, generate());
+    }
+
+    #[test]
+    fn validate_run_id() {
+        assert!(validate("deploy-2021.06_15").is_ok());
+        assert!(validate("").is_err());
+        assert!(validate("../escape").is_err());
+        assert!(validate("with space").is_err());
+        assert!(validate(&"x".repeat(65)).is_err());
+    }
+}
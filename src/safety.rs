@@ -0,0 +1,212 @@
+use crate::error::*;
+use crate::plan;
+use crate::solver::Operation;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Component, Path, PathBuf};
+
+/// Maximum number of operations allowed by the paranoid profile
+pub const PARANOID_MAX_OPS: usize = 1000;
+
+/// Guards against mistakes. All of them are enabled by the paranoid profile, which is the default
+/// for the root user.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Safety {
+    /// Ask for confirmation before renaming
+    pub confirm: bool,
+    /// Targets must stay in the directory of their sources
+    pub contain: bool,
+    /// Maximum number of operations of a run
+    pub max_ops: Option<usize>,
+    /// Hash of the approved plan, other plans are refused
+    pub plan_hash: Option<String>,
+}
+
+impl Safety {
+    pub fn paranoid() -> Safety {
+        Safety {
+            confirm: true,
+            contain: true,
+            max_ops: Some(PARANOID_MAX_OPS),
+            plan_hash: None,
+        }
+    }
+
+    /// Check the planned operations against the limits, the containment guard and the approved
+    /// plan hash.
+    pub fn check(&self, operations: &[Operation]) -> Result<()> {
+        if let Some(approved) = &self.plan_hash {
+            let plan_hash = plan::hash(operations);
+            if plan_hash != *approved {
+                return Err(Error {
+                    kind: ErrorKind::Unsafe,
+                    value: Some(format!(
+                        "plan hash {} differs from the approved {} (--plan-hash)",
+                        plan_hash, approved
+                    )),
+                });
+            }
+        }
+        if let Some(max_ops) = self.max_ops.filter(|max_ops| operations.len() > *max_ops) {
+            return Err(Error {
+                kind: ErrorKind::Unsafe,
+                value: Some(format!(
+                    "{} operations planned, more than the limit of {} (--max-ops)",
+                    operations.len(),
+                    max_ops
+                )),
+            });
+        }
+        if self.contain {
+            for operation in operations {
+                if !is_contained(&operation.source, &operation.target) {
+                    return Err(Error {
+                        kind: ErrorKind::Unsafe,
+                        value: Some(format!(
+                            "{} -> {}\ntarget is outside the source directory (--contain)",
+                            operation.source.display(),
+                            operation.target.display()
+                        )),
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Ask for confirmation to apply the given number of operations. Anything but yes declines.
+    pub fn ask<R: BufRead, W: Write>(
+        &self,
+        count: usize,
+        mut input: R,
+        mut output: W,
+    ) -> Result<()> {
+        write!(output, "Rename {} paths? [y/N] ", count)
+            .and_then(|_| output.flush())
+            .map_err(|err| Error {
+                kind: ErrorKind::CreateFile,
+                value: Some(format!("stdout\n{}", err)),
+            })?;
+        let mut answer = String::new();
+        input.read_line(&mut answer).map_err(|err| Error {
+            kind: ErrorKind::ReadFile,
+            value: Some(format!("stdin\n{}", err)),
+        })?;
+        match answer.trim().to_lowercase().as_str() {
+            "y" | "yes" => Ok(()),
+            _ => Err(Error {
+                kind: ErrorKind::Aborted,
+                value: None,
+            }),
+        }
+    }
+}
+
+/// Terminal to read answers from when the standard input is taken by a rules file or a path list
+#[cfg(windows)]
+const TERMINAL_PATH: &str = "CONIN$";
+#[cfg(not(windows))]
+const TERMINAL_PATH: &str = "/dev/tty";
+
+/// Open the terminal of the process to read answers from.
+pub fn open_terminal() -> Result<BufReader<File>> {
+    match File::open(TERMINAL_PATH) {
+        Ok(terminal) => Ok(BufReader::new(terminal)),
+        Err(err) => Err(Error {
+            kind: ErrorKind::ReadFile,
+            value: Some(format!(
+                "{}\n{}\nthe confirmation is read from the terminal when standard input is read as a file",
+                TERMINAL_PATH, err
+            )),
+        }),
+    }
+}
+
+/// Check if the process runs with root privileges.
+pub fn is_root() -> bool {
+    #[cfg(unix)]
+    {
+        // SAFETY: geteuid takes no arguments, never fails and has no side effects
+        unsafe { libc::geteuid() == 0 }
+    }
+    #[cfg(not(unix))]
+    {
+        false
+    }
+}
+
+/// Check if the target stays in the directory of the source or its subdirectories, resolving `.`
+/// and `..` components lexically.
+fn is_contained(source: &Path, target: &Path) -> bool {
+    let parent = source.parent().map(lexical).unwrap_or_default();
+    match lexical(target).strip_prefix(&parent) {
+        Ok(relative) => relative
+            .components()
+            .all(|component| matches!(component, Component::Normal(_))),
+        Err(_) => false,
+    }
+}
+
+fn lexical(path: &Path) -> PathBuf {
+    let mut resolved = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir if resolved.file_name().is_some() => {
+                resolved.pop();
+            }
+            component => resolved.push(component),
+        }
+    }
+    resolved
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Cursor;
+
+    fn operation(source: &str, target: &str) -> Operation {
+        Operation {
+            source: PathBuf::from(source),
+            target: PathBuf::from(target),
+            tag: None,
+            link: None,
+        }
+    }
+
+    #[test]
+    fn safety_check() {
+        let safety = Safety::paranoid();
+        assert!(safety
+            .check(&[operation("a", "b"), operation("./dir/a", "dir/sub/a")])
+            .is_ok());
+        assert!(safety.check(&[operation("dir/a", "dir/../b")]).is_err());
+        assert!(safety.check(&[operation("a", "../a")]).is_err());
+        assert!(safety.check(&[operation("/data/a", "/tmp/a")]).is_err());
+        assert!(safety.check(&[operation("../a", "../b")]).is_ok());
+
+        let operations = vec![operation("a", "b"); PARANOID_MAX_OPS + 1];
+        assert!(safety.check(&operations).is_err());
+        assert!(Safety::default().check(&operations).is_ok());
+
+        let plan = [operation("a", "b")];
+        let safety = Safety {
+            plan_hash: Some(plan::hash(&plan)),
+            ..Safety::default()
+        };
+        assert!(safety.check(&plan).is_ok());
+        let err = safety.check(&[operation("a", "c")]).unwrap_err();
+        assert_eq!(err.kind, ErrorKind::Unsafe);
+    }
+
+    #[test]
+    fn ask_confirmation() {
+        let safety = Safety::paranoid();
+        let mut output = Vec::new();
+        assert!(safety.ask(2, Cursor::new("y\n"), &mut output).is_ok());
+        assert_eq!(String::from_utf8(output).unwrap(), "Rename 2 paths? [y/N] ");
+        assert!(safety.ask(2, Cursor::new("\n"), Vec::new()).is_err());
+        assert!(safety.ask(2, Cursor::new(""), Vec::new()).is_err());
+    }
+}
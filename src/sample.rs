@@ -0,0 +1,223 @@
+use crate::solver::{Operation, Operations};
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::hash::{BuildHasher, Hasher};
+use std::path::Path;
+
+/// Random sample of the planned renames, to spot-check a pattern on large batches
+#[derive(Clone, Debug, PartialEq)]
+pub struct Sample {
+    pub size: usize,
+    /// Seed of the selection, the same seed selects the same renames of the same batch
+    pub seed: u64,
+}
+
+impl Sample {
+    /// Create a sample of the given size with a random seed unless one is given.
+    pub fn new(size: usize, seed: Option<u64>) -> Sample {
+        // Hasher keys are randomly generated per process
+        let seed = seed.unwrap_or_else(|| RandomState::new().build_hasher().finish());
+        Sample { size, seed }
+    }
+
+    /// Select the renames of the sample from the solved operations. Operations depending on each
+    /// other are drawn together, so a sample may exceed its size to keep whole chains, and the
+    /// selected operations keep their solved order.
+    pub fn select(&self, operations: Operations) -> Operations {
+        let chains = chains(&operations);
+
+        // Partial Fisher-Yates shuffle, chains are drawn until the sample is complete
+        let mut order: Vec<usize> = (0..chains.len()).collect();
+        let mut random = SplitMix64(self.seed);
+        let mut selected = vec![false; chains.len()];
+        let mut size = 0;
+        for index in 0..order.len() {
+            if size >= self.size {
+                break;
+            }
+            let remaining = (order.len() - index) as u64;
+            let drawn = index + (random.next() % remaining) as usize;
+            order.swap(index, drawn);
+            selected[order[index]] = true;
+            size += chains[order[index]].len();
+        }
+
+        let mut sample: Vec<(usize, Operation)> = Vec::new();
+        let mut operations: Vec<Option<Operation>> = operations.into_iter().map(Some).collect();
+        for (chain, indexes) in chains.iter().enumerate() {
+            if selected[chain] {
+                sample.extend(
+                    indexes
+                        .iter()
+                        .map(|&index| (index, operations[index].take().unwrap())),
+                );
+            }
+        }
+        sample.sort_by_key(|(index, _)| *index);
+        sample.into_iter().map(|(_, operation)| operation).collect()
+    }
+}
+
+/// Group the indexes of operations depending on each other: an operation freeing the target of
+/// another one, or renaming a directory holding the source or target of another one. Chains are
+/// sorted by their first operation, so the groups only depend on the solved plan.
+fn chains(operations: &[Operation]) -> Vec<Vec<usize>> {
+    let mut parents: Vec<usize> = (0..operations.len()).collect();
+    let sources: HashMap<&Path, usize> = operations
+        .iter()
+        .enumerate()
+        .map(|(index, operation)| (operation.source.as_path(), index))
+        .collect();
+    let targets: HashMap<&Path, usize> = operations
+        .iter()
+        .enumerate()
+        .map(|(index, operation)| (operation.target.as_path(), index))
+        .collect();
+    for (index, operation) in operations.iter().enumerate() {
+        if let Some(&freed) = sources.get(operation.target.as_path()) {
+            union(&mut parents, index, freed);
+        }
+        let ancestors = operation
+            .source
+            .ancestors()
+            .chain(operation.target.ancestors());
+        for ancestor in ancestors {
+            let renamed = sources
+                .get(ancestor)
+                .into_iter()
+                .chain(targets.get(ancestor));
+            for &renamed in renamed {
+                union(&mut parents, index, renamed);
+            }
+        }
+    }
+
+    let mut chains: Vec<Vec<usize>> = Vec::new();
+    let mut roots: HashMap<usize, usize> = HashMap::new();
+    for index in 0..operations.len() {
+        let root = find(&mut parents, index);
+        let chain = *roots.entry(root).or_insert_with(|| {
+            chains.push(Vec::new());
+            chains.len() - 1
+        });
+        chains[chain].push(index);
+    }
+    chains
+}
+
+fn find(parents: &mut [usize], index: usize) -> usize {
+    let mut root = index;
+    while parents[root] != root {
+        root = parents[root];
+    }
+    let mut current = index;
+    while parents[current] != root {
+        let next = parents[current];
+        parents[current] = root;
+        current = next;
+    }
+    root
+}
+
+fn union(parents: &mut [usize], first: usize, second: usize) {
+    let first = find(parents, first);
+    let second = find(parents, second);
+    parents[first.max(second)] = first.min(second);
+}
+
+/// Small seedable pseudo-random generator, good enough to draw samples
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn operation(source: &str, target: &str) -> Operation {
+        Operation {
+            source: PathBuf::from(source),
+            target: PathBuf::from(target),
+            tag: None,
+            link: None,
+        }
+    }
+
+    fn targets(operations: &[Operation]) -> Vec<PathBuf> {
+        operations
+            .iter()
+            .map(|operation| operation.target.clone())
+            .collect()
+    }
+
+    #[test]
+    fn select_sample() {
+        let operations: Operations = (0..100)
+            .map(|index| operation(&format!("old_{}", index), &format!("new_{}", index)))
+            .collect();
+
+        let sample = Sample::new(10, Some(42));
+        let selected = sample.select(operations.clone());
+        assert_eq!(selected.len(), 10);
+        for operation in &selected {
+            assert_eq!(
+                operation.source,
+                PathBuf::from(operation.target.to_string_lossy().replace("new_", "old_"))
+            );
+        }
+        // Same seed, same sample
+        assert_eq!(
+            targets(&sample.select(operations.clone())),
+            targets(&selected)
+        );
+        assert_ne!(
+            targets(&Sample::new(10, Some(43)).select(operations.clone())),
+            targets(&selected)
+        );
+
+        // Small batches are selected whole
+        assert_eq!(
+            targets(&Sample::new(200, None).select(operations.clone())),
+            targets(&operations)
+        );
+    }
+
+    #[test]
+    fn select_sample_chains() {
+        // Solved order: freed targets and directory contents first
+        let operations = vec![
+            operation("b", "c"),
+            operation("a", "b"),
+            operation("dir/file", "dir/renamed"),
+            operation("dir", "folder"),
+            operation("other", "moved/other"),
+            operation("moved", "kept"),
+            operation("single", "alone"),
+        ];
+        assert_eq!(
+            chains(&operations),
+            vec![vec![0, 1], vec![2, 3], vec![4, 5], vec![6]]
+        );
+
+        for seed in 0..20 {
+            let selected = Sample::new(1, Some(seed)).select(operations.clone());
+            let selected = targets(&selected);
+            let chain = operations
+                .chunks(2)
+                .map(targets)
+                .find(|chain| chain.contains(&selected[0]))
+                .unwrap();
+            // Whole chains are selected, in their solved order
+            assert_eq!(selected, chain);
+        }
+    }
+}
@@ -0,0 +1,620 @@
+use crate::config::{Config, ReplaceMode, RunMode};
+use crate::driver::DirMode;
+use crate::dumpfile;
+use crate::error::*;
+use crate::output::Printer;
+use crate::renamer::{absolute_operations, Renamer};
+use crate::safety::Safety;
+use crate::settings::Settings;
+use crate::solver::Operations;
+use crate::template::Template;
+use regex::Regex;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::io::{BufRead, Write};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+// JSON-RPC 2.0 error codes
+const PARSE_ERROR: i64 = -32700;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const SERVER_ERROR: i64 = -32000;
+
+/// Server for editor integrations. It reads JSON-RPC 2.0 requests line by line and writes one
+/// response line per request, except for notifications without an ID. Supported methods:
+///
+/// * `preview`: generate a plan from an expression, a replacement and a list of paths.
+/// * `apply`: execute a previously generated plan by its ID.
+pub struct Server {
+    config: Arc<Config>,
+    plans: HashMap<u64, Plan>,
+    next_plan_id: u64,
+}
+
+/// Operations generated by a preview request waiting to be applied.
+struct Plan {
+    params: PreviewParams,
+    operations: Operations,
+}
+
+#[derive(Deserialize)]
+struct Request {
+    /// Missing for notifications, which get no response
+    #[serde(default, deserialize_with = "deserialize_id")]
+    id: Option<Value>,
+    method: String,
+    #[serde(default)]
+    params: Value,
+}
+
+#[derive(Serialize)]
+struct Response {
+    jsonrpc: &'static str,
+    id: Value,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    result: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<ResponseError>,
+}
+
+#[derive(Serialize)]
+struct ResponseError {
+    code: i64,
+    message: String,
+}
+
+#[derive(Clone, Deserialize)]
+struct PreviewParams {
+    expression: String,
+    replacement: String,
+    paths: Vec<String>,
+    #[serde(default = "default_limit")]
+    limit: usize,
+    #[serde(default)]
+    include_dirs: bool,
+    #[serde(default)]
+    recursive: bool,
+    #[serde(default)]
+    max_depth: Option<usize>,
+    #[serde(default)]
+    hidden: bool,
+    #[serde(default)]
+    hidden_attr: bool,
+}
+
+fn default_limit() -> usize {
+    1
+}
+
+#[derive(Serialize)]
+struct PreviewResult<'a> {
+    plan_id: u64,
+    run_id: &'a str,
+    operations: &'a Operations,
+}
+
+#[derive(Deserialize)]
+struct ApplyParams {
+    plan_id: u64,
+}
+
+#[derive(Serialize)]
+struct ApplyResult<'a> {
+    applied: usize,
+    failed: usize,
+    /// Operations that failed, or were skipped because they depend on a failed one
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    errors: Vec<FailedOperation>,
+    run_id: &'a str,
+    /// Slowest operations when `--slowest` is set
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    slowest: Vec<SlowOperation>,
+}
+
+#[derive(Serialize)]
+struct FailedOperation {
+    source: PathBuf,
+    target: PathBuf,
+    message: String,
+}
+
+#[derive(Serialize)]
+struct SlowOperation {
+    source: PathBuf,
+    target: PathBuf,
+    millis: f64,
+}
+
+impl Server {
+    pub fn new(config: &Arc<Config>) -> Server {
+        Server {
+            config: config.clone(),
+            plans: HashMap::new(),
+            next_plan_id: 1,
+        }
+    }
+
+    /// Process requests from input until it is closed.
+    pub fn serve<R: BufRead, W: Write>(&mut self, input: R, mut output: W) -> Result<()> {
+        for line in input.lines() {
+            let line = line.map_err(|err| Error {
+                kind: ErrorKind::ReadFile,
+                value: Some(format!("stdin\n{}", err)),
+            })?;
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let response = match self.handle(&line) {
+                Some(response) => response,
+                None => continue,
+            };
+            let write_result = serde_json::to_writer(&mut output, &response)
+                .map_err(|err| err.to_string())
+                .and_then(|_| writeln!(output).map_err(|err| err.to_string()))
+                .and_then(|_| output.flush().map_err(|err| err.to_string()));
+            if let Err(err) = write_result {
+                return Err(Error {
+                    kind: ErrorKind::CreateFile,
+                    value: Some(format!("stdout\n{}", err)),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Handle a single request line and generate its response. Notifications are processed
+    /// without response.
+    fn handle(&mut self, line: &str) -> Option<Response> {
+        let request: Request = match serde_json::from_str(line) {
+            Ok(request) => request,
+            Err(err) => {
+                return Some(Response::error(Value::Null, PARSE_ERROR, err.to_string()));
+            }
+        };
+
+        let result = match request.method.as_str() {
+            "preview" => parse_params(request.params).and_then(|params| self.preview(params)),
+            "apply" => parse_params(request.params).and_then(|params| self.apply(params)),
+            method => Err(ResponseError {
+                code: METHOD_NOT_FOUND,
+                message: format!("Unknown method '{}'", method),
+            }),
+        };
+
+        let id = request.id?;
+        Some(match result {
+            Ok(result) => Response {
+                jsonrpc: "2.0",
+                id,
+                result: Some(result),
+                error: None,
+            },
+            Err(error) => Response {
+                jsonrpc: "2.0",
+                id,
+                result: None,
+                error: Some(error),
+            },
+        })
+    }
+
+    /// Generate operations and store them as a plan to be applied later.
+    fn preview(&mut self, params: PreviewParams) -> std::result::Result<Value, ResponseError> {
+        let config = Arc::new(plan_config(&params, &self.config, false)?);
+        let renamer = Renamer::new(&config).map_err(server_error)?;
+        let operations = renamer.process().map_err(server_error)?;
+
+        let plan_id = self.next_plan_id;
+        self.next_plan_id += 1;
+        let result = serde_json::to_value(PreviewResult {
+            plan_id,
+            run_id: &self.config.run_id,
+            operations: &operations,
+        })
+        .map_err(|err| ResponseError {
+            code: SERVER_ERROR,
+            message: err.to_string(),
+        })?;
+        self.plans.insert(plan_id, Plan { params, operations });
+
+        Ok(result)
+    }
+
+    /// Execute a stored plan. Plans can only be applied once.
+    fn apply(&mut self, params: ApplyParams) -> std::result::Result<Value, ResponseError> {
+        let plan = match self.plans.remove(&params.plan_id) {
+            Some(plan) => plan,
+            None => {
+                return Err(ResponseError {
+                    code: INVALID_PARAMS,
+                    message: format!("Unknown plan ID {}", params.plan_id),
+                })
+            }
+        };
+
+        let config = Arc::new(plan_config(&plan.params, &self.config, true)?);
+        let renamer = Renamer::new(&config).map_err(server_error)?;
+        renamer
+            .lock_operations(&plan.operations)
+            .map_err(server_error)?;
+        if self.config.dump {
+            let dump_operations = if self.config.abs {
+                absolute_operations(&plan.operations).map_err(server_error)?
+            } else {
+                plan.operations.clone()
+            };
+            dumpfile::dump_to_file(&dump_operations, &self.config.run_id).map_err(server_error)?;
+        }
+        let result = renamer.batch_rename(plan.operations);
+        let errors: Vec<FailedOperation> = renamer
+            .failures()
+            .into_iter()
+            .map(|failure| FailedOperation {
+                source: failure.source,
+                target: failure.target,
+                message: failure.message,
+            })
+            .collect();
+        // Failed operations are part of the result, other errors like a timeout are not
+        if let Err(err) = result {
+            if errors.is_empty() {
+                return Err(server_error(err));
+            }
+        }
+        let slowest = renamer
+            .slowest()
+            .into_iter()
+            .map(|timing| SlowOperation {
+                source: timing.source,
+                target: timing.target,
+                millis: timing.duration.as_secs_f64() * 1000.0,
+            })
+            .collect();
+
+        Ok(serde_json::to_value(ApplyResult {
+            applied: renamer.applied(),
+            failed: errors.len(),
+            errors,
+            run_id: &self.config.run_id,
+            slowest,
+        })
+        .unwrap_or(Value::Null))
+    }
+}
+
+impl Response {
+    fn error(id: Value, code: i64, message: String) -> Response {
+        Response {
+            jsonrpc: "2.0",
+            id,
+            result: None,
+            error: Some(ResponseError { code, message }),
+        }
+    }
+}
+
+/// Wrap any present ID, including null, to tell requests from notifications.
+fn deserialize_id<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> std::result::Result<Option<Value>, D::Error> {
+    serde::Deserialize::deserialize(deserializer).map(Some)
+}
+
+fn parse_params<T: serde::de::DeserializeOwned>(
+    params: Value,
+) -> std::result::Result<T, ResponseError> {
+    serde_json::from_value(params).map_err(|err| ResponseError {
+        code: INVALID_PARAMS,
+        message: err.to_string(),
+    })
+}
+
+fn server_error(error: Error) -> ResponseError {
+    ResponseError {
+        code: SERVER_ERROR,
+        message: format!(
+            "{}{}",
+            error.description(),
+            error.value.as_deref().unwrap_or("")
+        ),
+    }
+}
+
+/// Generate a silent configuration to run a plan from preview parameters. Settings, backups, safety
+/// checks and run ID are taken from the server configuration.
+fn plan_config(
+    params: &PreviewParams,
+    server: &Config,
+    force: bool,
+) -> std::result::Result<Config, ResponseError> {
+    let invalid_params = |message: String| ResponseError {
+        code: INVALID_PARAMS,
+        message,
+    };
+    let expression =
+        Regex::new(&params.expression).map_err(|err| invalid_params(err.to_string()))?;
+    let replacement = Template::parse_with_settings(&params.replacement, &server.settings)
+        .map_err(invalid_params)?;
+
+    let run_mode = if params.recursive {
+        RunMode::Recursive {
+            paths: params.paths.clone(),
+            max_depth: params.max_depth,
+            hidden: params.hidden,
+            hidden_attr: params.hidden_attr,
+        }
+    } else {
+        RunMode::Simple(params.paths.clone())
+    };
+
+    Ok(Config {
+        force,
+        backup: server.backup,
+        backup_keep: server.backup_keep,
+        dirs: params.include_dirs,
+        dump: false,
+        abs: false,
+        report: None,
+        time_filter: None,
+        capture_ranges: Vec::new(),
+        sample: None,
+        group_by: None,
+        move_to: None,
+        dir_mode: DirMode::Umask,
+        xattr_tag: false,
+        io_concurrency: 1,
+        skip_in_use: false,
+        collide_ignore_ext: server.collide_ignore_ext,
+        wait_lock: false,
+        ignore_errors: Vec::new(),
+        timeout: None,
+        progress: None,
+        slowest: server.slowest,
+        // Plans are applied by explicit requests, which stand for the confirmation
+        safety: Safety {
+            confirm: false,
+            ..server.safety.clone()
+        },
+        run_mode,
+        replace_mode: ReplaceMode::RegExp {
+            expression,
+            replacement,
+            limit: params.limit,
+        },
+        printer: Printer::silent(),
+        settings: Settings::default(),
+        driver: server.driver.clone(),
+        simulation: None,
+        run_id: server.run_id.clone(),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    extern crate tempfile;
+    use super::*;
+    use crate::driver::LocalDriver;
+    use std::fs;
+    use std::io::Cursor;
+    use std::path::Path;
+
+    fn request(server: &mut Server, request: &str) -> Value {
+        let mut output = Vec::new();
+        server
+            .serve(Cursor::new(request.to_string()), &mut output)
+            .unwrap();
+        serde_json::from_slice(&output).unwrap()
+    }
+
+    #[test]
+    fn preview_and_apply() {
+        let tempdir = tempfile::tempdir().expect("Error creating temp directory");
+        println!("Running test in '{:?}'", tempdir);
+        let temp_path = tempdir.path().to_str().unwrap();
+        let source = format!("{}/test_file.txt", temp_path);
+        fs::File::create(&source).expect("Error creating mock file...");
+
+        let mut server = Server::new(&Arc::new(Config {
+            force: false,
+            backup: false,
+            backup_keep: None,
+            dirs: false,
+            dump: false,
+            abs: false,
+            report: None,
+            time_filter: None,
+            capture_ranges: Vec::new(),
+            sample: None,
+            group_by: None,
+            move_to: None,
+            dir_mode: DirMode::Umask,
+            xattr_tag: false,
+            io_concurrency: 1,
+            skip_in_use: false,
+            collide_ignore_ext: false,
+            wait_lock: false,
+            ignore_errors: Vec::new(),
+            timeout: None,
+            progress: None,
+            slowest: Some(1),
+            safety: Safety::default(),
+            run_mode: RunMode::Serve,
+            replace_mode: ReplaceMode::ToASCII,
+            printer: Printer::silent(),
+            settings: Settings::default(),
+            driver: Arc::new(LocalDriver),
+            simulation: None,
+            run_id: "test".to_string(),
+        }));
+
+        let preview = request(
+            &mut server,
+            &format!(
+                r#"{{"jsonrpc":"2.0","id":1,"method":"preview","params":{{"expression":"test","replacement":"passed","paths":["{}"]}}}}"#,
+                source
+            ),
+        );
+        let result = preview.get("result").unwrap();
+        let plan_id = result.get("plan_id").unwrap().as_u64().unwrap();
+        assert_eq!(result.get("run_id").unwrap().as_str(), Some("test"));
+        let operations = result.get("operations").unwrap().as_array().unwrap();
+        assert_eq!(operations.len(), 1);
+        assert_eq!(
+            operations[0].get("target").unwrap().as_str().unwrap(),
+            format!("{}/passed_file.txt", temp_path)
+        );
+        // Preview does not modify files
+        assert!(Path::new(&source).exists());
+
+        let apply = request(
+            &mut server,
+            &format!(
+                r#"{{"jsonrpc":"2.0","id":2,"method":"apply","params":{{"plan_id":{}}}}}"#,
+                plan_id
+            ),
+        );
+        assert_eq!(
+            apply
+                .get("result")
+                .unwrap()
+                .get("applied")
+                .unwrap()
+                .as_u64(),
+            Some(1)
+        );
+        assert_eq!(
+            apply.get("result").unwrap().get("run_id").unwrap().as_str(),
+            Some("test")
+        );
+        assert_eq!(
+            apply.get("result").unwrap().get("failed").unwrap().as_u64(),
+            Some(0)
+        );
+        let slowest = apply.get("result").unwrap().get("slowest").unwrap();
+        assert_eq!(slowest.as_array().unwrap().len(), 1);
+        assert!(Path::new(&format!("{}/passed_file.txt", temp_path)).exists());
+
+        // Plans cannot be applied twice
+        let apply = request(
+            &mut server,
+            &format!(
+                r#"{{"jsonrpc":"2.0","id":3,"method":"apply","params":{{"plan_id":{}}}}}"#,
+                plan_id
+            ),
+        );
+        assert!(apply.get("error").is_some());
+
+        // Paths gone since the preview are reported as failed operations
+        let preview = request(
+            &mut server,
+            &format!(
+                r#"{{"jsonrpc":"2.0","id":4,"method":"preview","params":{{"expression":"passed","replacement":"failed","paths":["{}/passed_file.txt"]}}}}"#,
+                temp_path
+            ),
+        );
+        let plan_id = preview.get("result").unwrap().get("plan_id").unwrap();
+        fs::remove_file(format!("{}/passed_file.txt", temp_path)).unwrap();
+        let apply = request(
+            &mut server,
+            &format!(
+                r#"{{"jsonrpc":"2.0","id":5,"method":"apply","params":{{"plan_id":{}}}}}"#,
+                plan_id
+            ),
+        );
+        let result = apply.get("result").unwrap();
+        assert_eq!(result.get("applied").unwrap().as_u64(), Some(0));
+        assert_eq!(result.get("failed").unwrap().as_u64(), Some(1));
+        let errors = result.get("errors").unwrap().as_array().unwrap();
+        assert_eq!(
+            errors[0].get("source").unwrap().as_str().unwrap(),
+            format!("{}/passed_file.txt", temp_path)
+        );
+        assert!(errors[0].get("message").is_some());
+    }
+
+    #[test]
+    fn errors() {
+        let tempdir = tempfile::tempdir().expect("Error creating temp directory");
+        println!("Running test in '{:?}'", tempdir);
+        let source = tempdir.path().join("test_file.txt");
+        fs::File::create(&source).expect("Error creating mock file...");
+
+        let mut server = Server::new(&Arc::new(Config {
+            force: false,
+            backup: false,
+            backup_keep: None,
+            dirs: false,
+            dump: false,
+            abs: false,
+            report: None,
+            time_filter: None,
+            capture_ranges: Vec::new(),
+            sample: None,
+            group_by: None,
+            move_to: None,
+            dir_mode: DirMode::Umask,
+            xattr_tag: false,
+            io_concurrency: 1,
+            skip_in_use: false,
+            collide_ignore_ext: false,
+            wait_lock: false,
+            ignore_errors: Vec::new(),
+            timeout: None,
+            progress: None,
+            slowest: None,
+            safety: Safety::paranoid(),
+            run_mode: RunMode::Serve,
+            replace_mode: ReplaceMode::ToASCII,
+            printer: Printer::silent(),
+            settings: Settings::default(),
+            driver: Arc::new(LocalDriver),
+            simulation: None,
+            run_id: "test".to_string(),
+        }));
+
+        // Notifications get no response, even when they fail
+        let mut output = Vec::new();
+        server
+            .serve(
+                Cursor::new(r#"{"jsonrpc":"2.0","method":"unknown"}"#),
+                &mut output,
+            )
+            .unwrap();
+        assert!(output.is_empty());
+
+        let error_code = |response: Value| {
+            response
+                .get("error")
+                .and_then(|error| error.get("code"))
+                .and_then(|code| code.as_i64())
+        };
+        assert_eq!(
+            error_code(request(&mut server, "not json")),
+            Some(PARSE_ERROR)
+        );
+        assert_eq!(
+            error_code(request(&mut server, r#"{"id":1,"method":"unknown"}"#)),
+            Some(METHOD_NOT_FOUND)
+        );
+        assert_eq!(
+            error_code(request(
+                &mut server,
+                r#"{"id":1,"method":"preview","params":{"expression":"(","replacement":"","paths":[]}}"#
+            )),
+            Some(INVALID_PARAMS)
+        );
+        // Safety checks of the server apply to plans
+        assert_eq!(
+            error_code(request(
+                &mut server,
+                &format!(
+                    r#"{{"id":1,"method":"preview","params":{{"expression":"test","replacement":"../test","paths":["{}"]}}}}"#,
+                    source.display()
+                )
+            )),
+            Some(SERVER_ERROR)
+        );
+    }
+}
@@ -0,0 +1,218 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// Environment variable to override the default configuration file path.
+pub const CONFIG_ENV_VAR: &str = "RNR_CONFIG";
+
+/// Settings loaded from the user configuration file. The file uses a simple INI-like syntax:
+///
+/// ```text
+/// # Comments start with '#' or ';'
+/// [placeholders]
+/// ticket = "git log -1 --format=%s | grep -o 'ABC-[0-9]*'"
+/// ```
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Settings {
+    /// Custom placeholders mapping a name to a shell command.
+    pub placeholders: BTreeMap<String, String>,
+    /// Lookup table for `${1:map}` captures, read from the `--map` file.
+    pub map: Option<Arc<BTreeMap<String, String>>>,
+}
+
+impl Settings {
+    /// Load settings from the given file, the file set in `RNR_CONFIG` environment variable or the
+    /// default configuration file. Only an explicitly provided file is required to exist.
+    pub fn load(path: Option<&Path>) -> Result<Settings, String> {
+        let (path, required) = match path {
+            Some(path) => (path.to_path_buf(), true),
+            None => match env::var_os(CONFIG_ENV_VAR) {
+                Some(path) => (PathBuf::from(path), true),
+                None => match default_path() {
+                    Some(path) => (path, false),
+                    None => return Ok(Settings::default()),
+                },
+            },
+        };
+
+        match fs::read_to_string(&path) {
+            Ok(content) => Settings::parse(&content)
+                .map_err(|err| format!("Invalid configuration file {}\n{}", path.display(), err)),
+            Err(_) if !required => Ok(Settings::default()),
+            Err(err) => Err(format!(
+                "Cannot read configuration file {}\n{}",
+                path.display(),
+                err
+            )),
+        }
+    }
+
+    /// Parse settings file content.
+    pub fn parse(content: &str) -> Result<Settings, String> {
+        let mut settings = Settings::default();
+        let mut section = String::new();
+
+        for (index, line) in content.lines().enumerate() {
+            let line = line.trim();
+            let line_error = |message: &str| format!("Line {}: {}", index + 1, message);
+
+            if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+                continue;
+            }
+            if let Some(name) = line.strip_prefix('[') {
+                match name.strip_suffix(']') {
+                    Some(name) => section = name.trim().to_string(),
+                    None => return Err(line_error("unclosed section header")),
+                }
+                continue;
+            }
+
+            let (key, value) = match line.split_once('=') {
+                Some((key, value)) => (
+                    key.trim(),
+                    parse_value(value.trim()).map_err(|err| line_error(&err))?,
+                ),
+                None => return Err(line_error("expected 'key = value'")),
+            };
+            match section.as_str() {
+                "placeholders" => {
+                    // Names can be written with or without braces
+                    let name = key.trim_start_matches('{').trim_end_matches('}');
+                    if name.is_empty() {
+                        return Err(line_error("empty placeholder name"));
+                    }
+                    settings.placeholders.insert(name.to_string(), value);
+                }
+                "" => return Err(line_error("key outside of a section")),
+                section => return Err(line_error(&format!("unknown section '{}'", section))),
+            }
+        }
+
+        Ok(settings)
+    }
+}
+
+/// Read a lookup table from a CSV file with a `key,value` pair per line.
+pub fn read_map(path: &Path) -> Result<BTreeMap<String, String>, String> {
+    let content = fs::read_to_string(path)
+        .map_err(|err| format!("Cannot read map file {}\n{}", path.display(), err))?;
+    parse_map(&content).map_err(|err| format!("Invalid map file {}\n{}", path.display(), err))
+}
+
+/// Parse lookup table content. Keys and values can be double quoted, so values can contain
+/// commas. Empty lines and lines starting with '#' are skipped.
+pub fn parse_map(content: &str) -> Result<BTreeMap<String, String>, String> {
+    let mut map = BTreeMap::new();
+    for (index, line) in content.lines().enumerate() {
+        let line = line.trim();
+        let line_error = |message: &str| format!("Line {}: {}", index + 1, message);
+
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (key, value) = match line.split_once(',') {
+            Some((key, value)) => (
+                parse_value(key.trim()).map_err(|err| line_error(&err))?,
+                parse_value(value.trim()).map_err(|err| line_error(&err))?,
+            ),
+            None => return Err(line_error("expected 'key,value'")),
+        };
+        if map.contains_key(&key) {
+            return Err(line_error(&format!("duplicated key '{}'", key)));
+        }
+        map.insert(key, value);
+    }
+    Ok(map)
+}
+
+/// Parse a value that can be a double quoted string, with `\"` and `\\` escapes, or raw text.
+pub fn parse_value(value: &str) -> Result<String, String> {
+    let quoted = match value.strip_prefix('"') {
+        Some(quoted) => quoted,
+        None => return Ok(value.to_string()),
+    };
+
+    let mut parsed = String::new();
+    let mut chars = quoted.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some(escaped @ ('"' | '\\')) => parsed.push(escaped),
+                Some(other) => {
+                    parsed.push('\\');
+                    parsed.push(other);
+                }
+                None => return Err("unclosed quoted value".to_string()),
+            },
+            '"' => {
+                return if chars.as_str().trim().is_empty() {
+                    Ok(parsed)
+                } else {
+                    Err("unexpected text after quoted value".to_string())
+                };
+            }
+            c => parsed.push(c),
+        }
+    }
+    Err("unclosed quoted value".to_string())
+}
+
+/// Default configuration file path: `$XDG_CONFIG_HOME/rnr/config`, `~/.config/rnr/config` or
+/// `%APPDATA%\rnr\config` on Windows.
+fn default_path() -> Option<PathBuf> {
+    #[cfg(windows)]
+    let config_dir = env::var_os("APPDATA").map(PathBuf::from);
+    #[cfg(not(windows))]
+    let config_dir = env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")));
+
+    config_dir.map(|dir| dir.join("rnr").join("config"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_settings() {
+        let settings = Settings::parse(
+            r#"
+            # Organization tokens
+            [placeholders]
+            {ticket} = "git log -1 --format=%s | grep -o \"ABC-[0-9]*\""
+            host = hostname
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            settings.placeholders["ticket"],
+            r#"git log -1 --format=%s | grep -o "ABC-[0-9]*""#
+        );
+        assert_eq!(settings.placeholders["host"], "hostname");
+    }
+
+    #[test]
+    fn parse_settings_errors() {
+        assert!(Settings::parse("key = value").is_err());
+        assert!(Settings::parse("[unknown]\nkey = value").is_err());
+        assert!(Settings::parse("[placeholders\nkey = value").is_err());
+        assert!(Settings::parse("[placeholders]\nkey").is_err());
+        assert!(Settings::parse("[placeholders]\nkey = \"unclosed").is_err());
+        assert!(Settings::parse("[placeholders]\n{} = value").is_err());
+    }
+
+    #[test]
+    fn parse_lookup_map() {
+        let map = parse_map("# id,name\nC042, Acme\n\nC043,\"Smith, Jones & Co\"\n").unwrap();
+        assert_eq!(map.len(), 2);
+        assert_eq!(map["C042"], "Acme");
+        assert_eq!(map["C043"], "Smith, Jones & Co");
+
+        assert!(parse_map("C042").is_err());
+        assert!(parse_map("C042,Acme\nC042,Other").is_err());
+        assert!(parse_map("C042,\"unclosed").is_err());
+    }
+}
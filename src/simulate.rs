@@ -0,0 +1,324 @@
+use crate::config::RunMode;
+use crate::driver::{EntryKind, FsDriver, MemoryDriver};
+use crate::exif::EXIF_READ_LIMIT;
+use crate::importer;
+use std::path::{Component, Path, PathBuf};
+
+/// Output format of the simulated tree
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    Tree,
+    Json,
+}
+
+impl Format {
+    pub fn from_str(format: &str) -> Result<Format, String> {
+        match format {
+            "tree" => Ok(Format::Tree),
+            "json" => Ok(Format::Json),
+            _ => Err(format!(
+                "Unknown simulation format '{}', use tree or json",
+                format
+            )),
+        }
+    }
+}
+
+/// Simulation settings. The plan is applied to an in-memory snapshot of the roots, which are
+/// printed afterwards.
+pub struct Simulation {
+    pub format: Format,
+    pub roots: Vec<PathBuf>,
+}
+
+/// Simulated tree serialized in JSON format
+#[derive(Serialize, Debug)]
+struct Tree<'a> {
+    run_id: &'a str,
+    roots: Vec<Node>,
+}
+
+/// Entry of the simulated tree serialized in JSON format
+#[derive(Serialize, Debug)]
+struct Node {
+    name: String,
+    #[serde(rename = "type")]
+    kind: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    link: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    children: Vec<Node>,
+}
+
+impl Simulation {
+    pub fn new(format: Format, mode: &RunMode) -> Result<Simulation, String> {
+        Ok(Simulation {
+            format,
+            roots: roots(mode)?,
+        })
+    }
+
+    /// Render the roots of the simulated tree in the configured format. Roots inside other roots
+    /// are already rendered with their ancestors. JSON output includes the run ID.
+    pub fn render(&self, driver: &dyn FsDriver, run_id: &str) -> String {
+        let relative = |path: &Path| -> PathBuf {
+            path.components()
+                .filter(|component| *component != Component::CurDir)
+                .collect()
+        };
+        let nodes: Vec<Node> = self
+            .roots
+            .iter()
+            .filter(|root| {
+                !self
+                    .roots
+                    .iter()
+                    .any(|other| other != *root && relative(root).starts_with(relative(other)))
+            })
+            .map(|root| Node {
+                name: root.display().to_string(),
+                kind: "dir",
+                link: None,
+                children: children(driver, root),
+            })
+            .collect();
+
+        match self.format {
+            Format::Json => {
+                let tree = Tree {
+                    run_id,
+                    roots: nodes,
+                };
+                serde_json::to_string_pretty(&tree).unwrap_or_default()
+            }
+            Format::Tree => {
+                let mut lines = Vec::new();
+                for node in nodes {
+                    lines.push(node.name);
+                    render_tree(&node.children, "", &mut lines);
+                }
+                lines.join("\n")
+            }
+        }
+    }
+}
+
+/// Get the directories affected by the run mode. Parent directories of the given paths are
+/// included, so renamed paths and their siblings are shown.
+pub fn roots(mode: &RunMode) -> Result<Vec<PathBuf>, String> {
+    let paths: Vec<PathBuf> = match mode {
+        RunMode::Simple(paths) | RunMode::Contents(paths) | RunMode::Recursive { paths, .. } => {
+            paths.iter().map(PathBuf::from).collect()
+        }
+        RunMode::FromFile { path, format, .. } => importer::read(Path::new(path), *format)
+            .map_err(|err| {
+                format!(
+                    "{}{}",
+                    err.description(),
+                    err.value.as_deref().unwrap_or("")
+                )
+            })?
+            .operations
+            .into_iter()
+            .flat_map(|operation| [operation.source, operation.target])
+            .collect(),
+        RunMode::Review(_)
+        | RunMode::Serve
+        | RunMode::PlanDiff { .. }
+        | RunMode::DumpSpec
+        | RunMode::ValidateTemplate { .. }
+        | RunMode::PruneBackups { .. } => Vec::new(),
+    };
+
+    let mut roots: Vec<PathBuf> = Vec::new();
+    for path in paths {
+        let root = match path.parent() {
+            Some(parent) if parent.as_os_str().is_empty() => PathBuf::from("."),
+            Some(parent) => parent.to_path_buf(),
+            None => path,
+        };
+        if !roots.contains(&root) {
+            roots.push(root);
+        }
+    }
+    // Directories renamed by their contents are listed too
+    if let RunMode::Contents(paths) = mode {
+        for path in paths.iter().map(PathBuf::from) {
+            if !roots.contains(&path) {
+                roots.push(path);
+            }
+        }
+    }
+    Ok(roots)
+}
+
+/// Copy the roots into an in-memory tree. Root entries are copied, and in recursive mode all the
+/// descendants of the given paths. Only the beginning of files is read, enough to get their EXIF
+/// dates.
+pub fn snapshot(roots: &[PathBuf], mode: &RunMode, driver: &dyn FsDriver) -> MemoryDriver {
+    let memory = MemoryDriver::new();
+    let mut walk_list: Vec<PathBuf> = roots
+        .iter()
+        .flat_map(|root| driver.walk(root, Some(1), &|_, _| true))
+        .collect();
+    if let RunMode::Recursive { paths, .. } = mode {
+        for path in paths {
+            walk_list.extend(driver.walk(Path::new(path), None, &|_, _| true));
+        }
+    }
+
+    for path in walk_list {
+        let entry = match driver.symlink_metadata(&path) {
+            Ok(entry) => entry,
+            Err(_) => continue,
+        };
+        match entry.kind {
+            EntryKind::Dir => memory.create_dir(&path),
+            EntryKind::Symlink => match driver.read_link(&path) {
+                Ok(link) => memory.create_symlink(&path, &link),
+                Err(_) => continue,
+            },
+            EntryKind::File => {
+                let content = driver.read(&path, EXIF_READ_LIMIT).unwrap_or_default();
+                memory.create_file(&path, &content);
+            }
+        }
+        if let Some(modified) = entry.modified {
+            memory.set_modified(&path, modified).ok();
+        }
+    }
+    memory
+}
+
+/// Get the tree of entries in a directory, sorted by name.
+fn children(driver: &dyn FsDriver, dir: &Path) -> Vec<Node> {
+    driver
+        .walk(dir, Some(1), &|_, _| true)
+        .into_iter()
+        .skip(1)
+        .filter_map(|path| {
+            let entry = driver.symlink_metadata(&path).ok()?;
+            let name = path.file_name()?.to_string_lossy().to_string();
+            Some(match entry.kind {
+                EntryKind::Dir => Node {
+                    name,
+                    kind: "dir",
+                    link: None,
+                    children: children(driver, &path),
+                },
+                EntryKind::Symlink => Node {
+                    name,
+                    kind: "symlink",
+                    link: driver
+                        .read_link(&path)
+                        .ok()
+                        .map(|link| link.display().to_string()),
+                    children: Vec::new(),
+                },
+                EntryKind::File => Node {
+                    name,
+                    kind: "file",
+                    link: None,
+                    children: Vec::new(),
+                },
+            })
+        })
+        .collect()
+}
+
+/// Append tree lines with box-drawing connectors, like the `tree` command.
+fn render_tree(nodes: &[Node], indent: &str, lines: &mut Vec<String>) {
+    for (index, node) in nodes.iter().enumerate() {
+        let last = index + 1 == nodes.len();
+        let connector = if last { "└── " } else { "├── " };
+        match &node.link {
+            Some(link) => lines.push(format!("{}{}{} -> {}", indent, connector, node.name, link)),
+            None => lines.push(format!("{}{}{}", indent, connector, node.name)),
+        }
+        let indent = format!("{}{}", indent, if last { "    " } else { "│   " });
+        render_tree(&node.children, &indent, lines);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    extern crate tempfile;
+    use super::*;
+    use crate::driver::LocalDriver;
+    use std::fs;
+
+    #[test]
+    fn simulation_roots() {
+        let mode = RunMode::Simple(vec![
+            "a.txt".to_string(),
+            "b.txt".to_string(),
+            "dir/c.txt".to_string(),
+        ]);
+        let simulation = Simulation::new(Format::Tree, &mode).unwrap();
+        assert_eq!(
+            simulation.roots,
+            vec![PathBuf::from("."), PathBuf::from("dir")]
+        );
+
+        // Directories renamed by their contents are roots too
+        let mode = RunMode::Contents(vec!["dir".to_string()]);
+        let simulation = Simulation::new(Format::Tree, &mode).unwrap();
+        assert_eq!(
+            simulation.roots,
+            vec![PathBuf::from("."), PathBuf::from("dir")]
+        );
+    }
+
+    #[test]
+    fn snapshot_and_render() {
+        let tempdir = tempfile::tempdir().expect("Error creating temp directory");
+        println!("Running test in '{:?}'", tempdir);
+        let temp_path = tempdir.path();
+        fs::create_dir_all(temp_path.join("dir").join("sub")).unwrap();
+        fs::write(temp_path.join("dir").join("sub").join("file"), b"content").unwrap();
+        fs::write(temp_path.join("sibling"), b"").unwrap();
+
+        let dir = temp_path.join("dir").to_string_lossy().to_string();
+        let mode = RunMode::Recursive {
+            paths: vec![dir],
+            max_depth: None,
+            hidden: false,
+            hidden_attr: false,
+        };
+        let simulation = Simulation::new(Format::Tree, &mode).unwrap();
+        let memory = snapshot(&simulation.roots, &mode, &LocalDriver);
+        let file = temp_path.join("dir").join("sub").join("file");
+        assert_eq!(memory.read(&file, 100).unwrap(), b"content");
+
+        // Changes only happen in memory
+        memory
+            .rename(&temp_path.join("dir"), &temp_path.join("renamed"))
+            .unwrap();
+        assert!(file.exists());
+        assert_eq!(
+            simulation.render(&memory, "test"),
+            format!(
+                "{}\n├── renamed\n│   └── sub\n│       └── file\n└── sibling",
+                temp_path.display()
+            )
+        );
+
+        let simulation = Simulation {
+            format: Format::Json,
+            roots: simulation.roots,
+        };
+        let json: serde_json::Value =
+            serde_json::from_str(&simulation.render(&memory, "test")).unwrap();
+        assert_eq!(json.get("run_id").unwrap().as_str(), Some("test"));
+        let children =
+            |node: &serde_json::Value| node.get("children").unwrap().as_array().unwrap().clone();
+        let root_children = children(&json.get("roots").unwrap().as_array().unwrap()[0]);
+        assert_eq!(
+            root_children[0].get("name").unwrap().as_str(),
+            Some("renamed")
+        );
+        let sub = &children(&root_children[0])[0];
+        assert_eq!(sub.get("type").unwrap().as_str(), Some("dir"));
+        assert_eq!(root_children[1].get("type").unwrap().as_str(), Some("file"));
+    }
+}
@@ -1,8 +1,10 @@
 use crate::error::*;
-use crate::fileutils::{is_same_file, PathList};
+use crate::fileutils::PathList;
+use crate::metadata::MetadataCache;
 use path_abs::{PathAbs, PathInfo};
-use std::collections::HashMap;
-use std::path::PathBuf;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::env;
+use std::path::{Path, PathBuf};
 
 pub type RenameMap = HashMap<PathBuf, PathBuf>;
 
@@ -11,13 +13,55 @@ pub type RenameMap = HashMap<PathBuf, PathBuf>;
 pub struct Operation {
     pub source: PathBuf,
     pub target: PathBuf,
+    /// Review decision stored in dump files
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tag: Option<Tag>,
+    /// Target of the source symlink when the operation was planned, stored in dump files
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub link: Option<PathBuf>,
+}
+
+/// Tags assigned to operations while reviewing a dump file
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Tag {
+    Keep,
+    Later,
+    Never,
+}
+
+impl Tag {
+    pub const ALL: [Tag; 3] = [Tag::Keep, Tag::Later, Tag::Never];
+
+    /// Get the name of the tag, as written in dump files.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Tag::Keep => "keep",
+            Tag::Later => "later",
+            Tag::Never => "never",
+        }
+    }
+
+    /// Parse a tag filter name. `untagged` selects operations without tag.
+    pub fn parse_filter(name: &str) -> std::result::Result<Option<Tag>, String> {
+        match name {
+            "keep" => Ok(Some(Tag::Keep)),
+            "later" => Ok(Some(Tag::Later)),
+            "never" => Ok(Some(Tag::Never)),
+            "untagged" => Ok(None),
+            _ => Err(format!(
+                "Unknown tag '{}', use keep, later, never or untagged",
+                name
+            )),
+        }
+    }
 }
 
 pub type Operations = Vec<Operation>;
 
 /// Solve renaming order to avoid file overwrite. Solver will order the operations considering
-/// existing targets to avoid conflicts.
-pub fn solve_rename_order(rename_map: &RenameMap) -> Result<Operations> {
+/// existing targets to avoid conflicts, read through the metadata cache of the run.
+pub fn solve_rename_order(rename_map: &RenameMap, cache: &MetadataCache) -> Result<Operations> {
     // Get a list of path levels
     let mut level_list: Vec<usize> = rename_map
         .values()
@@ -30,31 +74,29 @@ pub fn solve_rename_order(rename_map: &RenameMap) -> Result<Operations> {
     // Sort from deeper to higher path level
     let mut rename_order = PathList::new();
     for level in level_list {
-        // Get all targets of this level
-        let level_targets: Vec<PathBuf> = rename_map
-            .keys()
-            .filter_map(|p| {
-                if p.components().count() == level {
-                    Some(p.clone())
+        // Get all targets whose sources are at this level, targets may be moved to other levels
+        let mut level_targets: Vec<PathBuf> = rename_map
+            .iter()
+            .filter_map(|(target, source)| {
+                if source.components().count() == level {
+                    Some(target.clone())
                 } else {
                     None
                 }
             })
             .collect();
+        // Sort by source, the map has no order and the same inputs must give the same plan
+        level_targets.sort_by(|a, b| rename_map[a].cmp(&rename_map[b]).then_with(|| a.cmp(b)));
         // Return existing targets in the list of original filenames
-        let mut existing_targets = get_existing_targets(&level_targets, rename_map)?;
+        let mut existing_targets = get_existing_targets(&level_targets, rename_map, cache)?;
 
         // Store first all non conflicting entries
+        let existing_set: HashSet<&PathBuf> = existing_targets.iter().collect();
         rename_order.append(
             &mut level_targets
                 .iter()
-                .filter_map(|p| {
-                    if !existing_targets.contains(p) {
-                        Some(p.clone())
-                    } else {
-                        None
-                    }
-                })
+                .filter(|p| !existing_set.contains(p))
+                .cloned()
                 .collect(),
         );
         // Order and append the rest of entries
@@ -70,12 +112,67 @@ pub fn solve_rename_order(rename_map: &RenameMap) -> Result<Operations> {
         operations.push(Operation {
             source: rename_map[&target].clone(),
             target,
+            tag: None,
+            link: None,
         });
     }
 
     Ok(operations)
 }
 
+/// Check a subset of planned operations, like the operations of a dump file with some tags or
+/// without the paths modified after it. The subset keeps the recorded order, which already puts
+/// directories renamed back before their contents in undos and passes, so only the conflicts
+/// created by the removed operations are checked: a target still taken by the source a removed
+/// operation would have moved, or a path expected inside the target of a removed operation. The
+/// subset must keep the order of the planned operations.
+pub fn check_subset(planned: &[Operation], subset: &[Operation]) -> Result<()> {
+    let mut kept = subset.iter().peekable();
+    let mut removed_sources: HashMap<&Path, &Operation> = HashMap::new();
+    let mut removed_targets: HashMap<&Path, &Operation> = HashMap::new();
+    for operation in planned {
+        let is_kept = kept
+            .peek()
+            .is_some_and(|next| next.source == operation.source && next.target == operation.target);
+        if !is_kept {
+            removed_sources.insert(&operation.source, operation);
+            removed_targets.insert(&operation.target, operation);
+            continue;
+        }
+        kept.next();
+
+        if removed_sources.contains_key(operation.target.as_path()) {
+            return Err(Error {
+                kind: ErrorKind::ExistingPath,
+                value: Some(format!(
+                    "{} -> {}",
+                    operation.source.display(),
+                    operation.target.display()
+                )),
+            });
+        }
+        let ancestors = operation
+            .source
+            .ancestors()
+            .chain(operation.target.ancestors());
+        for ancestor in ancestors {
+            if let Some(removed) = removed_targets.get(ancestor) {
+                return Err(Error {
+                    kind: ErrorKind::SolveOrder,
+                    value: Some(format!(
+                        " {} -> {} depends on the skipped {} -> {}",
+                        operation.source.display(),
+                        operation.target.display(),
+                        removed.source.display(),
+                        removed.target.display()
+                    )),
+                });
+            }
+        }
+    }
+    Ok(())
+}
+
 /// Revert the given operations. Returns operations in reverse order and with source/target
 /// fields interchanged.
 pub fn revert_operations(operations: &[Operation]) -> Result<Operations> {
@@ -83,30 +180,44 @@ pub fn revert_operations(operations: &[Operation]) -> Result<Operations> {
     reverse_operations.reverse();
     let inverse_operations = reverse_operations
         .into_iter()
-        .map(|Operation { source, target }| Operation {
-            source: target,
-            target: source,
-        })
+        .map(
+            |Operation {
+                 source,
+                 target,
+                 tag,
+                 link,
+             }| Operation {
+                source: target,
+                target: source,
+                tag,
+                link,
+            },
+        )
         .collect();
     Ok(inverse_operations)
 }
 
 /// Check if targets exist in the filesystem and return a list of them. If they exist, these
 /// targets must be contained in the original file list for the renaming problem to be solvable.
-fn get_existing_targets(targets: &[PathBuf], rename_map: &RenameMap) -> Result<PathList> {
+fn get_existing_targets(
+    targets: &[PathBuf],
+    rename_map: &RenameMap,
+    cache: &MetadataCache,
+) -> Result<PathList> {
     let mut existing_targets: PathList = Vec::new();
+    let sources: HashSet<&PathBuf> = rename_map.values().collect();
 
     for target in targets {
-        if target.symlink_metadata().is_err() {
+        if cache.symlink_metadata(target).is_none() {
             continue;
         }
 
-        if !rename_map.values().any(|x| x == target) {
+        if !sources.contains(target) {
             let source = rename_map.get(target).cloned().unwrap();
 
             // The source and the target may be the same file in some conditions like case
             // insensitive but case-preserving file systems.
-            if is_same_file(&source, target) {
+            if cache.same_file(&source, target) {
                 continue;
             }
 
@@ -121,64 +232,68 @@ fn get_existing_targets(targets: &[PathBuf], rename_map: &RenameMap) -> Result<P
     Ok(existing_targets)
 }
 
-/// Process the container with existing targets until it is empty. The algorithm extracts
-/// recursively all targets that are not present in a container with the sources exclusively related
-/// to current existing targets.
+/// Process the container with existing targets until it is empty. A target can be renamed once
+/// no other pending operation uses it as source, so targets are extracted starting from the ones
+/// not used as sources and following the chain of operations they release. Operations in a cycle
+/// cannot be ordered.
 fn sort_existing_targets(
     rename_map: &RenameMap,
     existing_targets: &mut PathList,
 ) -> Result<PathList> {
-    let mut ordered_targets: PathList = Vec::new();
-
-    while !existing_targets.is_empty() {
-        // Track selected index to extract value
-        let mut selected_index: Option<usize> = None;
-
-        // Create a vector with all sources from existing targets using absolute paths
-        #[allow(clippy::needless_collect)] // Benchmark shows no diff, code is clearer this way.
-        let sources: PathList = existing_targets
-            .iter()
-            .map(|x| rename_map.get(x).cloned().unwrap())
-            .map(|p| PathAbs::new(p).unwrap().as_path().to_path_buf())
-            .collect();
+    // Compare absolute paths, the current directory is read only once
+    let current_dir = env::current_dir().unwrap_or_default();
+    let absolute = |path: &Path| -> PathBuf {
+        PathAbs::new(current_dir.join(path))
+            .map(|path| path.as_path().to_path_buf())
+            .unwrap_or_else(|_| current_dir.join(path))
+    };
+    let targets: PathList = existing_targets.iter().map(|p| absolute(p)).collect();
+    let sources: PathList = existing_targets
+        .iter()
+        .map(|p| absolute(&rename_map[p]))
+        .collect();
+    let target_index: HashMap<&PathBuf, usize> =
+        targets.iter().enumerate().map(|(i, p)| (p, i)).collect();
+    let source_set: HashSet<&PathBuf> = sources.iter().collect();
 
-        // Select targets without conflicts in sources
-        for (index, target) in existing_targets.iter().enumerate() {
-            let absolute_target = PathAbs::new(target).unwrap().as_path().to_path_buf();
-            if !sources.contains(&absolute_target) {
-                selected_index = Some(index);
-                break;
+    // Targets not used as source by any other pending operation can be renamed right away
+    let mut ready: VecDeque<usize> = (0..targets.len())
+        .filter(|index| !source_set.contains(&targets[*index]))
+        .collect();
+    let mut ordered_targets: PathList = Vec::new();
+    while let Some(index) = ready.pop_front() {
+        ordered_targets.push(existing_targets[index].clone());
+        // Renaming this source releases the operation targeting it
+        if let Some(released) = target_index.get(&sources[index]) {
+            if *released != index {
+                ready.push_back(*released);
             }
         }
+    }
 
-        // Store result in ordered targets container or fail to stop the loop
-        match selected_index {
-            Some(index) => ordered_targets.push(existing_targets.swap_remove(index)),
-            // This will avoid infinite while loop if order is not solved
-            None => {
-                return Err(Error {
-                    kind: ErrorKind::SolveOrder,
-                    value: None,
-                })
-            }
-        }
+    // This will avoid infinite loops if order is not solved
+    if ordered_targets.len() != existing_targets.len() {
+        return Err(Error {
+            kind: ErrorKind::SolveOrder,
+            value: None,
+        });
     }
+    existing_targets.clear();
 
     Ok(ordered_targets)
 }
 
 #[cfg(test)]
 mod test {
-    extern crate tempfile;
     use super::*;
-    use crate::fileutils::create_symlink;
-    use std::fs;
+    use crate::driver::{FsDriver, MemoryDriver};
+    use std::sync::Arc;
 
     #[test]
     fn test_existing_targets() {
-        let tempdir = tempfile::tempdir().expect("Error creating temp directory");
-        println!("Running test in '{:?}'", tempdir);
-        let temp_path = tempdir.path().to_str().unwrap();
+        let driver = Arc::new(MemoryDriver::new());
+        let cache = MetadataCache::with_driver(driver.clone());
+        let temp_path = "/data";
 
         let mock_sources: PathList = vec![
             [temp_path, "a.txt"].iter().collect(),
@@ -190,7 +305,7 @@ mod test {
         ];
         // Create files in the filesystem
         for file in &mock_sources {
-            fs::File::create(&file).expect("Error creating mock file...");
+            driver.create_file(file, b"");
         }
 
         // Add one 'a' to the beginning of the filename
@@ -207,7 +322,7 @@ mod test {
             .into_iter()
             .zip(mock_sources.into_iter())
             .collect();
-        let existing_targets = get_existing_targets(&mock_targets, &mock_rename_map)
+        let existing_targets = get_existing_targets(&mock_targets, &mock_rename_map, &cache)
             .expect("Error getting existing targets.");
 
         assert!(existing_targets.contains(&mock_targets[0]));
@@ -220,9 +335,9 @@ mod test {
 
     #[test]
     fn test_existing_targets_symlinks() {
-        let tempdir = tempfile::tempdir().expect("Error creating temp directory");
-        println!("Running test in '{:?}'", tempdir);
-        let temp_path = tempdir.path().to_str().unwrap();
+        let driver = Arc::new(MemoryDriver::new());
+        let cache = MetadataCache::with_driver(driver.clone());
+        let temp_path = "/data";
 
         let mock_sources: PathList = vec![
             [temp_path, "a.txt"].iter().collect(),
@@ -230,10 +345,13 @@ mod test {
             [temp_path, "aaa.txt"].iter().collect(),
         ];
         // Create files in the filesystem
-        fs::File::create(&mock_sources[0]).expect("Error creating mock file...");
+        driver.create_file(&mock_sources[0], b"");
         // Create symlinks
-        create_symlink(&mock_sources[0], &mock_sources[1]).expect("Error creating symlink.");
-        create_symlink(&PathBuf::from("broken_link"), &mock_sources[2])
+        driver
+            .symlink(&mock_sources[0], &mock_sources[1])
+            .expect("Error creating symlink.");
+        driver
+            .symlink(&PathBuf::from("broken_link"), &mock_sources[2])
             .expect("Error creating broken symlink.");
 
         // Add one 'a' to the beginning of the filename
@@ -247,7 +365,7 @@ mod test {
             .into_iter()
             .zip(mock_sources.into_iter())
             .collect();
-        let existing_targets = get_existing_targets(&mock_targets, &mock_rename_map)
+        let existing_targets = get_existing_targets(&mock_targets, &mock_rename_map, &cache)
             .expect("Error getting existing targets.");
 
         assert!(existing_targets.contains(&mock_targets[0]));
@@ -257,9 +375,8 @@ mod test {
 
     #[test]
     fn test_sort_existing_targets() {
-        let tempdir = tempfile::tempdir().expect("Error creating temp directory");
-        println!("Running test in '{:?}'", tempdir);
-        let temp_path = tempdir.path().to_str().unwrap();
+        let driver = MemoryDriver::new();
+        let temp_path = "/data";
 
         let mock_sources: PathList = vec![
             [temp_path, "a.txt"].iter().collect(),
@@ -269,7 +386,7 @@ mod test {
         ];
         // Create files in the filesystem
         for file in &mock_sources {
-            fs::File::create(&file).expect("Error creating mock file...");
+            driver.create_file(file, b"");
         }
 
         // Add one 'a' to the beginning of the filename
@@ -309,9 +426,9 @@ mod test {
 
     #[test]
     fn test_solve_rename_order() {
-        let tempdir = tempfile::tempdir().expect("Error creating temp directory");
-        println!("Running test in '{:?}'", tempdir);
-        let temp_path = tempdir.path().to_str().unwrap();
+        let driver = Arc::new(MemoryDriver::new());
+        let cache = MetadataCache::with_driver(driver.clone());
+        let temp_path = "/data";
 
         let mock_sources: PathList = vec![
             [temp_path, "a.txt"].iter().collect(),
@@ -322,7 +439,7 @@ mod test {
         ];
         // Create directory tree and files in the filesystem
         for file in &mock_sources {
-            fs::File::create(&file).expect("Error creating mock file...");
+            driver.create_file(file, b"");
         }
 
         // Add one 'a' to the beginning of the filename
@@ -340,7 +457,7 @@ mod test {
             .collect();
 
         let operations =
-            solve_rename_order(&mock_rename_map).expect("Failed to solve rename order.");
+            solve_rename_order(&mock_rename_map, &cache).expect("Failed to solve rename order.");
 
         assert_eq!(operations[0].target, mock_targets[4]);
         assert_eq!(operations[1].target, mock_targets[3]);
@@ -348,4 +465,57 @@ mod test {
         assert_eq!(operations[3].target, mock_targets[1]);
         assert_eq!(operations[4].target, mock_targets[0]);
     }
+
+    #[test]
+    fn test_sort_existing_targets_cycle() {
+        let driver = MemoryDriver::new();
+        let temp_path = "/data";
+
+        let file_a: PathBuf = [temp_path, "a.txt"].iter().collect();
+        let file_b: PathBuf = [temp_path, "b.txt"].iter().collect();
+        driver.create_file(&file_a, b"");
+        driver.create_file(&file_b, b"");
+
+        // Swap names
+        let mock_rename_map: RenameMap = vec![
+            (file_b.clone(), file_a.clone()),
+            (file_a.clone(), file_b.clone()),
+        ]
+        .into_iter()
+        .collect();
+        let mut mock_existing_targets: PathList = vec![file_a, file_b];
+
+        let result = sort_existing_targets(&mock_rename_map, &mut mock_existing_targets);
+        assert_eq!(result.unwrap_err().kind, ErrorKind::SolveOrder);
+    }
+
+    #[test]
+    fn test_check_subset() {
+        let operation = |source: &str, target: &str| Operation {
+            source: PathBuf::from(source),
+            target: PathBuf::from(target),
+            tag: None,
+            link: None,
+        };
+
+        // Undo of dx/fx -> dx/fy, dx -> dy and zx -> zy, the directory is renamed back first
+        let planned = vec![
+            operation("zy", "zx"),
+            operation("dy", "dx"),
+            operation("dx/fy", "dx/fx"),
+        ];
+        let subset = planned[1..].to_vec();
+        assert!(check_subset(&planned, &subset).is_ok());
+
+        // Without the rename of the directory, its contents are not where they are expected
+        let subset = vec![planned[0].clone(), planned[2].clone()];
+        let result = check_subset(&planned, &subset);
+        assert_eq!(result.unwrap_err().kind, ErrorKind::SolveOrder);
+
+        // Without the second operation of the chain b -> c, a -> b, the target b is taken
+        let planned = vec![operation("b", "c"), operation("a", "b")];
+        let result = check_subset(&planned, &planned[1..]);
+        assert_eq!(result.unwrap_err().kind, ErrorKind::ExistingPath);
+        assert!(check_subset(&planned, &planned[..1]).is_ok());
+    }
 }
@@ -0,0 +1,140 @@
+use crate::driver::{EntryKind, FsDriver};
+use crate::error::*;
+use crate::solver::Operation;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+/// Bytes needed and available in a file system to apply a plan
+#[derive(Clone, Debug, PartialEq)]
+pub struct Usage {
+    /// Topmost directory of the file system containing the paths
+    pub mount: PathBuf,
+    pub required: u64,
+    pub available: u64,
+}
+
+/// Get the bytes needed by the backups of the operations in each file system. Backups are created
+/// next to their files, symlinks are backed up as links and take no space. Paths in file systems
+/// that cannot be queried are left out.
+pub fn backup_usage(operations: &[Operation], driver: &dyn FsDriver) -> Vec<Usage> {
+    let mut usage: BTreeMap<u64, Usage> = BTreeMap::new();
+    for operation in operations {
+        let size = match driver.symlink_metadata(&operation.source) {
+            Ok(entry) if entry.kind == EntryKind::File => entry.size,
+            _ => continue,
+        };
+        let dir = match operation.source.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+        let file_system = match driver.file_system(dir) {
+            Ok(file_system) => file_system,
+            Err(_) => continue,
+        };
+        usage
+            .entry(file_system.device)
+            .or_insert_with(|| Usage {
+                mount: mount_point(dir, file_system.device, driver),
+                required: 0,
+                available: file_system.available,
+            })
+            .required += size;
+    }
+    usage.into_values().collect()
+}
+
+/// Check that backups fit in their file systems before renaming anything. The error lists the
+/// space needed and available in each file system.
+pub fn check_backups(operations: &[Operation], driver: &dyn FsDriver) -> Result<()> {
+    let usage = backup_usage(operations, driver);
+    if usage.iter().all(|usage| usage.required <= usage.available) {
+        return Ok(());
+    }
+    let breakdown: Vec<String> = usage
+        .iter()
+        .map(|usage| {
+            format!(
+                "{}: {} needed, {} available",
+                usage.mount.display(),
+                format_size(usage.required),
+                format_size(usage.available)
+            )
+        })
+        .collect();
+    Err(Error {
+        kind: ErrorKind::NoSpace,
+        value: Some(breakdown.join("\n")),
+    })
+}
+
+/// Get the topmost ancestor of a directory in the same device.
+fn mount_point(dir: &Path, device: u64, driver: &dyn FsDriver) -> PathBuf {
+    let dir = std::path::absolute(dir).unwrap_or_else(|_| dir.to_path_buf());
+    dir.ancestors()
+        .take_while(|ancestor| {
+            driver
+                .file_system(ancestor)
+                .is_ok_and(|file_system| file_system.device == device)
+        })
+        .last()
+        .unwrap_or(&dir)
+        .to_path_buf()
+}
+
+/// Format a size in bytes with binary units.
+fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", size, UNITS[unit])
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::driver::MemoryDriver;
+
+    #[test]
+    fn backup_space() {
+        let driver = MemoryDriver::new();
+        driver.create_file(Path::new("/data/a.bin"), &[0; 1536]);
+        driver.create_file(Path::new("/data/b.bin"), &[0; 512]);
+        driver
+            .symlink(Path::new("a.bin"), Path::new("/data/link"))
+            .unwrap();
+        let operations: Vec<Operation> = ["a.bin", "b.bin", "link"]
+            .iter()
+            .map(|name| Operation {
+                source: Path::new("/data").join(name),
+                target: Path::new("/data").join(format!("new_{}", name)),
+                tag: None,
+                link: None,
+            })
+            .collect();
+
+        driver.set_available(2048);
+        let usage = backup_usage(&operations, &driver);
+        assert_eq!(
+            usage,
+            vec![Usage {
+                mount: PathBuf::from("/"),
+                required: 2048,
+                available: 2048,
+            }]
+        );
+        assert!(check_backups(&operations, &driver).is_ok());
+
+        driver.set_available(1000);
+        let err = check_backups(&operations, &driver).unwrap_err();
+        assert_eq!(err.kind, ErrorKind::NoSpace);
+        assert_eq!(err.value.unwrap(), "/: 2.0 KiB needed, 1000 B available");
+    }
+}
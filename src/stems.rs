@@ -0,0 +1,117 @@
+use crate::driver::FsDriver;
+use crate::error::*;
+use crate::solver::Operation;
+use std::collections::{BTreeMap, BTreeSet};
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+
+/// Check that no target shares its directory and file stem, the name without its extension, with
+/// another target or with an entry left in place. Downstream systems keyed by stem need them
+/// unique even if the full names are. Entries renamed away by the operations free their stem.
+pub fn check(operations: &[Operation], driver: &dyn FsDriver) -> Result<()> {
+    let sources: BTreeSet<&Path> = operations
+        .iter()
+        .map(|operation| operation.source.as_path())
+        .collect();
+    let targets: BTreeSet<&Path> = operations
+        .iter()
+        .map(|operation| operation.target.as_path())
+        .collect();
+
+    // Entries of the target directories once renamed, grouped by stem
+    let mut stems: BTreeMap<(&Path, OsString), BTreeSet<PathBuf>> = BTreeMap::new();
+    let dirs: BTreeSet<&Path> = targets
+        .iter()
+        .map(|target| target.parent().unwrap_or_else(|| Path::new("")))
+        .collect();
+    for dir in dirs {
+        let root = if dir.as_os_str().is_empty() {
+            Path::new(".")
+        } else {
+            dir
+        };
+        let entries = driver
+            .walk(root, Some(1), &|_, _| true)
+            .into_iter()
+            .filter(|entry| entry != root)
+            .filter_map(|entry| entry.file_name().map(|name| dir.join(name)))
+            .filter(|entry| !sources.contains(entry.as_path()));
+        let renamed = targets
+            .iter()
+            .filter(|target| target.parent().unwrap_or_else(|| Path::new("")) == dir)
+            .map(|target| target.to_path_buf());
+        for path in entries.chain(renamed) {
+            if let Some(stem) = path.file_stem() {
+                stems
+                    .entry((dir, stem.to_os_string()))
+                    .or_default()
+                    .insert(path);
+            }
+        }
+    }
+
+    // Entries left in place may share stems, only targets are reported
+    let collisions: Vec<String> = stems
+        .into_values()
+        .filter(|paths| paths.len() > 1)
+        .filter(|paths| paths.iter().any(|path| targets.contains(path.as_path())))
+        .map(|paths| {
+            let paths: Vec<String> = paths
+                .iter()
+                .map(|path| path.display().to_string())
+                .collect();
+            paths.join("\n")
+        })
+        .collect();
+    if collisions.is_empty() {
+        Ok(())
+    } else {
+        Err(Error {
+            kind: ErrorKind::SameStem,
+            value: Some(collisions.join("\n\n")),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::driver::MemoryDriver;
+
+    fn operation(source: &str, target: &str) -> Operation {
+        Operation {
+            source: PathBuf::from(source),
+            target: PathBuf::from(target),
+            tag: None,
+            link: None,
+        }
+    }
+
+    #[test]
+    fn stem_collisions() {
+        let driver = MemoryDriver::new();
+        for name in ["report.pdf", "a.docx", "b.docx", "notes.txt", "notes.md"] {
+            driver.create_file(&Path::new("/data").join(name), b"");
+        }
+
+        // Unique stems, even if untouched entries share one
+        assert!(check(&[operation("/data/a.docx", "/data/a2.docx")], &driver).is_ok());
+
+        // Targets cannot share a stem with entries left in place or with other targets
+        let err = check(&[operation("/data/a.docx", "/data/report.docx")], &driver).unwrap_err();
+        assert_eq!(err.kind, ErrorKind::SameStem);
+        assert_eq!(err.value.unwrap(), "/data/report.docx\n/data/report.pdf");
+        let operations = [
+            operation("/data/a.docx", "/data/c.docx"),
+            operation("/data/b.docx", "/data/c.odt"),
+        ];
+        assert!(check(&operations, &driver).is_err());
+
+        // Entries renamed away free their stem
+        let operations = [
+            operation("/data/report.pdf", "/data/old_report.pdf"),
+            operation("/data/a.docx", "/data/report.docx"),
+        ];
+        assert!(check(&operations, &driver).is_ok());
+    }
+}
@@ -0,0 +1,1006 @@
+use crate::filters::TimeSource;
+use crate::metadata::MetadataCache;
+use crate::settings::Settings;
+use chrono::format::{Item, StrftimeItems};
+use chrono::NaiveDateTime;
+use regex::Captures;
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::process::Command;
+use std::sync::{Arc, OnceLock};
+
+/// Default format used for date placeholders.
+const DEFAULT_DATE_FORMAT: &str = "%Y-%m-%d";
+
+/// Names of the built-in placeholders.
+const BUILTIN_PLACEHOLDERS: [&str; 5] = ["exif.date", "group", "idx", "mtime", "seq"];
+
+/// Replacement template. It extends the regex replacement syntax (`$1`, `${name}`) with
+/// placeholders (`{mtime:%Y%m%d}`) and conditionals (`{if exif.date}...{else}...{end}`).
+#[derive(Debug, PartialEq)]
+pub struct Template {
+    nodes: Vec<Node>,
+    /// Custom placeholders used by the template. Their values are cached for the whole run.
+    custom: BTreeMap<String, CustomPlaceholder>,
+    /// Lookup table for `${1:map}` captures
+    map: Option<Arc<BTreeMap<String, String>>>,
+}
+
+/// Placeholder which value is the output of a shell command.
+#[derive(Debug, PartialEq)]
+struct CustomPlaceholder {
+    command: String,
+    output: OnceLock<Option<String>>,
+}
+
+impl CustomPlaceholder {
+    /// Get command output, running it only the first time. Returns `None` if the command fails.
+    fn value(&self) -> Option<&str> {
+        self.output
+            .get_or_init(|| run_command(&self.command))
+            .as_deref()
+    }
+}
+
+/// Run a shell command and return its output without the trailing newline.
+fn run_command(command: &str) -> Option<String> {
+    #[cfg(windows)]
+    let output = Command::new("cmd").arg("/C").arg(command).output().ok()?;
+    #[cfg(not(windows))]
+    let output = Command::new("sh").arg("-c").arg(command).output().ok()?;
+
+    if !output.status.success() {
+        return None;
+    }
+    let stdout = String::from_utf8(output.stdout).ok()?;
+    Some(stdout.trim_end_matches(['\n', '\r']).to_string())
+}
+
+#[derive(Debug, PartialEq)]
+enum Node {
+    Text(String),
+    Capture {
+        group: CaptureRef,
+        filter: Option<Filter>,
+    },
+    Placeholder {
+        kind: Placeholder,
+        format: Option<String>,
+    },
+    Conditional {
+        condition: Condition,
+        then: Vec<Node>,
+        otherwise: Vec<Node>,
+    },
+}
+
+/// Reference to a regex capture group by index or name.
+#[derive(Debug, PartialEq)]
+enum CaptureRef {
+    Index(usize),
+    Name(String),
+}
+
+/// Transformation of a capture value, written after its name like `${1:+100}` or `${1:map}`.
+#[derive(Debug, PartialEq)]
+enum Filter {
+    Arithmetic(Arithmetic),
+    /// Replace the value by the one of the lookup table, values missing from it are unchanged
+    Map,
+}
+
+/// Arithmetic operation applied to numeric captures, e.g. `${1:+100}`.
+#[derive(Debug, PartialEq)]
+struct Arithmetic {
+    operator: Operator,
+    operand: i64,
+}
+
+#[derive(Debug, PartialEq)]
+enum Operator {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+}
+
+impl Arithmetic {
+    /// Parse arithmetic expression with `<operator><integer>` syntax.
+    fn parse(expression: &str) -> Result<Arithmetic, String> {
+        let mut chars = expression.chars();
+        let operator = match chars.next() {
+            Some('+') => Operator::Add,
+            Some('-') => Operator::Subtract,
+            Some('*') => Operator::Multiply,
+            Some('/') => Operator::Divide,
+            _ => {
+                return Err(format!(
+                    "Invalid arithmetic operator in '{}', use one of '+', '-', '*' or '/'",
+                    expression
+                ))
+            }
+        };
+        let operand = match chars.as_str().parse::<i64>() {
+            Ok(operand) => operand,
+            Err(_) => return Err(format!("Invalid arithmetic operand in '{}'", expression)),
+        };
+        if operator == Operator::Divide && operand == 0 {
+            return Err(format!("Division by zero in '{}'", expression));
+        }
+        Ok(Arithmetic { operator, operand })
+    }
+
+    /// Apply operation to a captured value. Non-numeric values are returned unchanged. Zero
+    /// padding of the original value is preserved, e.g. `007` + 12 is `019`.
+    fn apply(&self, value: &str) -> String {
+        let number = match value.parse::<i64>() {
+            Ok(number) => number,
+            Err(_) => return value.to_string(),
+        };
+        let result = match self.operator {
+            Operator::Add => number.checked_add(self.operand),
+            Operator::Subtract => number.checked_sub(self.operand),
+            Operator::Multiply => number.checked_mul(self.operand),
+            Operator::Divide => number.checked_div(self.operand),
+        };
+        let result = match result {
+            Some(result) => result,
+            None => return value.to_string(),
+        };
+
+        let digits = value.trim_start_matches(['+', '-']);
+        let width = if digits.len() > 1 && digits.starts_with('0') {
+            digits.len()
+        } else {
+            0
+        };
+        let sign = if result < 0 { "-" } else { "" };
+        format!("{}{:0width$}", sign, result.unsigned_abs(), width = width)
+    }
+}
+
+/// Position of a path among the paths sharing the same `--group-by` key. All numbers start at 1,
+/// groups are sorted by key and paths by name within each group.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Group {
+    pub index: usize,
+    pub seq: usize,
+    /// Position among all the candidate paths sorted by name, matched or not
+    pub idx: usize,
+}
+
+/// Template rendered for a path
+#[derive(Debug, Default, PartialEq)]
+pub struct Rendered {
+    pub text: String,
+    /// Placeholders without a value for the path, rendered empty
+    pub missing: Vec<String>,
+}
+
+/// Values that can be inserted into a template.
+#[derive(Clone, Debug, PartialEq)]
+enum Placeholder {
+    Custom(String),
+    ExifDate,
+    Group,
+    Idx,
+    Mtime,
+    Seq,
+}
+
+/// Value of a placeholder for a given path.
+enum PlaceholderValue<'a> {
+    Date(NaiveDateTime),
+    Number(usize),
+    Text(&'a str),
+}
+
+impl Placeholder {
+    /// Get placeholder from its name. Built-in placeholders take precedence over custom ones.
+    fn from_name(name: &str, custom: &BTreeMap<String, String>) -> Option<Placeholder> {
+        match name {
+            "exif.date" => Some(Placeholder::ExifDate),
+            "group" => Some(Placeholder::Group),
+            "idx" => Some(Placeholder::Idx),
+            "mtime" => Some(Placeholder::Mtime),
+            "seq" => Some(Placeholder::Seq),
+            name if custom.contains_key(name) => Some(Placeholder::Custom(name.to_string())),
+            _ => None,
+        }
+    }
+
+    fn name(&self) -> &str {
+        match self {
+            Placeholder::Custom(name) => name,
+            Placeholder::ExifDate => "exif.date",
+            Placeholder::Group => "group",
+            Placeholder::Idx => "idx",
+            Placeholder::Mtime => "mtime",
+            Placeholder::Seq => "seq",
+        }
+    }
+
+    /// Check if the format is valid for the placeholder. Dates take a `strftime` format and
+    /// counters a zero-padded width.
+    fn check_format(&self, content: &str, format: &str) -> Result<(), String> {
+        match self {
+            Placeholder::Custom(name) => Err(format!(
+                "Placeholder '{{{}}}' does not accept a format",
+                name
+            )),
+            Placeholder::Group | Placeholder::Idx | Placeholder::Seq
+                if format.parse::<usize>().is_err() =>
+            {
+                Err(format!("Invalid width '{}' in '{{{}}}'", format, content))
+            }
+            Placeholder::ExifDate | Placeholder::Mtime
+                if StrftimeItems::new(format).any(|item| item == Item::Error) =>
+            {
+                Err(format!(
+                    "Invalid date format '{}' in '{{{}}}'",
+                    format, content
+                ))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Condition evaluated by `{if ...}` blocks.
+#[derive(Debug, PartialEq)]
+enum Condition {
+    Capture(CaptureRef),
+    Placeholder(Placeholder),
+}
+
+/// Keywords that close a template block.
+#[derive(Debug, PartialEq)]
+enum Keyword {
+    Else,
+    End,
+}
+
+impl Template {
+    /// Parse a replacement template without custom placeholders.
+    #[cfg(test)]
+    pub fn parse(input: &str) -> Result<Template, String> {
+        Template::parse_with_settings(input, &Settings::default())
+    }
+
+    /// Parse a replacement template with the custom placeholders and lookup table of the settings.
+    pub fn parse_with_settings(input: &str, settings: &Settings) -> Result<Template, String> {
+        let placeholders = &settings.placeholders;
+        let mut parser = Parser {
+            input,
+            pos: 0,
+            placeholders,
+            has_map: settings.map.is_some(),
+        };
+        let nodes = match parser.parse_block()? {
+            (nodes, None) => nodes,
+            (_, Some(Keyword::Else)) => {
+                return Err("Unexpected '{else}' without '{if ...}'".to_string())
+            }
+            (_, Some(Keyword::End)) => {
+                return Err("Unexpected '{end}' without '{if ...}'".to_string())
+            }
+        };
+
+        let custom = placeholders
+            .iter()
+            .map(|(name, command)| {
+                let placeholder = CustomPlaceholder {
+                    command: command.clone(),
+                    output: OnceLock::new(),
+                };
+                (name.clone(), placeholder)
+            })
+            .collect();
+        Ok(Template {
+            nodes,
+            custom,
+            map: settings.map.clone(),
+        })
+    }
+
+    /// Render template for a single match of the given path, numbered within its group if any.
+    pub fn render(
+        &self,
+        captures: &Captures,
+        path: &Path,
+        group: Option<Group>,
+        cache: &MetadataCache,
+    ) -> String {
+        self.render_checked(captures, path, group, cache).text
+    }
+
+    /// Render template like `render`, also reporting the placeholders rendered empty because the
+    /// path has no value for them. Placeholders checked by conditionals are expected to be missing.
+    pub fn render_checked(
+        &self,
+        captures: &Captures,
+        path: &Path,
+        group: Option<Group>,
+        cache: &MetadataCache,
+    ) -> Rendered {
+        let mut output = Rendered::default();
+        self.render_nodes(&self.nodes, captures, path, group, cache, &mut output);
+        output
+    }
+
+    /// Get the capture groups referenced by the template, in order of first appearance.
+    pub fn captures(&self) -> Vec<String> {
+        let mut names = Vec::new();
+        visit_references(&self.nodes, &mut |reference| {
+            if let Reference::Capture(capture) = reference {
+                let name = match capture {
+                    CaptureRef::Index(index) => index.to_string(),
+                    CaptureRef::Name(name) => name.clone(),
+                };
+                if !names.contains(&name) {
+                    names.push(name);
+                }
+            }
+        });
+        names
+    }
+
+    /// Get the placeholders used by the template, in order of first appearance.
+    pub fn placeholders(&self) -> Vec<String> {
+        let mut names = Vec::new();
+        visit_references(&self.nodes, &mut |reference| {
+            if let Reference::Placeholder(placeholder) = reference {
+                let name = placeholder.name().to_string();
+                if !names.contains(&name) {
+                    names.push(name);
+                }
+            }
+        });
+        names
+    }
+
+    /// Check if the template numbers paths with `{group}`, `{seq}` or `{idx}`.
+    pub fn has_counters(&self) -> bool {
+        fn any_counter(nodes: &[Node]) -> bool {
+            nodes.iter().any(|node| match node {
+                Node::Placeholder { kind, .. } => {
+                    matches!(
+                        kind,
+                        Placeholder::Group | Placeholder::Idx | Placeholder::Seq
+                    )
+                }
+                Node::Conditional {
+                    condition,
+                    then,
+                    otherwise,
+                } => {
+                    matches!(
+                        condition,
+                        Condition::Placeholder(
+                            Placeholder::Group | Placeholder::Idx | Placeholder::Seq
+                        )
+                    ) || any_counter(then)
+                        || any_counter(otherwise)
+                }
+                _ => false,
+            })
+        }
+        any_counter(&self.nodes)
+    }
+
+    /// Get placeholder value for the given path. Returns `None` if it is not available.
+    fn placeholder_value(
+        &self,
+        placeholder: &Placeholder,
+        path: &Path,
+        group: Option<Group>,
+        cache: &MetadataCache,
+    ) -> Option<PlaceholderValue<'_>> {
+        match placeholder {
+            Placeholder::Custom(name) => self
+                .custom
+                .get(name)
+                .and_then(|custom| custom.value())
+                .map(PlaceholderValue::Text),
+            Placeholder::ExifDate => TimeSource::Exif
+                .timestamp(path, cache)
+                .map(PlaceholderValue::Date),
+            Placeholder::Group => group.map(|group| PlaceholderValue::Number(group.index)),
+            Placeholder::Idx => group.map(|group| PlaceholderValue::Number(group.idx)),
+            Placeholder::Mtime => TimeSource::Mtime
+                .timestamp(path, cache)
+                .map(PlaceholderValue::Date),
+            Placeholder::Seq => group.map(|group| PlaceholderValue::Number(group.seq)),
+        }
+    }
+
+    fn render_nodes(
+        &self,
+        nodes: &[Node],
+        captures: &Captures,
+        path: &Path,
+        group: Option<Group>,
+        cache: &MetadataCache,
+        output: &mut Rendered,
+    ) {
+        for node in nodes {
+            match node {
+                Node::Text(text) => output.text.push_str(text),
+                Node::Capture { group, filter } => {
+                    let value = get_capture(captures, group);
+                    match filter {
+                        Some(Filter::Arithmetic(arithmetic)) => {
+                            output.text.push_str(&arithmetic.apply(value))
+                        }
+                        Some(Filter::Map) => {
+                            let mapped = self.map.as_ref().and_then(|map| map.get(value));
+                            output.text.push_str(mapped.map_or(value, String::as_str))
+                        }
+                        None => output.text.push_str(value),
+                    }
+                }
+                Node::Placeholder { kind, format } => {
+                    match self.placeholder_value(kind, path, group, cache) {
+                        Some(PlaceholderValue::Date(date)) => {
+                            let format = format.as_deref().unwrap_or(DEFAULT_DATE_FORMAT);
+                            output.text.push_str(&date.format(format).to_string());
+                        }
+                        Some(PlaceholderValue::Number(number)) => {
+                            let width = format.as_deref().and_then(|width| width.parse().ok());
+                            output.text.push_str(&format!(
+                                "{:0width$}",
+                                number,
+                                width = width.unwrap_or(0)
+                            ));
+                        }
+                        Some(PlaceholderValue::Text(text)) => output.text.push_str(text),
+                        None => output.missing.push(kind.name().to_string()),
+                    }
+                }
+                Node::Conditional {
+                    condition,
+                    then,
+                    otherwise,
+                } => {
+                    let is_true = match condition {
+                        Condition::Capture(capture) => !get_capture(captures, capture).is_empty(),
+                        Condition::Placeholder(kind) => {
+                            self.placeholder_value(kind, path, group, cache).is_some()
+                        }
+                    };
+                    let branch = if is_true { then } else { otherwise };
+                    self.render_nodes(branch, captures, path, group, cache, output);
+                }
+            }
+        }
+    }
+}
+
+/// Capture group or placeholder referenced by a template.
+enum Reference<'a> {
+    Capture(&'a CaptureRef),
+    Placeholder(&'a Placeholder),
+}
+
+/// Call the visitor for every reference of the nodes, including conditions and both branches.
+fn visit_references<'a>(nodes: &'a [Node], visitor: &mut dyn FnMut(Reference<'a>)) {
+    for node in nodes {
+        match node {
+            Node::Text(_) => {}
+            Node::Capture { group, .. } => visitor(Reference::Capture(group)),
+            Node::Placeholder { kind, .. } => visitor(Reference::Placeholder(kind)),
+            Node::Conditional {
+                condition,
+                then,
+                otherwise,
+            } => {
+                match condition {
+                    Condition::Capture(capture) => visitor(Reference::Capture(capture)),
+                    Condition::Placeholder(kind) => visitor(Reference::Placeholder(kind)),
+                }
+                visit_references(then, visitor);
+                visit_references(otherwise, visitor);
+            }
+        }
+    }
+}
+
+/// Get capture group value, returns an empty string if the group did not participate.
+fn get_capture<'a>(captures: &'a Captures, capture: &CaptureRef) -> &'a str {
+    let group = match capture {
+        CaptureRef::Index(index) => captures.get(*index),
+        CaptureRef::Name(name) => captures.name(name),
+    };
+    group.map(|m| m.as_str()).unwrap_or("")
+}
+
+struct Parser<'a> {
+    input: &'a str,
+    pos: usize,
+    placeholders: &'a BTreeMap<String, String>,
+    /// A lookup table is available for `${1:map}` captures
+    has_map: bool,
+}
+
+impl Parser<'_> {
+    /// Parse nodes until the end of the input or a block keyword is found.
+    fn parse_block(&mut self) -> Result<(Vec<Node>, Option<Keyword>), String> {
+        let mut nodes = Vec::new();
+        let mut text = String::new();
+
+        while let Some(c) = self.input[self.pos..].chars().next() {
+            match c {
+                '$' => match self.parse_capture()? {
+                    Some(capture) => {
+                        flush_text(&mut text, &mut nodes);
+                        nodes.push(capture);
+                    }
+                    None => {
+                        // `$$` is an escaped dollar sign, any other `$` is kept as literal
+                        self.pos += 1;
+                        if self.input[self.pos..].starts_with('$') {
+                            self.pos += 1;
+                        }
+                        text.push('$');
+                    }
+                },
+                // `{{` and `}}` are escaped braces, any other brace is part of an expression
+                '{' | '}' if self.input[self.pos + 1..].starts_with(c) => {
+                    self.pos += 2;
+                    text.push(c);
+                }
+                '}' => {
+                    return Err(format!(
+                        "Unexpected '}}' at position {}, use '}}}}' for a literal brace",
+                        self.position()
+                    ))
+                }
+                '{' => {
+                    let rest = &self.input[self.pos + 1..];
+                    let content = match rest.find('}') {
+                        Some(end) => &rest[..end],
+                        None => {
+                            return Err(format!(
+                                "Unclosed '{{' at position {}, use '{{{{' for a literal brace",
+                                self.position()
+                            ))
+                        }
+                    };
+                    let next_pos = self.pos + content.len() + 2;
+
+                    if let Some(condition) = content.strip_prefix("if ") {
+                        let condition = parse_condition(condition.trim(), self.placeholders)?;
+                        self.pos = next_pos;
+                        flush_text(&mut text, &mut nodes);
+                        nodes.push(self.parse_conditional(condition)?);
+                    } else if content == "else" || content == "end" {
+                        self.pos = next_pos;
+                        flush_text(&mut text, &mut nodes);
+                        let keyword = if content == "else" {
+                            Keyword::Else
+                        } else {
+                            Keyword::End
+                        };
+                        return Ok((nodes, Some(keyword)));
+                    } else if let Some(node) = parse_placeholder(content, self.placeholders)? {
+                        self.pos = next_pos;
+                        flush_text(&mut text, &mut nodes);
+                        nodes.push(node);
+                    } else {
+                        let name = content.split(':').next().unwrap_or(content);
+                        let escape = "'{{' and '}}' for literal braces";
+                        let hint = match suggest_placeholder(name, self.placeholders) {
+                            Some(suggestion) => {
+                                format!("did you mean '{{{}}}'? Use {}", suggestion, escape)
+                            }
+                            None => format!("use {}", escape),
+                        };
+                        return Err(format!("Unknown placeholder '{{{}}}', {}", content, hint));
+                    }
+                }
+                _ => {
+                    self.pos += c.len_utf8();
+                    text.push(c);
+                }
+            }
+        }
+
+        flush_text(&mut text, &mut nodes);
+        Ok((nodes, None))
+    }
+
+    /// Position of the current char, starting at 1.
+    fn position(&self) -> usize {
+        self.input[..self.pos].chars().count() + 1
+    }
+
+    /// Parse the branches of a conditional block after its `{if ...}` opening.
+    fn parse_conditional(&mut self, condition: Condition) -> Result<Node, String> {
+        let (then, keyword) = self.parse_block()?;
+        let otherwise = match keyword {
+            Some(Keyword::End) => Vec::new(),
+            Some(Keyword::Else) => match self.parse_block()? {
+                (otherwise, Some(Keyword::End)) => otherwise,
+                (_, Some(Keyword::Else)) => {
+                    return Err("Duplicated '{else}' in '{if ...}' block".to_string())
+                }
+                (_, None) => return Err("Missing '{end}' to close '{if ...}' block".to_string()),
+            },
+            None => return Err("Missing '{end}' to close '{if ...}' block".to_string()),
+        };
+        Ok(Node::Conditional {
+            condition,
+            then,
+            otherwise,
+        })
+    }
+
+    /// Parse a capture group reference (`$1`, `$name`, `${1}` or `${name}`) using the same
+    /// rules as the regex crate. Braced references can include an arithmetic operation like
+    /// `${1:+100}` or a lookup like `${1:map}`. Position is only advanced if a valid reference is
+    /// found.
+    fn parse_capture(&mut self) -> Result<Option<Node>, String> {
+        let rest = &self.input[self.pos + 1..];
+        let mut filter = None;
+        let (name, length) = if let Some(braced) = rest.strip_prefix('{') {
+            let end = match braced.find('}') {
+                Some(end) => end,
+                None => return Ok(None),
+            };
+            let mut name = &braced[..end];
+            if let Some((group, expression)) = name.split_once(':') {
+                filter = Some(match expression {
+                    "map" if !self.has_map => {
+                        return Err(format!(
+                            "No lookup table for '${{{}}}', use --map FILE to read one",
+                            name
+                        ))
+                    }
+                    "map" => Filter::Map,
+                    expression => Filter::Arithmetic(Arithmetic::parse(expression)?),
+                });
+                name = group;
+            }
+            (name, end + 2)
+        } else {
+            let end = rest
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .unwrap_or(rest.len());
+            (&rest[..end], end)
+        };
+        if name.is_empty() {
+            return Ok(None);
+        }
+        self.pos += length + 1;
+        Ok(Some(Node::Capture {
+            group: parse_capture_name(name),
+            filter,
+        }))
+    }
+}
+
+/// Get the known placeholder name closest to a misspelled one, if it is close enough.
+fn suggest_placeholder<'a>(
+    name: &str,
+    placeholders: &'a BTreeMap<String, String>,
+) -> Option<&'a str> {
+    BUILTIN_PLACEHOLDERS
+        .into_iter()
+        .chain(placeholders.keys().map(String::as_str))
+        .map(|known| (edit_distance(name, known), known))
+        // Short names are close to anything, suggestions must keep part of both names
+        .filter(|(distance, known)| {
+            *distance <= 2 && *distance < known.chars().count() && *distance < name.chars().count()
+        })
+        .min()
+        .map(|(_, known)| known)
+}
+
+/// Number of single char insertions, deletions or substitutions to turn a string into another.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, a_char) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, b_char) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(a_char != *b_char);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+fn flush_text(text: &mut String, nodes: &mut Vec<Node>) {
+    if !text.is_empty() {
+        nodes.push(Node::Text(std::mem::take(text)));
+    }
+}
+
+fn parse_capture_name(name: &str) -> CaptureRef {
+    match name.parse::<usize>() {
+        Ok(index) => CaptureRef::Index(index),
+        Err(_) => CaptureRef::Name(name.to_string()),
+    }
+}
+
+/// Parse `{if ...}` condition. It can be a capture group reference or a placeholder name.
+fn parse_condition(
+    condition: &str,
+    placeholders: &BTreeMap<String, String>,
+) -> Result<Condition, String> {
+    if let Some(name) = condition.strip_prefix('$') {
+        let name = name.trim_start_matches('{').trim_end_matches('}');
+        if !name.is_empty() {
+            return Ok(Condition::Capture(parse_capture_name(name)));
+        }
+    } else if let Some(kind) = Placeholder::from_name(condition, placeholders) {
+        return Ok(Condition::Placeholder(kind));
+    }
+    Err(format!("Invalid condition '{{if {}}}'", condition))
+}
+
+/// Parse placeholder content with `name[:format]` syntax. Returns `None` if the name is not a
+/// known placeholder.
+fn parse_placeholder(
+    content: &str,
+    placeholders: &BTreeMap<String, String>,
+) -> Result<Option<Node>, String> {
+    let (name, format) = match content.split_once(':') {
+        Some((name, format)) => (name, Some(format)),
+        None => (content, None),
+    };
+    let kind = match Placeholder::from_name(name, placeholders) {
+        Some(kind) => kind,
+        None => return Ok(None),
+    };
+    if let Some(format) = format {
+        kind.check_format(content, format)?;
+    }
+    Ok(Some(Node::Placeholder {
+        kind,
+        format: format.map(String::from),
+    }))
+}
+
+#[cfg(test)]
+mod test {
+    extern crate tempfile;
+    use super::*;
+    use crate::exif::test::jpeg_with_date;
+    use chrono::Local;
+    use regex::Regex;
+    use std::fs;
+    use std::time::SystemTime;
+
+    fn replace(expression: &str, template: &str, name: &str, path: &Path) -> String {
+        let template = Template::parse(template).unwrap();
+        Regex::new(expression)
+            .unwrap()
+            .replace(name, |captures: &Captures| {
+                template.render(captures, path, None, &MetadataCache::new())
+            })
+            .to_string()
+    }
+
+    #[test]
+    fn captures() {
+        let path = Path::new("file-01.txt");
+        let expression = r"(?P<name>\w+)-(\d+)\.(\w+)";
+        assert_eq!(
+            replace(expression, "${2}-${name}.$3", "file-01.txt", path),
+            "01-file.txt"
+        );
+        assert_eq!(replace(expression, "${2}_$$", "file-01.txt", path), "01_$");
+        // Non-existing groups are replaced with empty strings
+        assert_eq!(replace(expression, "$9$missing", "file-01.txt", path), "");
+        // Braces that are not template expressions are kept
+        assert_eq!(
+            replace(expression, "{{x}}{{$2", "file-01.txt", path),
+            "{x}{01"
+        );
+        assert_eq!(
+            Template::parse("{x}").unwrap_err(),
+            "Unknown placeholder '{x}', use '{{' and '}}' for literal braces"
+        );
+        assert_eq!(
+            Template::parse("a{$2").unwrap_err(),
+            "Unclosed '{' at position 2, use '{{' for a literal brace"
+        );
+        assert_eq!(
+            Template::parse("ß}").unwrap_err(),
+            "Unexpected '}' at position 2, use '}}' for a literal brace"
+        );
+    }
+
+    #[test]
+    fn arithmetic() {
+        let path = Path::new("show-e07.mkv");
+        let expression = r"e(\d+)";
+        assert_eq!(
+            replace(expression, "e${1:+12}", "show-e07.mkv", path),
+            "show-e19.mkv"
+        );
+        assert_eq!(
+            replace(expression, "e${1:-8}", "show-e07.mkv", path),
+            "show-e-01.mkv"
+        );
+        assert_eq!(
+            replace(expression, "e${1:*20}", "show-e7.mkv", path),
+            "show-e140.mkv"
+        );
+        assert_eq!(
+            replace(expression, "e${1:/2}", "show-e07.mkv", path),
+            "show-e03.mkv"
+        );
+        // Non-numeric captures are not modified
+        assert_eq!(
+            replace(r"(\w+)-", "${1:+1}_", "show-e07.mkv", path),
+            "show_e07.mkv"
+        );
+
+        assert!(Template::parse("${1:%2}").is_err());
+        assert!(Template::parse("${1:+a}").is_err());
+        assert!(Template::parse("${1:/0}").is_err());
+    }
+
+    #[test]
+    fn lookup_map() {
+        let settings = Settings {
+            map: Some(Arc::new(BTreeMap::from([(
+                "C042".to_string(),
+                "Acme".to_string(),
+            )]))),
+            ..Settings::default()
+        };
+        let template = Template::parse_with_settings("${1:map}_", &settings).unwrap();
+        let expression = Regex::new(r"^(C\d+)-").unwrap();
+        let replace = |name: &str| {
+            expression
+                .replace(name, |captures: &Captures| {
+                    template.render(captures, Path::new(name), None, &MetadataCache::new())
+                })
+                .to_string()
+        };
+        assert_eq!(replace("C042-invoice.pdf"), "Acme_invoice.pdf");
+        // Values missing from the table are not modified
+        assert_eq!(replace("C007-invoice.pdf"), "C007_invoice.pdf");
+
+        assert!(Template::parse("${1:map}").is_err());
+    }
+
+    #[test]
+    fn placeholders_and_conditionals() {
+        let tempdir = tempfile::tempdir().expect("Error creating temp directory");
+        println!("Running test in '{:?}'", tempdir);
+        let photo = tempdir.path().join("photo.jpg");
+        let text = tempdir.path().join("text.txt");
+        fs::write(&photo, jpeg_with_date("2021:06:15 10:30:00")).unwrap();
+        fs::write(&text, "").unwrap();
+
+        // Set a known modification time
+        let mtime = SystemTime::from(
+            NaiveDateTime::parse_from_str("2020-01-02 03:04:05", "%Y-%m-%d %H:%M:%S")
+                .unwrap()
+                .and_local_timezone(Local)
+                .unwrap(),
+        );
+        fs::File::options()
+            .write(true)
+            .open(&text)
+            .unwrap()
+            .set_modified(mtime)
+            .unwrap();
+
+        let template = "{if exif.date}{exif.date:%Y%m%d}{else}{mtime:%Y%m%d}{end}_$0";
+        assert_eq!(
+            replace(r"^\w+", template, "photo", &photo),
+            "20210615_photo"
+        );
+        assert_eq!(replace(r"^\w+", template, "text", &text), "20200102_text");
+        assert_eq!(replace(r"^\w+", "{mtime}", "text", &text), "2020-01-02");
+
+        // Placeholders without a value are reported, unless checked by a conditional
+        let expression = Regex::new("text").unwrap();
+        let captures = expression.captures("text").unwrap();
+        let cache = MetadataCache::new();
+        let rendered = Template::parse("{exif.date}_$0")
+            .unwrap()
+            .render_checked(&captures, &text, None, &cache);
+        assert_eq!(rendered.text, "_text");
+        assert_eq!(rendered.missing, ["exif.date"]);
+        let rendered = Template::parse(template)
+            .unwrap()
+            .render_checked(&captures, &text, None, &cache);
+        assert!(rendered.missing.is_empty());
+
+        // Capture conditions
+        let template = "{if $2}$2{else}none{end}";
+        assert_eq!(replace(r"(a)(b)?", template, "ab", &text), "b");
+        assert_eq!(replace(r"(a)(b)?", template, "a", &text), "none");
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert!(Template::parse("{if exif.date}a").is_err());
+        assert!(Template::parse("a{else}b").is_err());
+        assert!(Template::parse("a{end}").is_err());
+        assert!(Template::parse("{if mtime}a{else}b{else}c{end}").is_err());
+        assert!(Template::parse("{if unknown}a{end}").is_err());
+        assert!(Template::parse("{mtime:%Q}").is_err());
+        assert!(Template::parse("{if $1}{if mtime}a{end}{else}b{end}").is_ok());
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn custom_placeholders() {
+        let tempdir = tempfile::tempdir().expect("Error creating temp directory");
+        println!("Running test in '{:?}'", tempdir);
+        let counter = tempdir.path().join("counter");
+        let path = tempdir.path().join("file.txt");
+
+        let mut settings = Settings::default();
+        let placeholders = &mut settings.placeholders;
+        // Command appends to a file on each run to check that it is cached
+        placeholders.insert(
+            "ticket".to_string(),
+            format!("echo x >> '{}'; echo ABC-1", counter.display()),
+        );
+        placeholders.insert("failing".to_string(), "exit 1".to_string());
+        placeholders.insert("mtime".to_string(), "echo shadowed".to_string());
+
+        let template = Template::parse_with_settings(
+            "{ticket}-$1{if failing}-{failing}{end}{if mtime}-ok{end}",
+            &settings,
+        )
+        .unwrap();
+        let expression = Regex::new("(file)").unwrap();
+        for _ in 0..2 {
+            let result = expression.replace("file.txt", |captures: &Captures| {
+                template.render(captures, &path, None, &MetadataCache::new())
+            });
+            assert_eq!(result, "ABC-1-file.txt");
+        }
+        assert_eq!(fs::read_to_string(&counter).unwrap(), "x\n");
+
+        assert!(Template::parse_with_settings("{ticket:%Y}", &settings).is_err());
+        // Unknown names must be escaped to be kept as text
+        assert!(Template::parse_with_settings("{other}", &settings).is_err());
+        let template = Template::parse_with_settings("{{other}}", &settings).unwrap();
+        let result = expression.replace("file.txt", |captures: &Captures| {
+            template.render(captures, &path, None, &MetadataCache::new())
+        });
+        assert_eq!(result, "{other}.txt");
+    }
+
+    #[test]
+    fn counters() {
+        let template = Template::parse("day{group}-{seq:3}{if seq}!{end}").unwrap();
+        assert!(template.has_counters());
+        assert!(!Template::parse("{mtime}-$1").unwrap().has_counters());
+        let expression = Regex::new("file").unwrap();
+        let path = Path::new("file.txt");
+        let group = Group {
+            index: 2,
+            seq: 7,
+            idx: 12,
+        };
+        let result = expression.replace("file.txt", |captures: &Captures| {
+            template.render(captures, path, Some(group), &MetadataCache::new())
+        });
+        assert_eq!(result, "day2-007!.txt");
+        let idx_template = Template::parse("{idx:4}_$0").unwrap();
+        assert!(idx_template.has_counters());
+        let result = expression.replace("file.txt", |captures: &Captures| {
+            idx_template.render(captures, path, Some(group), &MetadataCache::new())
+        });
+        assert_eq!(result, "0012_file.txt");
+        let result = expression.replace("file.txt", |captures: &Captures| {
+            template.render(captures, path, None, &MetadataCache::new())
+        });
+        assert_eq!(result, "day-.txt");
+
+        assert!(Template::parse("{seq:%Y}").is_err());
+        assert!(Template::parse("{mtime:3}").is_ok());
+    }
+}
@@ -0,0 +1,89 @@
+use crate::error::*;
+use crate::output::Printer;
+use crate::settings::Settings;
+use crate::template::Template;
+use regex::Regex;
+
+/// Parse a replacement template and check that the captures it uses exist in the expression.
+pub fn validate(input: &str, expression: Option<&str>, settings: &Settings) -> Result<Template> {
+    let invalid = |message: String| Error {
+        kind: ErrorKind::InvalidTemplate,
+        value: Some(message),
+    };
+    let template = Template::parse_with_settings(input, settings).map_err(invalid)?;
+
+    if let Some(expression) = expression {
+        let expression = Regex::new(expression).map_err(|err| invalid(err.to_string()))?;
+        for capture in template.captures() {
+            let exists = match capture.parse::<usize>() {
+                Ok(index) => index < expression.captures_len(),
+                Err(_) => expression
+                    .capture_names()
+                    .any(|name| name == Some(capture.as_str())),
+            };
+            if !exists {
+                return Err(invalid(format!(
+                    "Capture '{}' does not exist in expression '{}'",
+                    capture, expression
+                )));
+            }
+        }
+    }
+    Ok(template)
+}
+
+/// Print the captures and placeholders used by a valid template.
+pub fn print_validation(
+    input: &str,
+    expression: Option<&str>,
+    settings: &Settings,
+    printer: &Printer,
+) -> Result<()> {
+    let template = validate(input, expression, settings)?;
+    let list = |names: Vec<String>| {
+        if names.is_empty() {
+            "none".to_string()
+        } else {
+            names.join(", ")
+        }
+    };
+    let info = &printer.colors.info;
+    printer.print(&format!("{}", info.paint("Template is valid")));
+    printer.print(&format!(
+        "{}{}",
+        info.paint("Captures: "),
+        list(template.captures())
+    ));
+    printer.print(&format!(
+        "{}{}",
+        info.paint("Placeholders: "),
+        list(template.placeholders())
+    ));
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn validate_template() {
+        let settings = Settings::default();
+        let template = validate("${1}-{mtime:%Y}-${name}", None, &settings).unwrap();
+        assert_eq!(template.captures(), vec!["1", "name"]);
+        assert_eq!(template.placeholders(), vec!["mtime"]);
+
+        assert!(validate("${1}-${name}", Some("(?P<name>.*)"), &settings).is_ok());
+        let err = validate("${2}", Some("(a)"), &settings).unwrap_err();
+        assert_eq!(err.kind, ErrorKind::InvalidTemplate);
+        assert!(validate("${other}", Some("(?P<name>.*)"), &settings).is_err());
+
+        let err = validate("{mtiem}", None, &settings).unwrap_err();
+        assert!(err.value.unwrap().contains("did you mean '{mtime}'?"));
+        let err = validate("{sq}", None, &settings).unwrap_err();
+        assert!(err.value.unwrap().contains("did you mean '{seq}'?"));
+        // Too short to guess which one was meant
+        let err = validate("{q}", None, &settings).unwrap_err();
+        assert!(!err.value.unwrap().contains("did you mean"));
+    }
+}
@@ -0,0 +1,56 @@
+/// Categories of warnings, so expected ones can be suppressed without hiding the others
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Warning {
+    /// Files skipped because other processes have them open
+    InUse,
+    /// Paths not reverted because they were modified after the dump was created
+    ModifiedAfterDump,
+    /// Final paths or file names longer than the OS limits
+    NameTooLong,
+    /// Renaming errors printed as warnings with `--ignore-errors-matching`
+    IgnoredError,
+    /// Symlinks pointed back to the content recorded in the dump
+    SymlinkRestored,
+    /// Renamed files that could not be tagged with `--xattr-tag`
+    XattrTag,
+    /// Runs waiting for another one renaming in the same directories
+    LockWait,
+    /// Placeholders rendered empty because a path has no value for them, like `{exif.date}`
+    MissingMetadata,
+}
+
+impl Warning {
+    pub const ALL: [Warning; 8] = [
+        Warning::InUse,
+        Warning::ModifiedAfterDump,
+        Warning::NameTooLong,
+        Warning::IgnoredError,
+        Warning::SymlinkRestored,
+        Warning::XattrTag,
+        Warning::LockWait,
+        Warning::MissingMetadata,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Warning::InUse => "in-use",
+            Warning::ModifiedAfterDump => "modified-after-dump",
+            Warning::NameTooLong => "name-too-long",
+            Warning::IgnoredError => "ignored-error",
+            Warning::SymlinkRestored => "symlink-restored",
+            Warning::XattrTag => "xattr-tag",
+            Warning::LockWait => "lock-wait",
+            Warning::MissingMetadata => "missing-metadata",
+        }
+    }
+
+    pub fn from_str(name: &str) -> std::result::Result<Warning, String> {
+        Warning::ALL
+            .into_iter()
+            .find(|warning| warning.name() == name)
+            .ok_or_else(|| {
+                let names: Vec<&str> = Warning::ALL.iter().map(Warning::name).collect();
+                format!("Unknown warning '{}', use {}", name, names.join(", "))
+            })
+    }
+}